@@ -1,10 +1,12 @@
 use crate::model::{VData, Outline, LevGnxOps, Tree};
 use crate::utils::{extract_section_ref, is_directive, has_others, is_special};
+use crate::diff::lcs;
+use std::collections::{HashMap, HashSet};
 static SPACES:&'static str = "                                                                                                                                              ";
 pub fn atclean_to_string(outline:&Outline, nodes:&Vec<VData>, ni:usize) -> String {
   let mut res = String::new();
   let cleanit = AtCleanTree::new(outline, nodes, ni, 0);
-  for (_lev, _v, _a, _b, ind, t) in cleanit {
+  for (_lev, _v, _a, _b, ind, t, _ni) in cleanit {
     if ind > 0 {
       res.push_str(&SPACES[0..ind]);
     }
@@ -29,7 +31,7 @@ impl<'a> AtCleanTree<'a> {
   }
 }
 impl<'a> Iterator for AtCleanTree<'a> {
-  type Item = (u8, &'a VData, usize, usize, usize, &'a str);
+  type Item = (u8, &'a VData, usize, usize, usize, &'a str, usize);
   fn next(&mut self) -> Option<Self::Item> {
     while self.children.len() > 0 {
       let x = self.children[0].next();
@@ -47,7 +49,7 @@ impl<'a> Iterator for AtCleanTree<'a> {
       let t = &b[i..self.bi-1];
       if is_directive(t) { continue }
       if !is_special(t) {
-        return Some((lev, v, i, self.bi-1, self.ind, t))
+        return Some((lev, v, i, self.bi-1, self.ind, t, self.ni))
       }
       let lt = t.trim_start();
       let ind = t.len() - lt.len();
@@ -79,17 +81,19 @@ impl<'a> Iterator for AtCleanTree<'a> {
         let sname = extract_section_ref(t).unwrap();
         let before = t.find(sname).unwrap();
         let after = &t[(before + sname.len())..];
-        if after.len() > 0 {
-          self.bi -= after.len() + 1;
-        }
         let ti=Tree::new(self.o, self.vs);
         if let Some(ni) = ti.find_section(sname, self.ni) {
           self.children.insert(0, AtCleanTree::new(self.o, self.vs, ni, ind + self.ind));
         } else {
-          break
+          // undefined section, the line is written as it is and the
+          // problem is reported by sections::analyze_sections
+          return Some((lev, v, i, self.bi-1, self.ind, t, self.ni))
+        }
+        if after.len() > 0 {
+          self.bi -= after.len() + 1;
         }
         if before > ind {
-          return Some((lev, v, i, before, self.ind + ind, &t[0..before]))
+          return Some((lev, v, i, before, self.ind + ind, &t[0..before], self.ni))
         } else {
           return self.next()
         }
@@ -98,14 +102,131 @@ impl<'a> Iterator for AtCleanTree<'a> {
     None
   }
 }
+/// returns for every line of `at-clean` output of the node at index ni
+/// the index of the node which owns this line and the line number
+/// in its body, starting from 0
+pub fn atclean_line_owners(outline:&Outline, nodes:&Vec<VData>, ni:usize) -> Vec<(usize, usize)> {
+  AtCleanTree::new(outline, nodes, ni, 0)
+    .map(|(_lev, v, a, _b, _ind, _t, i)|(i, v.b[..a].matches('\n').count()))
+    .collect()
+}
+/// updates bodies in the `at-clean` subtree of the node at index ni so
+/// that its output becomes cont. Output lines are matched with lines of
+/// cont; unchanged lines stay where they are, removed lines are deleted
+/// and added lines go to the node of the line they replace or else of
+/// the preceding line. Directives, `@others` and section references are
+/// kept, and so are lines of clones that were already written once.
 pub fn update_atclean_tree(outline:&Outline, nodes:&mut Vec<VData>, ni:usize, cont:&str) {
-  let n = outline.len() + nodes.len() + cont.len()+ni;
-  if n > 1 {return}
-  let oldtxt = atclean_to_string(outline, nodes, 0);
-  let v = nodes.get_mut(0).unwrap();
-  if cont.len() == 0 {
-    v.b.push_str(cont);
-  } else {
-    v.b.push_str(oldtxt.as_str());
+  // output line, its indentation and, if it is a whole body line
+  // seen for the first time, ignx and start of the line in the body
+  let mut seen = HashSet::new();
+  let old:Vec<_> = AtCleanTree::new(outline, nodes, ni, 0)
+    .map(|(_lev, v, a, _b, ind, t, _i)| {
+      let whole = (a == 0 || v.b.as_bytes()[a - 1] == b'\n') && v.b[a..].split('\n').next() == Some(t);
+      let at = Some((v.ignx, a)).filter(|x|whole && seen.insert(*x));
+      (format!("{}{}", " ".repeat(ind), t), ind, at)
+    })
+    .collect();
+  let ol:Vec<&str> = old.iter().map(|x|x.0.as_str()).collect();
+  let nl:Vec<&str> = cont.lines().collect();
+  let mut repl:Vec<Vec<&str>> = vec![Vec::new(); old.len()];
+  let mut rest:Vec<&str> = Vec::new();
+  let editable = |k:&usize|old[*k].2.is_some();
+  let (mut i, mut j) = (0, 0);
+  for (x, y) in lcs(&ol, &nl).into_iter().chain(std::iter::once((ol.len(), nl.len()))) {
+    if j < y {
+      let anchor = (i..x).find(editable)
+        .or_else(||(0..i).rev().find(editable))
+        .or_else(||(x..ol.len()).find(editable));
+      match anchor {
+        Some(k) => repl[k].extend_from_slice(&nl[j..y]),
+        None => rest.extend_from_slice(&nl[j..y])
+      }
+    }
+    if x < ol.len() { repl[x].push(nl[y]) }
+    i = x + 1;
+    j = y + 1;
+  }
+  let mut bodies:HashMap<u32, Vec<(usize, usize)>> = HashMap::new();
+  for (k, x) in old.iter().enumerate() {
+    if let Some((ignx, a)) = x.2 {
+      bodies.entry(ignx).or_insert_with(Vec::new).push((a, k));
+    }
+  }
+  for (ignx, mut lines) in bodies {
+    lines.sort();
+    let v = &mut nodes[ignx as usize];
+    let mut b = String::with_capacity(v.b.len());
+    let mut pos = 0;
+    for (a, k) in lines {
+      let (t, ind, _) = &old[k];
+      b.push_str(&v.b[pos..a]);
+      for l in repl[k].iter() {
+        let n = l.len() - l.trim_start_matches(' ').len();
+        b.push_str(&l[n.min(*ind)..]);
+        b.push('\n');
+      }
+      pos = a + t.len() - ind;
+      if v.b[pos..].starts_with('\n') {
+        pos += 1;
+      } else if !repl[k].is_empty() {
+        b.pop();
+      }
+    }
+    b.push_str(&v.b[pos..]);
+    v.b = b;
+  }
+  if !rest.is_empty() {
+    let v = &mut nodes[outline[ni].ignx() as usize];
+    if !v.b.is_empty() && !v.b.ends_with('\n') { v.b.push('\n') }
+    for l in rest {
+      v.b.push_str(l);
+      v.b.push('\n');
+    }
+  }
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::from_leo_content;
+  const LEO:&str = r#"<leo_file><vnodes>
+<v t="c"><vh>@clean x.py</vh><v t="a"><vh>A</vh></v><v t="k"><vh>K</vh><v t="m"><vh>m</vh></v></v></v>
+</vnodes><tnodes><t tx="c">@language python
+import os
+@others
+</t><t tx="a">def a():
+    return 1
+</t><t tx="k">class K:
+    @others
+</t><t tx="m">def m(self):
+    pass
+</t></tnodes></leo_file>"#;
+  #[test]
+  fn test_update_atclean_tree() {
+    let (o, mut nodes) = from_leo_content(LEO);
+    let b = |nodes:&Vec<VData>, i:usize|nodes[o[i].ignx() as usize].b.clone();
+    let txt = atclean_to_string(&o, &nodes, 1);
+    let before = nodes.clone();
+    update_atclean_tree(&o, &mut nodes, 1, &txt);
+    assert_eq!(nodes, before);
+    let txt2 = txt.replace("    return 1\n", "    return 2\n    # two\n")
+      .replace("import os\n", "")
+      .replace("        pass\n", "        pass\n\n    def n(self):\n        pass\n");
+    update_atclean_tree(&o, &mut nodes, 1, &txt2);
+    assert_eq!(b(&nodes, 1), "@language python\n@others\n");
+    assert_eq!(b(&nodes, 2), "def a():\n    return 2\n    # two\n");
+    assert_eq!(b(&nodes, 3), "class K:\n    @others\n");
+    assert_eq!(b(&nodes, 4), "def m(self):\n    pass\n\ndef n(self):\n    pass\n");
+    // empty lines in indented nodes are written with the indentation
+    assert_eq!(atclean_to_string(&o, &nodes, 1).replace("    \n", "\n"), txt2);
+  }
+  #[test]
+  fn test_update_atclean_tree_empty() {
+    let (o, mut nodes) = from_leo_content(LEO);
+    update_atclean_tree(&o, &mut nodes, 1, "");
+    assert_eq!(atclean_to_string(&o, &nodes, 1), "");
+    assert_eq!(nodes[o[3].ignx() as usize].b, "    @others\n");
+    update_atclean_tree(&o, &mut nodes, 1, "x = 1\n");
+    assert_eq!(atclean_to_string(&o, &nodes, 1), "x = 1\n");
   }
 }
@@ -0,0 +1,60 @@
+//! Git merge driver for Leo documents.
+//!
+//! Merges two versions of a `.leo` file with their common ancestor
+//! using merge_outlines. To use it, add to `.git/config`:
+//!
+//!     [merge "leo"]
+//!       name = Leo outline merge
+//!       driver = leo-merge %O %A %B
+//!
+//! and to `.gitattributes`:
+//!
+//!     *.leo merge=leo
+//!
+//! The result is written to the file with our version, keeping its
+//! header and other sections (globals, preferences...) as they were.
+//! Exit status is 1 if there were conflicts, which are listed in the
+//! `Merge conflicts` node of the result, and 2 on errors, including
+//! any of the files not being a well formed Leo document.
+//!
+//! The binary doesn't need the python extension module feature:
+//!
+//!     cargo build --release --bin leo-merge --no-default-features
+use mini_leo::{try_from_leo_content, to_leo_document, merge_outlines, GnxGenerator, LeoExtras};
+use mini_leo::{Outline, VData};
+use std::{env, fs, process};
+
+fn read(fname:&str) -> (Outline, Vec<VData>, LeoExtras) {
+  let fail = |e:String| -> ! {
+    eprintln!("leo-merge: {}: {}", fname, e);
+    process::exit(2)
+  };
+  let xml = fs::read_to_string(fname).unwrap_or_else(|e|fail(e.to_string()));
+  let (o, nodes) = try_from_leo_content(&xml).unwrap_or_else(|e|fail(e));
+  let extras = LeoExtras::from_content(&xml)
+    .unwrap_or_else(||fail("not a Leo document".to_string()));
+  (o, nodes, extras)
+}
+fn main() {
+  let args:Vec<String> = env::args().collect();
+  if args.len() != 4 {
+    eprintln!("usage: leo-merge BASE OURS THEIRS");
+    process::exit(2);
+  }
+  let (ob, nb, _) = read(&args[1]);
+  let (oo, no, mut extras) = read(&args[2]);
+  let (ot, nt, et) = read(&args[3]);
+  // attributes of nodes added on their side
+  for (gnx, a) in et.vattrs.into_iter() { extras.vattrs.entry(gnx).or_insert(a); }
+  for (gnx, a) in et.tattrs.into_iter() { extras.tattrs.entry(gnx).or_insert(a); }
+  let mut g = GnxGenerator::new("leo-merge");
+  let r = merge_outlines(&ob, &nb, &oo, &no, &ot, &nt, &mut g);
+  if let Err(e) = fs::write(&args[2], to_leo_document(&r.outline, &r.nodes, &extras)) {
+    eprintln!("leo-merge: {}: {}", args[2], e);
+    process::exit(2);
+  }
+  for c in r.conflicts.iter() {
+    eprintln!("leo-merge: {} conflict in '{}'", c.kind.name(), c.h);
+  }
+  process::exit(if r.conflicts.is_empty() { 0 } else { 1 });
+}
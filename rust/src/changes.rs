@@ -0,0 +1,744 @@
+use crate::model::{VData, Outline, LevGnx, LevGnxOps, shift_blocks, set_nodes};
+use crate::utils::{b64write, b64int, is_b64, partition, insert_parts, delete_blocks};
+use std::error::Error;
+use std::fmt;
+
+/// version of the change record encoding produced by this module.
+///
+/// Text encoding (version 1) is a sequence of lines, optionally preceded
+/// by a header line `#changes:1`. Records without header are accepted as
+/// version 1, so histories saved before the header existed keep working.
+/// Records made after the outline has been compacted start with a line
+/// `#epoch:e` (before the version header, if any), where e is the number
+/// of compactions done before they were made. Records without it belong
+/// to epoch 0. Each other line is one record:
+///
+///   ip:zlabel,(m1,m2,...),(n1,n2,...)        inserted blocks
+///   db:size,(m1,m2,...),(n1,n2,...)          deleted blocks
+///   sb:size,[-]delta,(m1,m2,...)             shifted blocks
+///   sn:size,(m1,m2,...),(new...),(old...)    replaced blocks
+///   addv:gnx                                 appended vnode
+///   vupd:old<TAB>new                         updated vnode
+///   collapse:i / expand:i                    toggled expansion
+///
+/// where numbers are written in base 64 (see `b64write`) and each node
+/// `n` is written as `+level.ignx.label` (expanded) or `-level.ignx.label`.
+///
+/// Binary encoding starts with `MLC` followed by the version byte. Every
+/// record is a tag byte followed by its fields, integers are written as
+/// LEB128 varints and strings as a varint length followed by utf-8 bytes.
+/// Records of a later epoch are preceded by `MLE` and the epoch varint.
+pub const CHANGES_VERSION:u8 = 1;
+const TEXT_HEADER:&str = "#changes:";
+const EPOCH_HEADER:&str = "#epoch:";
+const BIN_MAGIC:&[u8] = b"MLC";
+const BIN_EPOCH:&[u8] = b"MLE";
+
+#[derive(Debug, PartialEq)]
+pub enum Change {
+  /// blocks of data were inserted at marks, zlabel is label of root before
+  InsertParts { zlabel:u32, marks:Vec<usize>, data:Vec<u64> },
+  /// blocks of given size were deleted at marks, data holds deleted nodes
+  DeleteBlocks { size:usize, marks:Vec<usize>, data:Vec<u64> },
+  /// blocks of given size at marks were shifted by delta levels
+  ShiftBlocks { size:usize, delta:i8, marks:Vec<usize> },
+  /// blocks at marks were replaced, new data replaced the old data
+  SetNodes { size:usize, marks:Vec<usize>, new:Vec<u64>, old:Vec<u64> },
+  /// a new vnode with the given gnx was appended to the nodes
+  AddVNode { gnx:String },
+  /// vnode old was replaced by new
+  UpdateVNode { old:VData, new:VData },
+  /// node at the given index was collapsed
+  Collapse(usize),
+  /// node at the given index was expanded
+  Expand(usize),
+}
+
+#[derive(Debug)]
+pub struct ChangeError {
+  pub line: usize,
+  pub msg: String
+}
+impl Error for ChangeError{}
+impl fmt::Display for ChangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ChangeError at record {}:{}", self.line, self.msg)
+    }
+}
+fn err<T>(msg:&str) -> Result<T, ChangeError> {
+  Err(ChangeError { line: 0, msg: msg.to_string() })
+}
+impl Change {
+  /// parses one line of the text encoding
+  pub fn from_text(x:&str) -> Result<Change, ChangeError> {
+    let (tag, sep, rest) = partition(x, ":");
+    if sep.len() == 0 {
+      return err("missing record tag");
+    }
+    match tag {
+      "ip" => {
+        let (zl, _, rest) = partition(rest, ",(");
+        let (marks, _, data) = partition(rest, "),(");
+        let marks = decode_marks(marks)?;
+        let data = decode_nodes(strip_paren(data)?)?;
+        check_blocks(&marks, data.len())?;
+        Ok(Change::InsertParts { zlabel: decode_num(zl)? as u32, marks, data })
+      },
+      "db" => {
+        let (sz, _, rest) = partition(rest, ",(");
+        let (marks, _, data) = partition(rest, "),(");
+        let size = decode_num(sz)? as usize;
+        let marks = decode_marks(marks)?;
+        let data = decode_nodes(strip_paren(data)?)?;
+        if size.checked_mul(marks.len()) != Some(data.len()) {
+          return err("deleted data doesn't match block size");
+        }
+        Ok(Change::DeleteBlocks { size, marks, data })
+      },
+      "sb" => {
+        let (sz, _, rest) = partition(rest, ",");
+        let (d, _, marks) = partition(rest, ",(");
+        let size = decode_num(sz)? as usize;
+        let delta = if d.starts_with('-') {
+          -(decode_num(&d[1..])?.min(256) as i16)
+        } else {
+          decode_num(d)?.min(256) as i16
+        };
+        if delta < i8::MIN as i16 || delta > i8::MAX as i16 {
+          return err("level delta out of range");
+        }
+        let delta = delta as i8;
+        let marks = decode_marks(strip_paren(marks)?)?;
+        Ok(Change::ShiftBlocks { size, delta, marks })
+      },
+      "sn" => {
+        let (sz, _, rest) = partition(rest, ",(");
+        let (marks, _, rest) = partition(rest, "),(");
+        let (new, _, old) = partition(rest, "),(");
+        let size = decode_num(sz)? as usize;
+        let marks = decode_marks(marks)?;
+        let new = decode_nodes(new)?;
+        let old = decode_nodes(strip_paren(old)?)?;
+        if size.checked_mul(marks.len()) != Some(new.len()) || old.len() != new.len() {
+          return err("replaced data doesn't match block size");
+        }
+        Ok(Change::SetNodes { size, marks, new, old })
+      },
+      "addv" => {
+        if rest.len() == 0 { return err("empty gnx") }
+        Ok(Change::AddVNode { gnx: rest.to_string() })
+      },
+      "vupd" => {
+        let (a, sep, b) = partition(rest, "\t");
+        if sep.len() == 0 { return err("missing new vnode") }
+        Ok(Change::UpdateVNode { old: decode_vnode(a)?, new: decode_vnode(b)? })
+      },
+      "collapse" => Ok(Change::Collapse(decode_num(rest)? as usize)),
+      "expand" => Ok(Change::Expand(decode_num(rest)? as usize)),
+      _ => err("unknown record tag")
+    }
+  }
+  /// appends the text encoding of this record to the given buffer
+  pub fn write_text(&self, buf:&mut String) {
+    match self {
+      Change::InsertParts { zlabel, marks, data } => {
+        buf.push_str("ip:");
+        b64write(*zlabel as u64, buf);
+        buf.push_str(",(");
+        encode_marks(marks, buf);
+        buf.push_str("),(");
+        encode_nodes(data, buf);
+        buf.push(')');
+      },
+      Change::DeleteBlocks { size, marks, data } => {
+        buf.push_str("db:");
+        b64write(*size as u64, buf);
+        buf.push_str(",(");
+        encode_marks(marks, buf);
+        buf.push_str("),(");
+        encode_nodes(data, buf);
+        buf.push(')');
+      },
+      Change::ShiftBlocks { size, delta, marks } => {
+        buf.push_str("sb:");
+        b64write(*size as u64, buf);
+        buf.push(',');
+        if *delta < 0 { buf.push('-'); }
+        b64write(delta.unsigned_abs() as u64, buf);
+        buf.push_str(",(");
+        encode_marks(marks, buf);
+        buf.push(')');
+      },
+      Change::SetNodes { size, marks, new, old } => {
+        buf.push_str("sn:");
+        b64write(*size as u64, buf);
+        buf.push_str(",(");
+        encode_marks(marks, buf);
+        buf.push_str("),(");
+        encode_nodes(new, buf);
+        buf.push_str("),(");
+        encode_nodes(old, buf);
+        buf.push(')');
+      },
+      Change::AddVNode { gnx } => {
+        buf.push_str("addv:");
+        buf.push_str(gnx);
+      },
+      Change::UpdateVNode { old, new } => {
+        buf.push_str("vupd:");
+        old.write_to(buf);
+        buf.push('\t');
+        new.write_to(buf);
+      },
+      Change::Collapse(i) => {
+        buf.push_str("collapse:");
+        b64write(*i as u64, buf);
+      },
+      Change::Expand(i) => {
+        buf.push_str("expand:");
+        b64write(*i as u64, buf);
+      }
+    }
+  }
+  /// returns the text encoding of this record
+  pub fn to_text(&self) -> String {
+    let mut buf = String::new();
+    self.write_text(&mut buf);
+    buf
+  }
+  /// returns true if this record can be applied to the given tree
+  /// without indexing outside of the outline or nodes
+  pub fn fits(&self, o:&Outline, nodes:&Vec<VData>) -> bool {
+    let n = o.len();
+    match self {
+      Change::InsertParts { marks, .. } => marks.iter().all(|m| *m <= n),
+      Change::DeleteBlocks { size, marks, .. } |
+      Change::ShiftBlocks { size, marks, .. } |
+      Change::SetNodes { size, marks, .. } => marks.iter().all(|m| m + size <= n),
+      Change::AddVNode { .. } => true,
+      Change::UpdateVNode { old, new } => {
+        (old.ignx as usize) < nodes.len() && (new.ignx as usize) < nodes.len()
+      },
+      Change::Collapse(i) | Change::Expand(i) => *i < n
+    }
+  }
+  /// reverts this change
+  pub fn undo(&self, o:&mut Outline, nodes:&mut Vec<VData>) {
+    match self {
+      Change::InsertParts { zlabel, marks, data } => {
+        let size = data.len() / marks.len();
+        let nmarks:Vec<usize> = marks
+          .iter()
+          .enumerate()
+          .map(|(i, j)| j + i * size)
+          .collect();
+        delete_blocks(o, &nmarks, size);
+        o[0].set_label(*zlabel);
+      },
+      Change::DeleteBlocks { size, marks, data } => {
+        let pmarks:Vec<usize> = marks
+          .iter()
+          .enumerate()
+          .map(|(i, j)| j - i * size)
+          .collect();
+        insert_parts(o, &pmarks, data);
+      },
+      Change::ShiftBlocks { size, delta, marks } => shift_blocks(o, marks, *size, -delta),
+      Change::SetNodes { marks, old, .. } => set_nodes(o, marks, old),
+      Change::AddVNode { .. } => { nodes.pop(); },
+      Change::UpdateVNode { old, .. } => {
+        let i = old.ignx as usize;
+        nodes[i] = old.clone();
+      },
+      Change::Collapse(i) => o[*i].expand(),
+      Change::Expand(i) => o[*i].collapse()
+    }
+  }
+  /// applies this change again after it has been undone
+  pub fn redo(&self, o:&mut Outline, nodes:&mut Vec<VData>) {
+    match self {
+      Change::InsertParts { marks, data, .. } => {
+        let nlabel = data.iter().fold(o[0].label(), |x, y| x.max(y.label()));
+        insert_parts(o, marks, data);
+        o[0].set_label(nlabel);
+      },
+      Change::DeleteBlocks { size, marks, .. } => delete_blocks(o, marks, *size),
+      Change::ShiftBlocks { size, delta, marks } => shift_blocks(o, marks, *size, *delta),
+      Change::SetNodes { marks, new, .. } => set_nodes(o, marks, new),
+      Change::AddVNode { gnx } => {
+        let mut v = VData::new(gnx);
+        v.ignx = nodes.len() as u32;
+        nodes.push(v);
+      },
+      Change::UpdateVNode { new, .. } => {
+        let i = new.ignx as usize;
+        nodes[i] = new.clone();
+      },
+      Change::Collapse(i) => o[*i].collapse(),
+      Change::Expand(i) => o[*i].expand()
+    }
+  }
+  /// appends the binary encoding of this record to the given buffer
+  pub fn write_bytes(&self, buf:&mut Vec<u8>) {
+    match self {
+      Change::InsertParts { zlabel, marks, data } => {
+        buf.push(1);
+        write_varint(*zlabel as u64, buf);
+        write_marks(marks, buf);
+        write_nodes(data, buf);
+      },
+      Change::DeleteBlocks { size, marks, data } => {
+        buf.push(2);
+        write_varint(*size as u64, buf);
+        write_marks(marks, buf);
+        write_nodes(data, buf);
+      },
+      Change::ShiftBlocks { size, delta, marks } => {
+        buf.push(3);
+        write_varint(*size as u64, buf);
+        buf.push(*delta as u8);
+        write_marks(marks, buf);
+      },
+      Change::SetNodes { size, marks, new, old } => {
+        buf.push(4);
+        write_varint(*size as u64, buf);
+        write_marks(marks, buf);
+        write_nodes(new, buf);
+        write_nodes(old, buf);
+      },
+      Change::AddVNode { gnx } => {
+        buf.push(5);
+        write_str(gnx, buf);
+      },
+      Change::UpdateVNode { old, new } => {
+        buf.push(6);
+        write_vnode(old, buf);
+        write_vnode(new, buf);
+      },
+      Change::Collapse(i) => {
+        buf.push(7);
+        write_varint(*i as u64, buf);
+      },
+      Change::Expand(i) => {
+        buf.push(8);
+        write_varint(*i as u64, buf);
+      }
+    }
+  }
+}
+/// parses text encoded change records. Records are validated and the
+/// first malformed one is reported with its (1-based) line number.
+/// Empty lines are ignored.
+pub fn decode_changes(txt:&str) -> Result<Vec<Change>, ChangeError> {
+  let (_, body) = change_epoch(txt)?;
+  let skip = if body.len() < txt.len() { 1 } else { 0 };
+  let mut res = Vec::new();
+  for (i, x) in body.split('\n').enumerate() {
+    if x.len() == 0 { continue }
+    if x.starts_with(TEXT_HEADER) {
+      if i > 0 || decode_num(&x[TEXT_HEADER.len()..]).ok() != Some(CHANGES_VERSION as u64) {
+        return Err(ChangeError { line: i + skip + 1, msg: format!("unsupported header {:?}", x) });
+      }
+      continue;
+    }
+    match Change::from_text(x) {
+      Ok(c) => res.push(c),
+      Err(e) => return Err(ChangeError { line: i + skip + 1, msg: e.msg })
+    }
+  }
+  Ok(res)
+}
+/// returns the epoch of text encoded change records and the records
+/// without the epoch line
+pub fn change_epoch(txt:&str) -> Result<(usize, &str), ChangeError> {
+  if !txt.starts_with(EPOCH_HEADER) { return Ok((0, txt)) }
+  let (line, _, rest) = partition(txt, "\n");
+  match decode_num(&line[EPOCH_HEADER.len()..]) {
+    Ok(e) => Ok((e as usize, rest)),
+    Err(e) => Err(ChangeError { line: 1, msg: e.msg })
+  }
+}
+/// returns text encoded change records marked as made in the given
+/// epoch. Records of epoch 0 are returned unchanged.
+pub fn with_epoch(epoch:usize, txt:&str) -> String {
+  let (_, body) = change_epoch(txt).unwrap_or((0, txt));
+  if epoch == 0 || body.is_empty() { return body.to_string() }
+  let mut buf = String::from(EPOCH_HEADER);
+  b64write(epoch as u64, &mut buf);
+  buf.push('\n');
+  buf.push_str(body);
+  buf
+}
+/// encodes change records as text, starting with the version header
+pub fn encode_changes(changes:&[Change]) -> String {
+  let mut buf = String::from(TEXT_HEADER);
+  b64write(CHANGES_VERSION as u64, &mut buf);
+  for c in changes {
+    buf.push('\n');
+    c.write_text(&mut buf);
+  }
+  buf
+}
+/// encodes change records in the binary format
+pub fn changes_to_bytes(changes:&[Change]) -> Vec<u8> {
+  let mut buf = BIN_MAGIC.to_vec();
+  buf.push(CHANGES_VERSION);
+  write_varint(changes.len() as u64, &mut buf);
+  for c in changes {
+    c.write_bytes(&mut buf);
+  }
+  buf
+}
+/// converts text encoded change records into the binary format,
+/// keeping their epoch
+pub fn text_to_bytes(txt:&str) -> Result<Vec<u8>, ChangeError> {
+  let (e, _) = change_epoch(txt)?;
+  let chs = decode_changes(txt)?;
+  let mut buf = Vec::new();
+  if e > 0 {
+    buf.extend_from_slice(BIN_EPOCH);
+    write_varint(e as u64, &mut buf);
+  }
+  buf.extend(changes_to_bytes(&chs));
+  Ok(buf)
+}
+/// converts binary encoded change records back into their versioned
+/// text encoding, keeping their epoch
+pub fn bytes_to_text(data:&[u8]) -> Result<String, ChangeError> {
+  let (e, rest) = if data.starts_with(BIN_EPOCH) {
+    let mut r = ByteReader { data, i: BIN_EPOCH.len() };
+    let e = r.varint()? as usize;
+    (e, &data[r.i..])
+  } else {
+    (0, data)
+  };
+  Ok(with_epoch(e, &encode_changes(&changes_from_bytes(rest)?)))
+}
+/// decodes change records from the binary format
+pub fn changes_from_bytes(data:&[u8]) -> Result<Vec<Change>, ChangeError> {
+  if !data.starts_with(BIN_MAGIC) {
+    return err("not a change record stream");
+  }
+  let mut r = ByteReader { data, i: BIN_MAGIC.len() };
+  if r.byte()? != CHANGES_VERSION {
+    return err("unsupported version");
+  }
+  let n = r.varint()? as usize;
+  let mut res = Vec::new();
+  for k in 0..n {
+    match read_change(&mut r) {
+      Ok(c) => res.push(c),
+      Err(e) => return Err(ChangeError { line: k + 1, msg: e.msg })
+    }
+  }
+  if r.i != data.len() {
+    return err("trailing bytes after last record");
+  }
+  Ok(res)
+}
+/// applies undo of all records in reverse order
+pub fn undo_changes(o:&mut Outline, nodes:&mut Vec<VData>, changes:&[Change]) {
+  for c in changes.iter().rev() {
+    c.undo(o, nodes);
+  }
+}
+/// applies all records in order
+pub fn redo_changes(o:&mut Outline, nodes:&mut Vec<VData>, changes:&[Change]) {
+  for c in changes.iter() {
+    c.redo(o, nodes);
+  }
+}
+fn read_change(r:&mut ByteReader) -> Result<Change, ChangeError> {
+  let c = match r.byte()? {
+    1 => {
+      let zlabel = r.varint()? as u32;
+      let marks = r.marks()?;
+      let data = r.nodes()?;
+      check_blocks(&marks, data.len())?;
+      Change::InsertParts { zlabel, marks, data }
+    },
+    2 => {
+      let size = r.varint()? as usize;
+      let marks = r.marks()?;
+      let data = r.nodes()?;
+      if size.checked_mul(marks.len()) != Some(data.len()) {
+        return err("deleted data doesn't match block size");
+      }
+      Change::DeleteBlocks { size, marks, data }
+    },
+    3 => {
+      let size = r.varint()? as usize;
+      let delta = r.byte()? as i8;
+      let marks = r.marks()?;
+      Change::ShiftBlocks { size, delta, marks }
+    },
+    4 => {
+      let size = r.varint()? as usize;
+      let marks = r.marks()?;
+      let new = r.nodes()?;
+      let old = r.nodes()?;
+      if size.checked_mul(marks.len()) != Some(new.len()) || old.len() != new.len() {
+        return err("replaced data doesn't match block size");
+      }
+      Change::SetNodes { size, marks, new, old }
+    },
+    5 => Change::AddVNode { gnx: r.string()? },
+    6 => {
+      let old = r.vnode()?;
+      let new = r.vnode()?;
+      Change::UpdateVNode { old, new }
+    },
+    7 => Change::Collapse(r.varint()? as usize),
+    8 => Change::Expand(r.varint()? as usize),
+    _ => return err("unknown record tag")
+  };
+  Ok(c)
+}
+fn strip_paren(s:&str) -> Result<&str, ChangeError> {
+  if s.ends_with(')') { Ok(&s[..s.len()-1]) } else { err("missing closing parenthesis") }
+}
+fn decode_num(s:&str) -> Result<u64, ChangeError> {
+  if is_b64(s) { Ok(b64int(s)) } else { err(&format!("invalid number {:?}", s)) }
+}
+fn decode_marks(s:&str) -> Result<Vec<usize>, ChangeError> {
+  if s.len() == 0 { return err("empty list of marks") }
+  s.split(',').map(|x| decode_num(x).map(|m| m as usize)).collect()
+}
+fn decode_nodes(s:&str) -> Result<Vec<u64>, ChangeError> {
+  if s.len() == 0 { return Ok(Vec::new()) }
+  s.split(',').map(|x| {
+    let expanded = match x.chars().next() {
+      Some('+') => true,
+      Some('-') => false,
+      _ => return err(&format!("invalid node {:?}", x))
+    };
+    let mut it = x[1..].split('.');
+    let (lev, ignx, lab) = match (it.next(), it.next(), it.next(), it.next()) {
+      (Some(a), Some(b), Some(c), None) => (decode_num(a)?, decode_num(b)?, decode_num(c)?),
+      _ => return err(&format!("invalid node {:?}", x))
+    };
+    let mut y = LevGnx::make(lev as u8, ignx as u32, lab as u32);
+    if expanded { y.expand() }
+    Ok(y)
+  }).collect()
+}
+fn decode_vnode(s:&str) -> Result<VData, ChangeError> {
+  let mut it = s.split(0 as char);
+  if it.next().map(is_b64) != Some(true) || it.count() != 3 {
+    return err("invalid vnode");
+  }
+  Ok(VData::from_str(s))
+}
+fn check_blocks(marks:&Vec<usize>, n:usize) -> Result<(), ChangeError> {
+  if n == 0 || n % marks.len() != 0 {
+    return err("inserted data doesn't match number of marks");
+  }
+  Ok(())
+}
+fn encode_marks(marks:&Vec<usize>, buf:&mut String) {
+  for (i, m) in marks.iter().enumerate() {
+    if i > 0 { buf.push(','); }
+    b64write(*m as u64, buf);
+  }
+}
+fn encode_nodes(data:&Vec<u64>, buf:&mut String) {
+  for (i, m) in data.iter().enumerate() {
+    if i > 0 { buf.push(','); }
+    buf.push(if m.is_expanded() { '+' } else { '-' });
+    b64write(m.level() as u64, buf);
+    buf.push('.');
+    b64write(m.ignx() as u64, buf);
+    buf.push('.');
+    b64write(m.label() as u64, buf);
+  }
+}
+pub(crate) fn write_varint(n:u64, buf:&mut Vec<u8>) {
+  let mut n = n;
+  while n >= 0x80 {
+    buf.push((n as u8) | 0x80);
+    n >>= 7;
+  }
+  buf.push(n as u8);
+}
+pub(crate) fn write_str(s:&str, buf:&mut Vec<u8>) {
+  write_varint(s.len() as u64, buf);
+  buf.extend_from_slice(s.as_bytes());
+}
+fn write_marks(marks:&Vec<usize>, buf:&mut Vec<u8>) {
+  write_varint(marks.len() as u64, buf);
+  for m in marks { write_varint(*m as u64, buf); }
+}
+pub(crate) fn write_nodes(data:&Vec<u64>, buf:&mut Vec<u8>) {
+  write_varint(data.len() as u64, buf);
+  for x in data { buf.extend_from_slice(&x.to_le_bytes()); }
+}
+pub(crate) fn write_vnode(v:&VData, buf:&mut Vec<u8>) {
+  write_varint(v.ignx as u64, buf);
+  write_varint(v.flags as u64, buf);
+  write_str(&v.gnx, buf);
+  write_str(&v.h, buf);
+  write_str(&v.b, buf);
+}
+pub(crate) struct ByteReader<'a> {
+  data: &'a [u8],
+  i: usize
+}
+impl<'a> ByteReader<'a> {
+  pub(crate) fn new(data:&'a [u8]) -> Self { ByteReader { data, i: 0 } }
+
+  pub(crate) fn at_end(&self) -> bool { self.i >= self.data.len() }
+
+  pub(crate) fn byte(&mut self) -> Result<u8, ChangeError> {
+    match self.data.get(self.i) {
+      Some(x) => { self.i += 1; Ok(*x) },
+      None => err("unexpected end of data")
+    }
+  }
+  pub(crate) fn varint(&mut self) -> Result<u64, ChangeError> {
+    let mut res = 0u64;
+    let mut shift = 0;
+    loop {
+      let b = self.byte()?;
+      if shift > 63 { return err("varint too long") }
+      res |= ((b & 0x7F) as u64) << shift;
+      if b & 0x80 == 0 { return Ok(res) }
+      shift += 7;
+    }
+  }
+  /// returns index of the end of next n bytes, or error if there
+  /// are not enough bytes left
+  fn end_of(&self, n:usize) -> Result<usize, ChangeError> {
+    match self.i.checked_add(n) {
+      Some(j) if j <= self.data.len() => Ok(j),
+      _ => err("unexpected end of data")
+    }
+  }
+  pub(crate) fn string(&mut self) -> Result<String, ChangeError> {
+    let n = self.varint()? as usize;
+    let j = self.end_of(n)?;
+    let s = std::str::from_utf8(&self.data[self.i..j]);
+    self.i = j;
+    match s {
+      Ok(s) => Ok(s.to_string()),
+      Err(_) => err("invalid utf-8 string")
+    }
+  }
+  fn marks(&mut self) -> Result<Vec<usize>, ChangeError> {
+    let n = self.varint()? as usize;
+    if n == 0 { return err("empty list of marks") }
+    (0..n).map(|_| self.varint().map(|x| x as usize)).collect()
+  }
+  pub(crate) fn nodes(&mut self) -> Result<Vec<u64>, ChangeError> {
+    let n = self.varint()? as usize;
+    let j = self.end_of(n.checked_mul(8).unwrap_or(usize::MAX))?;
+    let res = self.data[self.i..j]
+      .chunks(8)
+      .map(|x| {
+        let mut a = [0u8; 8];
+        a.copy_from_slice(x);
+        u64::from_le_bytes(a)
+      })
+      .collect();
+    self.i = j;
+    Ok(res)
+  }
+  pub(crate) fn vnode(&mut self) -> Result<VData, ChangeError> {
+    let ignx = self.varint()? as u32;
+    let flags = self.varint()? as u16;
+    let mut v = VData::new(&self.string()?);
+    v.h = self.string()?;
+    v.b = self.string()?;
+    v.ignx = ignx;
+    v.flags = flags;
+    Ok(v)
+  }
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::from_leo_content;
+  use crate::model::{OutlineOps, move_node_right, clone_node};
+  const LEO:&str = r#"<?xml version="1.0" encoding="utf-8"?>
+<leo_file><vnodes>
+<v t="a"><vh>A</vh></v>
+<v t="b"><vh>B</vh><v t="c"><vh>C</vh></v></v>
+<v t="d"><vh>D</vh></v>
+</vnodes><tnodes></tnodes></leo_file>"#;
+  #[test]
+  fn test_text_roundtrip() {
+    let (mut o, _) = from_leo_content(LEO);
+    let s1 = move_node_right(&mut o, 4).unwrap();
+    let (_, s2) = clone_node(&mut o, 2);
+    for s in [s1, s2].iter() {
+      let chs = decode_changes(s).unwrap();
+      let s3:Vec<String> = chs.iter().map(|x|x.to_text()).collect();
+      assert_eq!(&s3.join("\n"), s);
+      let enc = encode_changes(&chs);
+      assert_eq!(decode_changes(&enc).unwrap(), chs);
+      assert_eq!(changes_from_bytes(&changes_to_bytes(&chs)).unwrap(), chs);
+    }
+  }
+  #[test]
+  fn test_undo_redo() {
+    let (mut o, mut nodes) = from_leo_content(LEO);
+    let o1 = o.clone();
+    let s = clone_node(&mut o, 2).1;
+    let o2 = o.clone();
+    let chs = decode_changes(&s).unwrap();
+    assert!(chs.iter().all(|x|x.fits(&o, &nodes)));
+    undo_changes(&mut o, &mut nodes, &chs);
+    assert_eq!(o, o1);
+    redo_changes(&mut o, &mut nodes, &chs);
+    assert_eq!(o, o2);
+    assert!(o.has(3));
+  }
+  #[test]
+  fn test_vnode_update_roundtrip() {
+    let (_, mut nodes) = from_leo_content(LEO);
+    let n0 = nodes.clone();
+    let mut v = nodes[1].clone();
+    v.h = "A\twith tab\nand newline \\".to_string();
+    v.b = "body\twith\ttabs\n".to_string();
+    let mut s = "vupd:".to_string();
+    nodes[1].write_to(&mut s);
+    s.push('\t');
+    v.write_to(&mut s);
+    let chs = decode_changes(&s).unwrap();
+    assert_eq!(decode_changes(&encode_changes(&chs)).unwrap(), chs);
+    assert_eq!(changes_from_bytes(&changes_to_bytes(&chs)).unwrap(), chs);
+    redo_changes(&mut vec![], &mut nodes, &chs);
+    assert_eq!(nodes[1], v);
+    undo_changes(&mut vec![], &mut nodes, &chs);
+    assert_eq!(nodes, n0);
+  }
+  #[test]
+  fn test_epoch() {
+    assert_eq!(change_epoch("expand:3").unwrap(), (0, "expand:3"));
+    assert_eq!(with_epoch(0, "expand:3"), "expand:3");
+    let s = with_epoch(3, "#changes:1\nexpand:3");
+    assert_eq!(change_epoch(&s).unwrap(), (3, "#changes:1\nexpand:3"));
+    assert_eq!(with_epoch(5, &s), with_epoch(5, "#changes:1\nexpand:3"));
+    assert_eq!(decode_changes(&s).unwrap(), vec![Change::Expand(3)]);
+    assert_eq!(with_epoch(2, ""), "");
+    assert_eq!(bytes_to_text(&text_to_bytes(&s).unwrap()).unwrap(), s);
+    assert_eq!(bytes_to_text(&text_to_bytes("expand:3").unwrap()).unwrap(), "#changes:1\nexpand:3");
+  }
+  #[test]
+  fn test_malformed() {
+    assert_eq!(decode_changes("expand:3\nfoo:1").unwrap_err().line, 2);
+    assert!(decode_changes("ip:2,(3),(+1.2)").is_err());
+    assert!(decode_changes("sb:2,1,(3,").is_err());
+    assert!(decode_changes("#changes:7\nexpand:3").is_err());
+    assert!(decode_changes("#epoch:?\nexpand:3").is_err());
+    assert_eq!(decode_changes("#epoch:2\nexpand:3\nfoo:1").unwrap_err().line, 3);
+    assert!(changes_from_bytes(b"MLC\x01\x01\x09").is_err());
+    // out of range values are errors, not panics
+    assert_eq!(decode_changes("sb:1,-20,(1)").unwrap(),
+               vec![Change::ShiftBlocks { size: 1, delta: -128, marks: vec![1] }]);
+    assert!(decode_changes("sb:1,-21,(1)").is_err());
+    assert!(decode_changes("sb:1,20,(1)").is_err());
+    assert!(decode_changes("db:zzzzzzzzzzz,(1,2),(-1.1.1)").is_err());
+    assert!(changes_from_bytes(b"MLC\x01\x01\x02\x01\x01\x00\x80\x80\x80\x80\x80\x80\x80\x80\x20").is_err());
+    assert!(changes_from_bytes(b"MLC\x01\x01\x05\xff\xff\xff\xff\xff\xff\xff\xff\x7f").is_err());
+  }
+}
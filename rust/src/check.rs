@@ -0,0 +1,200 @@
+use crate::model::{VData, Outline, LevGnxOps};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Problem found by check_outline
+#[derive(Debug, PartialEq, Clone)]
+pub enum Problem {
+  /// outline entry refers to a vnode which doesn't exist
+  BadIgnx { index:usize, ignx:u32 },
+  /// node at index is more than one level deeper than the previous one
+  LevelJump { index:usize },
+  /// subtree of the clone at index differs from the first occurrence
+  CloneMismatch { ignx:u32, index:usize },
+  /// node at index has a label used by an earlier node
+  DuplicateLabel { index:usize, label:u32 },
+  /// label of the root is lower than the max label in the outline
+  RootLabel { label:u32, max:u32 },
+  /// vnode in the slot has a different ignx
+  WrongIgnx { slot:usize, ignx:u32 },
+  /// more vnodes have the same gnx
+  DuplicateGnx { gnx:String, ignxs:Vec<u32> },
+  /// vnode is not used in the outline
+  Orphan { ignx:u32 },
+  /// node at index is inside the subtree of one of its own clones
+  CloneCycle { ignx:u32, index:usize }
+}
+impl Problem {
+  /// returns true if repair_outline can fix this problem
+  pub fn is_repairable(&self) -> bool {
+    matches!(self, Problem::LevelJump{..} | Problem::DuplicateLabel{..}
+                 | Problem::RootLabel{..} | Problem::WrongIgnx{..})
+  }
+}
+impl fmt::Display for Problem {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Problem::BadIgnx { index, ignx } =>
+        write!(f, "node at index {} refers to missing vnode {}", index, ignx),
+      Problem::LevelJump { index } =>
+        write!(f, "level jump at index {}", index),
+      Problem::CloneMismatch { ignx, index } =>
+        write!(f, "clone of vnode {} at index {} differs from its first occurrence", ignx, index),
+      Problem::DuplicateLabel { index, label } =>
+        write!(f, "duplicate position {} at index {}", label, index),
+      Problem::RootLabel { label, max } =>
+        write!(f, "root label {} is lower than max position {}", label, max),
+      Problem::WrongIgnx { slot, ignx } =>
+        write!(f, "vnode in slot {} has ignx {}", slot, ignx),
+      Problem::DuplicateGnx { gnx, ignxs } =>
+        write!(f, "gnx {} is used by vnodes {:?}", gnx, ignxs),
+      Problem::Orphan { ignx } =>
+        write!(f, "vnode {} is not used in the outline", ignx),
+      Problem::CloneCycle { ignx, index } =>
+        write!(f, "vnode {} at index {} is its own ancestor", ignx, index)
+    }
+  }
+}
+// subtree at index i with levels relative to it
+fn subtree_at(o:&Outline, i:usize) -> Vec<(u8, u32)> {
+  let zlev = o[i].level();
+  let mut res = vec![(0, o[i].ignx())];
+  res.extend(o[i+1..].iter()
+    .take_while(|x|x.level() > zlev)
+    .map(|x|(x.level() - zlev, x.ignx())));
+  res
+}
+/// checks the outline and returns all problems found
+pub fn check_outline(o:&Outline, nodes:&Vec<VData>) -> Vec<Problem> {
+  let mut res = Vec::new();
+  let n = nodes.len() as u32;
+  for (i, x) in o.iter().enumerate().skip(1) {
+    if x.ignx() >= n { res.push(Problem::BadIgnx { index: i, ignx: x.ignx() }) }
+  }
+  for (i, x) in o.iter().enumerate().skip(1) {
+    if x.level() == 0 || x.level() > o[i-1].level() + 1 {
+      res.push(Problem::LevelJump { index: i });
+    }
+  }
+  // clones and cycles
+  let mut first:HashMap<u32, Vec<(u8, u32)>> = HashMap::new();
+  let mut stack:Vec<u32> = Vec::new();
+  for (i, x) in o.iter().enumerate().skip(1) {
+    let lev = x.level() as usize;
+    stack.truncate(lev.saturating_sub(1));
+    if stack.contains(&x.ignx()) {
+      res.push(Problem::CloneCycle { ignx: x.ignx(), index: i });
+    }
+    stack.push(x.ignx());
+    let st = subtree_at(o, i);
+    match first.get(&x.ignx()) {
+      Some(t) => if *t != st { res.push(Problem::CloneMismatch { ignx: x.ignx(), index: i }) },
+      None => { first.insert(x.ignx(), st); }
+    }
+  }
+  let mut labels:HashSet<u32> = HashSet::new();
+  for (i, x) in o.iter().enumerate().skip(1) {
+    if !labels.insert(x.label()) {
+      res.push(Problem::DuplicateLabel { index: i, label: x.label() });
+    }
+  }
+  let max = o.iter().skip(1).map(|x|x.label()).max().unwrap_or(0);
+  if let Some(r) = o.first() {
+    if r.label() < max { res.push(Problem::RootLabel { label: r.label(), max }) }
+  }
+  let mut gnxs:HashMap<&str, Vec<u32>> = HashMap::new();
+  for (i, v) in nodes.iter().enumerate() {
+    if v.ignx as usize != i { res.push(Problem::WrongIgnx { slot: i, ignx: v.ignx }) }
+    gnxs.entry(v.gnx.as_str()).or_insert_with(Vec::new).push(i as u32);
+  }
+  let mut dups:Vec<(&str, Vec<u32>)> = gnxs.into_iter().filter(|x|x.1.len() > 1).collect();
+  dups.sort_by_key(|x|x.1[0]);
+  for (gnx, ignxs) in dups {
+    res.push(Problem::DuplicateGnx { gnx: gnx.to_string(), ignxs });
+  }
+  let used:HashSet<u32> = o.iter().map(|x|x.ignx()).collect();
+  for i in 1..n {
+    if !used.contains(&i) { res.push(Problem::Orphan { ignx: i }) }
+  }
+  res
+}
+/// fixes problems which can be fixed without losing any data:
+/// levels are made consecutive keeping the shape of the outline,
+/// duplicate labels get new values, root label is set to the max label
+/// and vnodes get ignx of their slot.
+///
+/// Returns descriptions of the repairs made and the problems which
+/// remain after the repair.
+pub fn repair_outline(o:&mut Outline, nodes:&mut Vec<VData>) -> (Vec<String>, Vec<Problem>) {
+  let mut done = Vec::new();
+  for (i, v) in nodes.iter_mut().enumerate() {
+    if v.ignx as usize != i {
+      done.push(format!("set ignx of vnode in slot {} from {} to {}", i, v.ignx, i));
+      v.ignx = i as u32;
+    }
+  }
+  // (old level, new level) of the current ancestors
+  let mut stack:Vec<(u8, u8)> = vec![(0, 0)];
+  for i in 1..o.len() {
+    let lev = o[i].level().max(1);
+    while stack.len() > 1 && stack.last().unwrap().0 >= lev { stack.pop(); }
+    let nlev = stack.last().unwrap().1 + 1;
+    stack.push((lev, nlev));
+    if nlev != o[i].level() {
+      done.push(format!("changed level at index {} from {} to {}", i, o[i].level(), nlev));
+      o[i].set_level(nlev);
+    }
+  }
+  let mut max = o.iter().skip(1).map(|x|x.label()).max().unwrap_or(0);
+  let mut labels:HashSet<u32> = HashSet::new();
+  for i in 1..o.len() {
+    if !labels.insert(o[i].label()) {
+      max += 1;
+      done.push(format!("changed position at index {} from {} to {}", i, o[i].label(), max));
+      o[i].set_label(max);
+    }
+  }
+  if !o.is_empty() && o[0].label() < max {
+    done.push(format!("changed root label from {} to {}", o[0].label(), max));
+    o[0].set_label(max);
+  }
+  let rest = check_outline(o, nodes);
+  (done, rest)
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::from_leo_content;
+  #[test]
+  fn test_check_and_repair() {
+    let (mut o, mut nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="a"><vh>A</vh><v t="b"><vh>B</vh><v t="d"><vh>D</vh></v></v></v>
+<v t="c"><vh>C</vh><v t="b"/></v>
+</vnodes><tnodes></tnodes></leo_file>"#);
+    assert_eq!(check_outline(&o, &nodes), vec![]);
+    let mut v = VData::new("a");
+    v.ignx = 7;
+    nodes.push(v);
+    o[3].set_level(4);
+    let l = o[1].label();
+    o[6].set_label(l);
+    o[0].set_label(1);
+    let max = o.iter().skip(1).map(|x|x.label()).max().unwrap();
+    let ps = check_outline(&o, &nodes);
+    assert_eq!(ps, vec![
+      Problem::LevelJump { index: 3 },
+      Problem::CloneMismatch { ignx: 2, index: 5 },
+      Problem::DuplicateLabel { index: 6, label: l },
+      Problem::RootLabel { label: 1, max },
+      Problem::WrongIgnx { slot: 5, ignx: 7 },
+      Problem::DuplicateGnx { gnx: "a".to_string(), ignxs: vec![1, 5] },
+      Problem::Orphan { ignx: 5 }]);
+    let (done, rest) = repair_outline(&mut o, &mut nodes);
+    assert_eq!(done.len(), 4);
+    assert_eq!(rest, vec![
+      Problem::DuplicateGnx { gnx: "a".to_string(), ignxs: vec![1, 5] },
+      Problem::Orphan { ignx: 5 }]);
+    assert_eq!(o[3].level(), 3);
+    assert_eq!(o[0].label(), max + 1);
+  }
+}
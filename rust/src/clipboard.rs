@@ -0,0 +1,196 @@
+use crate::model::{VData, Outline, OutlineOps, LevGnxOps, TreeError, gnx_index, insert_block};
+use crate::parsing::try_from_leo_content;
+use crate::writing::to_leo_content;
+use std::collections::HashMap;
+
+/// returns xml which Leo puts on the clipboard when the node at
+/// index i is copied
+pub fn copy_outline(o:&Outline, nodes:&Vec<VData>, i:usize) -> String {
+  to_leo_content(o, nodes, i)
+}
+/// pastes the outline from Leo clipboard xml as the next sibling(s)
+/// of the node at index i.
+///
+/// If retain_gnx is true, pasted nodes keep their gnx and nodes whose
+/// gnx already exists in the outline become clones of existing nodes.
+/// A vnode that is no longer used anywhere in the outline is reused
+/// with the pasted headline and body.
+/// Otherwise every pasted vnode gets a new gnx returned by new_gnx,
+/// which is called with the pasted gnx.
+///
+/// Returns label of the first pasted node and undo_info.
+pub fn paste_outline(
+    o:&mut Outline,
+    nodes:&mut Vec<VData>,
+    i:usize,
+    xml:&str,
+    retain_gnx:bool,
+    new_gnx:&mut dyn FnMut(&str) -> String) -> Result<(u32, String), TreeError> {
+  if i == 0 || i >= o.len() {
+    return Err(TreeError(format!("no node at index {}", i)));
+  }
+  if !xml.contains("<vnodes>") {
+    return Err(TreeError("not a Leo outline".to_string()));
+  }
+  let (po, pn) = try_from_leo_content(xml).map_err(TreeError)?;
+  if po.len() < 2 {
+    return Err(TreeError("no nodes to paste".to_string()));
+  }
+  let pi = o.parent_index(i);
+  let pignx = o[pi].ignx();
+  let mut buf = String::new();
+  let mut vupd = String::new();
+  let mut tch:Outline = Vec::new();
+  {
+    let index = gnx_index(nodes);
+    let mut remap:HashMap<u32, u32> = HashMap::new();
+    let mut added:Vec<VData> = Vec::new();
+    let mut revived:Vec<VData> = Vec::new();
+    let mut skip_level = 255u8;
+    for x in &po[1..] {
+      if x.level() > skip_level { continue }
+      skip_level = 255u8;
+      let pv = &pn[x.ignx() as usize];
+      let existing = if retain_gnx { index.get(pv.gnx.as_str()).copied() } else { None };
+      if let Some(ignx) = existing {
+        let st = o.subtree(ignx);
+        if st.len() > 0 {
+          // node is already in the outline, its clones must be equal
+          for mut y in st {
+            y.shift((x.level() - 1) as i8);
+            tch.push(y);
+          }
+          skip_level = x.level();
+          continue;
+        }
+      }
+      let ignx = match existing {
+        // vnode is not in the outline any more, so it gets the pasted content
+        Some(ignx) => *remap.entry(x.ignx()).or_insert_with(|| {
+          let mut v = nodes[ignx as usize].clone();
+          vupd.push_str("vupd:");
+          v.write_to(&mut vupd);
+          v.h.clone_from(&pv.h);
+          v.b.clone_from(&pv.b);
+          vupd.push('\t');
+          v.write_to(&mut vupd);
+          vupd.push('\n');
+          revived.push(v);
+          ignx
+        }),
+        None => *remap.entry(x.ignx()).or_insert_with(|| {
+          let ignx = (nodes.len() + added.len()) as u32;
+          let gnx = if retain_gnx { pv.gnx.clone() } else { new_gnx(&pv.gnx) };
+          let mut v = VData::new(&gnx);
+          v.ignx = ignx;
+          buf.push_str("addv:");
+          buf.push_str(&gnx);
+          buf.push('\n');
+          vupd.push_str("vupd:");
+          v.write_to(&mut vupd);
+          v.h.push_str(&pv.h);
+          v.b.push_str(&pv.b);
+          vupd.push('\t');
+          v.write_to(&mut vupd);
+          vupd.push('\n');
+          added.push(v);
+          ignx
+        })
+      };
+      let mut y = *x;
+      y.set_ignx(ignx);
+      y.shift(-1);
+      tch.push(y);
+    }
+    if tch.iter().any(|x|x.ignx() == pignx) {
+      return Err(TreeError("pasted outline contains its own parent".to_string()));
+    }
+    for v in revived.into_iter() {
+      let i = v.ignx as usize;
+      nodes[i] = v;
+    }
+    nodes.extend(added);
+  }
+  buf.push_str(&vupd);
+  let j = i + o.subtree_size(i);
+  let (label, s) = insert_block(o, pi, j, &tch);
+  buf.push_str(&s);
+  Ok((label, buf))
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::changes::{decode_changes, undo_changes, redo_changes};
+  use crate::parsing::from_leo_content;
+  const LEO:&str = r#"<leo_file><vnodes>
+<v t="a"><vh>A</vh><v t="b"><vh>B</vh></v></v>
+<v t="c"><vh>C</vh></v>
+</vnodes><tnodes><t tx="b">body b</t></tnodes></leo_file>"#;
+  #[test]
+  fn test_copy_paste() {
+    let (mut o, mut nodes) = from_leo_content(LEO);
+    let xml = copy_outline(&o, &nodes, 1);
+    let mut n = 0;
+    let (p, s) = paste_outline(&mut o, &mut nodes, 3, &xml, false, &mut |g| {
+      n += 1;
+      format!("{}.{}", g, n)
+    }).unwrap();
+    assert_eq!(o.len(), 6);
+    assert_eq!(o.label_index(p), Some(4));
+    assert_eq!(nodes[o[5].ignx() as usize].gnx, "b.2");
+    assert_eq!(nodes[o[5].ignx() as usize].b, "body b");
+
+    let chs = decode_changes(&s).unwrap();
+    let (o2, n2) = (o.clone(), nodes.clone());
+    undo_changes(&mut o, &mut nodes, &chs);
+    assert_eq!(o.len(), 4);
+    assert_eq!(nodes.len(), 4);
+    redo_changes(&mut o, &mut nodes, &chs);
+    assert_eq!(o, o2);
+    assert_eq!(nodes, n2);
+  }
+  #[test]
+  fn test_paste_retaining_gnx() {
+    let (mut o, mut nodes) = from_leo_content(LEO);
+    let xml = copy_outline(&o, &nodes, 1);
+    let (_, _) = paste_outline(&mut o, &mut nodes, 3, &xml, true, &mut |g|g.to_string()).unwrap();
+    // pasted a is a clone of existing a
+    assert_eq!(o.len(), 6);
+    assert_eq!(nodes.len(), 4);
+    assert_eq!(o[4].ignx(), o[1].ignx());
+    // can't paste a inside itself
+    assert!(paste_outline(&mut o, &mut nodes, 2, &xml, true, &mut |g|g.to_string()).is_err());
+    // truncated clipboard content is not pasted
+    let bad = xml.replace("</v>", "</x>");
+    assert!(paste_outline(&mut o, &mut nodes, 3, &bad, true, &mut |g|g.to_string()).is_err());
+    assert_eq!(o.len(), 6);
+  }
+  #[test]
+  fn test_paste_malformed() {
+    let (mut o, mut nodes) = from_leo_content(LEO);
+    let (o1, n1) = (o.clone(), nodes.clone());
+    let xml = copy_outline(&o, &nodes, 1);
+    for bad in [xml.replace(r#"<v t="b">"#, "<v>"),
+                xml.replace(r#"<t tx="b">"#, "<t>"),
+                xml.replace("<vh>A</vh>", "<vh>A &bogus;</vh>")].iter() {
+      assert!(paste_outline(&mut o, &mut nodes, 3, bad, false, &mut |g|g.to_string()).is_err());
+      assert_eq!((&o, &nodes), (&o1, &n1));
+    }
+  }
+  #[test]
+  fn test_paste_unused_gnx() {
+    let (mut o, mut nodes) = from_leo_content(LEO);
+    let xml = copy_outline(&o, &nodes, 2).replace(">B<", ">B2<").replace("body b", "body b2");
+    // b stays in nodes, but it is no longer in the outline
+    o.remove(2);
+    let n1 = nodes.clone();
+    let (_, s) = paste_outline(&mut o, &mut nodes, 2, &xml, true, &mut |g|g.to_string()).unwrap();
+    assert_eq!(o.len(), 4);
+    assert_eq!(nodes.len(), 4);
+    let v = &nodes[o[3].ignx() as usize];
+    assert_eq!((v.gnx.as_str(), v.h.as_str(), v.b.as_str()), ("b", "B2", "body b2"));
+    let chs = decode_changes(&s).unwrap();
+    undo_changes(&mut o, &mut nodes, &chs);
+    assert_eq!(nodes, n1);
+  }
+}
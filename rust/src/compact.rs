@@ -0,0 +1,138 @@
+use crate::model::{VData, Outline, LevGnxOps};
+use crate::changes::{Change, ChangeError, decode_changes, encode_changes, change_epoch, with_epoch};
+
+/// Result of compact: maps ignx before compaction to ignx after it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Remap {
+  /// new ignx for every old ignx, None if the vnode was dropped
+  pub table: Vec<Option<u32>>,
+  /// number of vnodes after compaction
+  pub len: usize
+}
+impl Remap {
+  /// returns new ignx for the old one. Vnodes which didn't exist at the
+  /// time of compaction, but may be appended later by redoing changes,
+  /// are shifted by the number of dropped vnodes.
+  pub fn get(&self, ignx:u32) -> Option<u32> {
+    let n = self.table.len() as u32;
+    if ignx >= n {
+      Some(ignx - n + self.len as u32)
+    } else {
+      self.table[ignx as usize]
+    }
+  }
+  /// number of dropped vnodes
+  pub fn dropped(&self) -> usize { self.table.len() - self.len }
+}
+/// drops vnodes which are not used in the outline, and renumbers
+/// remaining ones keeping their order. The hidden root is always kept.
+pub fn compact(o:&mut Outline, nodes:&mut Vec<VData>) -> Remap {
+  let mut used = vec![false; nodes.len()];
+  if !used.is_empty() { used[0] = true }
+  for x in o.iter() {
+    if let Some(u) = used.get_mut(x.ignx() as usize) { *u = true }
+  }
+  let mut table = Vec::with_capacity(nodes.len());
+  let mut n = 0u32;
+  for u in used.iter() {
+    table.push(if *u { n += 1; Some(n - 1) } else { None });
+  }
+  let mut k = 0;
+  nodes.retain(|_| { k += 1; used[k - 1] });
+  for (i, v) in nodes.iter_mut().enumerate() { v.ignx = i as u32 }
+  let remap = Remap { table, len: nodes.len() };
+  for x in o.iter_mut() {
+    if let Some(i) = remap.get(x.ignx()) { x.set_ignx(i) }
+  }
+  remap
+}
+fn remap_data(data:&mut Vec<u64>, remap:&Remap) -> Result<(), String> {
+  for x in data.iter_mut() {
+    match remap.get(x.ignx()) {
+      Some(i) => x.set_ignx(i),
+      None => return Err(format!("vnode {} has been dropped", x.ignx()))
+    }
+  }
+  Ok(())
+}
+/// rewrites ignx in change records made before compaction, so that
+/// they can be undone or redone after it. Fails if a record refers to
+/// a dropped vnode, in which case this change and all older ones can't
+/// be undone anymore.
+pub fn remap_changes(txt:&str, remap:&Remap) -> Result<String, ChangeError> {
+  let mut chs = decode_changes(txt)?;
+  for (i, c) in chs.iter_mut().enumerate() {
+    let res = match c {
+      Change::InsertParts { data, .. } | Change::DeleteBlocks { data, .. } => remap_data(data, remap),
+      Change::SetNodes { new, old, .. } => remap_data(new, remap).and_then(|_|remap_data(old, remap)),
+      Change::UpdateVNode { old, new } => match remap.get(old.ignx) {
+        Some(j) => { old.ignx = j; new.ignx = j; Ok(()) },
+        None => Err(format!("vnode {} has been dropped", old.ignx))
+      },
+      _ => Ok(())
+    };
+    if let Err(msg) = res {
+      return Err(ChangeError { line: i + 1, msg });
+    }
+  }
+  Ok(encode_changes(&chs))
+}
+/// converts change records to the current epoch, which is the number of
+/// compactions in remaps. Records are marked with the epoch in which
+/// they were made (see `with_epoch`) and are remapped by all later
+/// compactions. Fails if the epoch is unknown or if records refer to
+/// dropped vnodes.
+pub fn to_current_epoch(txt:&str, remaps:&[Remap]) -> Result<String, ChangeError> {
+  let (e, body) = change_epoch(txt)?;
+  if e == remaps.len() { return Ok(txt.to_string()) }
+  if e > remaps.len() {
+    return Err(ChangeError { line: 1, msg: format!("unknown epoch {}", e) });
+  }
+  let mut res = body.to_string();
+  for r in remaps[e..].iter() {
+    res = remap_changes(&res, r)?;
+  }
+  Ok(with_epoch(remaps.len(), &res))
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::from_leo_content;
+  use crate::model::{delete_node, insert_new_node};
+  use crate::changes::{undo_changes, redo_changes};
+  #[test]
+  fn test_compact() {
+    let (mut o, mut nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="a"><vh>A</vh></v><v t="b"><vh>B</vh></v><v t="c"><vh>C</vh></v>
+</vnodes><tnodes></tnodes></leo_file>"#);
+    let del = delete_node(&mut o, 1).unwrap();
+    let (_, ins) = insert_new_node(&mut o, &mut nodes, 1, "d");
+    let o0 = o.clone();
+    let r = compact(&mut o, &mut nodes);
+    assert_eq!(r.table, vec![Some(0), None, Some(1), Some(2), Some(3)]);
+    assert_eq!(r.dropped(), 1);
+    assert_eq!(nodes.iter().map(|v|v.gnx.as_str()).collect::<Vec<_>>(),
+               vec!["hidden-root-vnode-gnx", "b", "c", "d"]);
+    assert!(nodes.iter().enumerate().all(|(i, v)|v.ignx as usize == i));
+    assert_eq!(o.iter().map(|x|x.label()).collect::<Vec<_>>(),
+               o0.iter().map(|x|x.label()).collect::<Vec<_>>());
+    // insertion can still be undone and redone
+    let ins0 = ins;
+    let ins = remap_changes(&ins0, &r).unwrap();
+    let o1 = o.clone();
+    undo_changes(&mut o, &mut nodes, &decode_changes(&ins).unwrap());
+    assert_eq!(nodes.len(), 3);
+    redo_changes(&mut o, &mut nodes, &decode_changes(&ins).unwrap());
+    assert_eq!(o, o1);
+    assert_eq!(nodes[3].gnx, "d");
+    // deleted vnode is gone
+    assert_eq!(remap_changes(&del, &r).unwrap_err().line, 1);
+    // records without epoch belong to epoch 0 and are remapped
+    let remaps = vec![r];
+    assert!(to_current_epoch(&del, &remaps).is_err());
+    let ins1 = to_current_epoch(&ins0, &remaps).unwrap();
+    assert_eq!(ins1, with_epoch(1, &ins));
+    assert_eq!(to_current_epoch(&ins1, &remaps).unwrap(), ins1);
+    assert!(to_current_epoch(&with_epoch(2, &ins), &remaps).is_err());
+  }
+}
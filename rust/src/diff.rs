@@ -0,0 +1,398 @@
+use crate::model::{VData, Outline, OutlineOps, LevGnxOps, gnx_index};
+use std::collections::{HashMap, HashSet};
+
+/// One line of a line diff
+#[derive(Debug, PartialEq, Clone)]
+pub enum DiffLine {
+  Same(String),
+  Added(String),
+  Removed(String)
+}
+impl DiffLine {
+  /// returns line prefixed with ' ', '+' or '-'
+  pub fn to_prefixed(&self) -> String {
+    match self {
+      DiffLine::Same(s) => format!(" {}", s),
+      DiffLine::Added(s) => format!("+{}", s),
+      DiffLine::Removed(s) => format!("-{}", s)
+    }
+  }
+}
+/// What has changed with a vnode. Parents are given by their gnx.
+#[derive(Debug, PartialEq, Clone)]
+pub enum DiffKind {
+  Inserted { parents:Vec<String> },
+  Deleted { parents:Vec<String> },
+  /// order of the node among its siblings has changed
+  Moved { parent:String, old_index:usize, new_index:usize },
+  Reparented { old_parents:Vec<String>, new_parents:Vec<String> },
+  CloneAdded { parents:Vec<String> },
+  CloneRemoved { parents:Vec<String> },
+  HeadChanged { old:String },
+  BodyChanged { lines:Vec<DiffLine> }
+}
+/// Change of a single vnode, identified by its gnx. Headline is the
+/// new one, or the old one for deleted nodes.
+#[derive(Debug, PartialEq, Clone)]
+pub struct NodeDiff {
+  pub gnx: String,
+  pub h: String,
+  pub kind: DiffKind
+}
+impl NodeDiff {
+  /// returns the name of the change kind, as used in json output
+  pub fn name(&self) -> &'static str {
+    match self.kind {
+      DiffKind::Inserted{..} => "inserted",
+      DiffKind::Deleted{..} => "deleted",
+      DiffKind::Moved{..} => "moved",
+      DiffKind::Reparented{..} => "reparented",
+      DiffKind::CloneAdded{..} => "clone-added",
+      DiffKind::CloneRemoved{..} => "clone-removed",
+      DiffKind::HeadChanged{..} => "head",
+      DiffKind::BodyChanged{..} => "body"
+    }
+  }
+}
+/// Result of diff_outlines
+#[derive(Debug, Default, PartialEq)]
+pub struct OutlineDiff {
+  pub changes: Vec<NodeDiff>,
+  /// headlines of all nodes from both outlines, used in the report
+  heads: HashMap<String, String>
+}
+pub const ROOT_GNX:&str = "hidden-root-vnode-gnx";
+
+/// returns pairs of indices of a longest common subsequence of a and b.
+///
+/// Common prefix and suffix are matched directly and the rest is found
+/// by Hirschberg's algorithm, which needs memory linear in the length
+/// of the texts.
+pub fn lcs<T:PartialEq>(a:&[T], b:&[T]) -> Vec<(usize, usize)> {
+  let p = a.iter().zip(b.iter()).take_while(|(x, y)|x == y).count();
+  let s = a[p..].iter().rev().zip(b[p..].iter().rev()).take_while(|(x, y)|x == y).count();
+  let (n, m) = (a.len() - s, b.len() - s);
+  let mut res:Vec<(usize, usize)> = (0..p).map(|i|(i, i)).collect();
+  hirschberg(&a[p..n], &b[p..m], p, p, &mut res);
+  res.extend((0..s).map(|k|(n + k, m + k)));
+  res
+}
+// appends to res the lcs of a and b, whose indices start at ai and bi
+fn hirschberg<T:PartialEq>(a:&[T], b:&[T], ai:usize, bi:usize, res:&mut Vec<(usize, usize)>) {
+  if a.is_empty() || b.is_empty() { return }
+  if a.len() == 1 {
+    if let Some(j) = b.iter().position(|y|*y == a[0]) { res.push((ai, bi + j)) }
+    return
+  }
+  let mid = a.len() / 2;
+  let top = lcs_lengths(a[..mid].iter(), b.iter());
+  let bottom = lcs_lengths(a[mid..].iter().rev(), b.iter().rev());
+  let m = b.len();
+  let k = (0..=m).max_by_key(|j|(top[*j] + bottom[m - j], std::cmp::Reverse(*j))).unwrap_or(0);
+  hirschberg(&a[..mid], &b[..k], ai, bi, res);
+  hirschberg(&a[mid..], &b[k..], ai + mid, bi + k, res);
+}
+// returns lengths of the lcs of a and every prefix of b
+fn lcs_lengths<'a, T:PartialEq + 'a>(a:impl Iterator<Item=&'a T>, b:impl Iterator<Item=&'a T> + Clone) -> Vec<u32> {
+  let mut row = vec![0u32; b.clone().count() + 1];
+  for x in a {
+    let mut diag = 0;
+    for (j, y) in b.clone().enumerate() {
+      let up = row[j + 1];
+      row[j + 1] = if x == y { diag + 1 } else { up.max(row[j]) };
+      diag = up;
+    }
+  }
+  row
+}
+/// returns line diff of two texts
+pub fn diff_lines(a:&str, b:&str) -> Vec<DiffLine> {
+  let la:Vec<&str> = a.lines().collect();
+  let lb:Vec<&str> = b.lines().collect();
+  let mut res = Vec::new();
+  let (mut i, mut j) = (0, 0);
+  for (x, y) in lcs(&la, &lb).into_iter().chain(std::iter::once((la.len(), lb.len()))) {
+    res.extend(la[i..x].iter().map(|s|DiffLine::Removed(s.to_string())));
+    res.extend(lb[j..y].iter().map(|s|DiffLine::Added(s.to_string())));
+    if x < la.len() { res.push(DiffLine::Same(la[x].to_string())) }
+    i = x + 1;
+    j = y + 1;
+  }
+  res
+}
+// gnx -> list of (parent gnx, child index), and gnx of vnodes in outline order
+fn links<'a>(o:&Outline, nodes:&'a Vec<VData>) -> (HashMap<&'a str, Vec<(&'a str, usize)>>, Vec<&'a str>) {
+  let mut res:HashMap<&str, Vec<(&str, usize)>> = HashMap::new();
+  let mut order = Vec::new();
+  let mut seen:HashSet<u32> = HashSet::new();
+  for (i, x) in o.iter().enumerate() {
+    if !seen.insert(x.ignx()) { continue }
+    let pgnx = nodes[x.ignx() as usize].gnx.as_str();
+    if i > 0 { order.push(pgnx) }
+    for (k, c) in o.children(i).into_iter().enumerate() {
+      res.entry(nodes[c as usize].gnx.as_str()).or_insert_with(Vec::new).push((pgnx, k));
+    }
+  }
+  (res, order)
+}
+// elements of a which are not in b, respecting multiplicity
+fn minus(a:&[&str], b:&[&str]) -> Vec<String> {
+  let mut b = b.to_vec();
+  let mut res = Vec::new();
+  for x in a {
+    match b.iter().position(|y|y == x) {
+      Some(k) => { b.remove(k); },
+      None => res.push(x.to_string())
+    }
+  }
+  res
+}
+/// compares outline a with outline b, matching nodes by gnx
+pub fn diff_outlines(oa:&Outline, na:&Vec<VData>, ob:&Outline, nb:&Vec<VData>) -> OutlineDiff {
+  let (la, order_a) = links(oa, na);
+  let (lb, order_b) = links(ob, nb);
+  let ga = gnx_index(na);
+  let gb = gnx_index(nb);
+  let mut res = OutlineDiff::default();
+  for v in na.iter().chain(nb.iter()) {
+    res.heads.insert(v.gnx.clone(), v.h.clone());
+  }
+  let parents = |l:&HashMap<&str, Vec<(&str, usize)>>, gnx:&str| -> Vec<String> {
+    l.get(gnx).map(|x|x.iter().map(|y|y.0.to_string()).collect()).unwrap_or_default()
+  };
+  // order changes among siblings present in both outlines
+  let mut moved:HashMap<&str, Vec<DiffKind>> = HashMap::new();
+  for p in order_b.iter().copied().chain(std::iter::once(ROOT_GNX)) {
+    let (ia, ib) = match (ga.get(p), gb.get(p)) {
+      (Some(ia), Some(ib)) => (*ia, *ib),
+      _ => continue
+    };
+    let (ca, cb) = match (oa.find(ia), ob.find(ib)) {
+      (Some(x), Some(y)) => (children_gnx(oa, na, x), children_gnx(ob, nb, y)),
+      _ => continue
+    };
+    let common_a:Vec<(usize, &str)> = ca.iter().copied().enumerate().filter(|x|cb.contains(&x.1)).collect();
+    let common_b:Vec<(usize, &str)> = cb.iter().copied().enumerate().filter(|x|ca.contains(&x.1)).collect();
+    let sa:Vec<&str> = common_a.iter().map(|x|x.1).collect();
+    let sb:Vec<&str> = common_b.iter().map(|x|x.1).collect();
+    let kept:HashSet<usize> = lcs(&sa, &sb).into_iter().map(|x|x.1).collect();
+    for (k, (new_index, gnx)) in common_b.iter().enumerate() {
+      if kept.contains(&k) { continue }
+      let old_index = common_a.iter().find(|x|x.1 == *gnx).map(|x|x.0).unwrap_or(0);
+      moved.entry(gnx).or_insert_with(Vec::new)
+        .push(DiffKind::Moved { parent: p.to_string(), old_index, new_index: *new_index });
+    }
+  }
+  let mut done:HashSet<&str> = HashSet::new();
+  for gnx in order_b.iter().copied() {
+    if !done.insert(gnx) { continue }
+    let vb = &nb[gb[gnx] as usize];
+    let mut push = |kind:DiffKind| res.changes.push(NodeDiff { gnx: gnx.to_string(), h: vb.h.clone(), kind });
+    let va = match ga.get(gnx).filter(|_|la.contains_key(gnx)) {
+      Some(i) => &na[*i as usize],
+      None => { push(DiffKind::Inserted { parents: parents(&lb, gnx) }); continue }
+    };
+    let pa:Vec<&str> = la[gnx].iter().map(|x|x.0).collect();
+    let pb:Vec<&str> = lb[gnx].iter().map(|x|x.0).collect();
+    let (removed, added) = (minus(&pa, &pb), minus(&pb, &pa));
+    if !removed.is_empty() && removed.len() == added.len() {
+      push(DiffKind::Reparented { old_parents: removed, new_parents: added });
+    } else {
+      if !added.is_empty() { push(DiffKind::CloneAdded { parents: added }) }
+      if !removed.is_empty() { push(DiffKind::CloneRemoved { parents: removed }) }
+    }
+    for m in moved.remove(gnx).unwrap_or_default() { push(m) }
+    if va.h != vb.h { push(DiffKind::HeadChanged { old: va.h.clone() }) }
+    if va.b != vb.b { push(DiffKind::BodyChanged { lines: diff_lines(&va.b, &vb.b) }) }
+  }
+  for gnx in order_a {
+    if lb.contains_key(gnx) || !done.insert(gnx) { continue }
+    res.changes.push(NodeDiff {
+      gnx: gnx.to_string(),
+      h: na[ga[gnx] as usize].h.clone(),
+      kind: DiffKind::Deleted { parents: parents(&la, gnx) }
+    });
+  }
+  res
+}
+fn children_gnx<'a>(o:&Outline, nodes:&'a Vec<VData>, i:usize) -> Vec<&'a str> {
+  o.children(i).into_iter().map(|c|nodes[c as usize].gnx.as_str()).collect()
+}
+/// appends s to buf as a json string
+pub fn json_str(s:&str, buf:&mut String) {
+  buf.push('"');
+  for c in s.chars() {
+    match c {
+      '"' => buf.push_str("\\\""),
+      '\\' => buf.push_str("\\\\"),
+      '\n' => buf.push_str("\\n"),
+      '\r' => buf.push_str("\\r"),
+      '\t' => buf.push_str("\\t"),
+      c if (c as u32) < 0x20 => buf.push_str(&format!("\\u{:04x}", c as u32)),
+      c => buf.push(c)
+    }
+  }
+  buf.push('"');
+}
+fn json_list<T:AsRef<str>>(xs:&[T], buf:&mut String) {
+  buf.push('[');
+  for (i, x) in xs.iter().enumerate() {
+    if i > 0 { buf.push(',') }
+    json_str(x.as_ref(), buf);
+  }
+  buf.push(']');
+}
+impl OutlineDiff {
+  pub fn is_empty(&self) -> bool { self.changes.is_empty() }
+
+  fn head(&self, gnx:&str) -> String {
+    if gnx == ROOT_GNX { return String::from("<root>") }
+    self.heads.get(gnx).map(|h|format!("'{}'", h)).unwrap_or_else(||gnx.to_string())
+  }
+  fn heads_of(&self, gnxs:&[String]) -> String {
+    gnxs.iter().map(|g|self.head(g)).collect::<Vec<_>>().join(", ")
+  }
+  /// returns json array with an object for every change. Each object
+  /// has keys kind, gnx and h, and the fields of its kind; body line
+  /// diff is a list of lines prefixed with ' ', '+' or '-'.
+  pub fn to_json(&self) -> String {
+    let mut buf = String::from("[");
+    for (i, c) in self.changes.iter().enumerate() {
+      if i > 0 { buf.push_str(",\n ") }
+      buf.push_str("{\"kind\":");
+      json_str(c.name(), &mut buf);
+      buf.push_str(",\"gnx\":");
+      json_str(&c.gnx, &mut buf);
+      buf.push_str(",\"h\":");
+      json_str(&c.h, &mut buf);
+      match &c.kind {
+        DiffKind::Inserted { parents } | DiffKind::Deleted { parents }
+        | DiffKind::CloneAdded { parents } | DiffKind::CloneRemoved { parents } => {
+          buf.push_str(",\"parents\":");
+          json_list(parents, &mut buf);
+        },
+        DiffKind::Moved { parent, old_index, new_index } => {
+          buf.push_str(",\"parent\":");
+          json_str(parent, &mut buf);
+          buf.push_str(&format!(",\"old_index\":{},\"new_index\":{}", old_index, new_index));
+        },
+        DiffKind::Reparented { old_parents, new_parents } => {
+          buf.push_str(",\"old_parents\":");
+          json_list(old_parents, &mut buf);
+          buf.push_str(",\"new_parents\":");
+          json_list(new_parents, &mut buf);
+        },
+        DiffKind::HeadChanged { old } => {
+          buf.push_str(",\"old\":");
+          json_str(old, &mut buf);
+        },
+        DiffKind::BodyChanged { lines } => {
+          buf.push_str(",\"lines\":");
+          let ls:Vec<String> = lines.iter().map(|x|x.to_prefixed()).collect();
+          json_list(&ls, &mut buf);
+        }
+      }
+      buf.push('}');
+    }
+    buf.push(']');
+    buf
+  }
+  /// returns human readable description of all changes
+  pub fn report(&self) -> String {
+    let mut res = String::new();
+    for c in self.changes.iter() {
+      let h = self.head(&c.gnx);
+      match &c.kind {
+        DiffKind::Inserted { parents } =>
+          res.push_str(&format!("inserted {} under {}\n", h, self.heads_of(parents))),
+        DiffKind::Deleted { parents } =>
+          res.push_str(&format!("deleted {} from {}\n", h, self.heads_of(parents))),
+        DiffKind::Moved { parent, old_index, new_index } =>
+          res.push_str(&format!("moved {} in {} from {} to {}\n",
+                                h, self.head(parent), old_index, new_index)),
+        DiffKind::Reparented { old_parents, new_parents } =>
+          res.push_str(&format!("moved {} from {} to {}\n",
+                                h, self.heads_of(old_parents), self.heads_of(new_parents))),
+        DiffKind::CloneAdded { parents } =>
+          res.push_str(&format!("cloned {} under {}\n", h, self.heads_of(parents))),
+        DiffKind::CloneRemoved { parents } =>
+          res.push_str(&format!("removed clone of {} from {}\n", h, self.heads_of(parents))),
+        DiffKind::HeadChanged { old } =>
+          res.push_str(&format!("renamed '{}' to {}\n", old, h)),
+        DiffKind::BodyChanged { lines } => {
+          res.push_str(&format!("changed body of {}\n", h));
+          for l in lines.iter().filter(|x|!matches!(x, DiffLine::Same(_))) {
+            res.push_str("  ");
+            res.push_str(&l.to_prefixed());
+            res.push('\n');
+          }
+        }
+      }
+    }
+    res
+  }
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::from_leo_content;
+  #[test]
+  fn test_lcs() {
+    // pseudo random sequences over a small alphabet
+    let mut seed = 7u32;
+    let mut seq = |n:usize| -> Vec<u32> {
+      (0..n).map(|_|{ seed = seed.wrapping_mul(1103515245).wrapping_add(12345); (seed >> 16) % 4 }).collect()
+    };
+    for (n, m) in [(0, 3), (1, 1), (5, 9), (17, 12), (40, 33)].iter() {
+      let (a, b) = (seq(*n), seq(*m));
+      let mut t = vec![vec![0usize; m + 1]; n + 1];
+      for i in (0..*n).rev() {
+        for j in (0..*m).rev() {
+          t[i][j] = if a[i] == b[j] { t[i+1][j+1] + 1 } else { t[i+1][j].max(t[i][j+1]) };
+        }
+      }
+      let r = lcs(&a, &b);
+      assert_eq!(r.len(), t[0][0]);
+      assert!(r.iter().all(|(i, j)|a[*i] == b[*j]));
+      assert!(r.windows(2).all(|x|x[0].0 < x[1].0 && x[0].1 < x[1].1));
+    }
+    // long texts with a small change in the middle
+    let a:Vec<String> = (0..100000).map(|i|i.to_string()).collect();
+    let mut b = a.clone();
+    b[50000] = "x".to_string();
+    b.insert(50100, "y".to_string());
+    assert_eq!(lcs(&a, &b).len(), a.len() - 1);
+  }
+  #[test]
+  fn test_diff_outlines() {
+    let (oa, na) = from_leo_content(r#"<leo_file><vnodes>
+<v t="a"><vh>A</vh><v t="b"><vh>B</vh></v><v t="c"><vh>C</vh></v></v>
+<v t="d"><vh>D</vh></v>
+<v t="e"><vh>E</vh></v>
+</vnodes><tnodes><t tx="b">one
+two
+three</t></tnodes></leo_file>"#);
+    let (ob, nb) = from_leo_content(r#"<leo_file><vnodes>
+<v t="a"><vh>A</vh><v t="c"><vh>C</vh></v><v t="b"><vh>B</vh></v></v>
+<v t="e"><vh>E1</vh><v t="d"><vh>D</vh></v><v t="c"/></v>
+<v t="f"><vh>F</vh></v>
+</vnodes><tnodes><t tx="b">one
+2
+three</t></tnodes></leo_file>"#);
+    let d = diff_outlines(&oa, &na, &ob, &nb);
+    let kinds:Vec<(&str, &str)> = d.changes.iter().map(|c|(c.gnx.as_str(), c.name())).collect();
+    assert_eq!(kinds, vec![("c", "clone-added"), ("b", "moved"), ("b", "body"),
+                           ("e", "head"), ("d", "reparented"), ("f", "inserted")]);
+    assert_eq!(d.changes[1].kind, DiffKind::Moved { parent: "a".to_string(), old_index: 0, new_index: 1 });
+    assert_eq!(d.changes[2].kind, DiffKind::BodyChanged { lines: vec![
+      DiffLine::Same("one".to_string()),
+      DiffLine::Removed("two".to_string()),
+      DiffLine::Added("2".to_string()),
+      DiffLine::Same("three".to_string())] });
+    assert!(d.report().contains("moved 'D' from <root> to 'E1'\n"));
+    assert!(d.to_json().starts_with(r#"[{"kind":"clone-added","gnx":"c","h":"C","parents":["e"]}"#));
+    let d = diff_outlines(&ob, &nb, &oa, &na);
+    assert_eq!(d.changes.last().map(|c|c.name()), Some("deleted"));
+    assert!(diff_outlines(&oa, &na, &oa, &na).is_empty());
+  }
+}
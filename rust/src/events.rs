@@ -0,0 +1,199 @@
+use crate::changes::{Change, decode_changes};
+
+/// Describes what has changed in the outline after an operation.
+///
+/// Indices are outline indices, and events in a list are ordered so
+/// that applying them one after another to a copy of the outline
+/// (or to a view of it) gives the new state. Node indices are
+/// valid at the moment the event is applied.
+#[derive(Debug, PartialEq, Clone)]
+pub enum OutlineEvent {
+  /// count nodes were inserted starting at index
+  Inserted { index:usize, count:usize },
+  /// count nodes were removed starting at index
+  Removed { index:usize, count:usize },
+  /// count nodes starting at index have changed level or vnode
+  Changed { index:usize, count:usize },
+  /// headline of the vnode with given ignx has changed
+  HeadChanged(u32),
+  /// body of the vnode with given ignx has changed
+  BodyChanged(u32),
+  /// flags of the vnode with given ignx have changed
+  FlagsChanged(u32),
+  /// node at index was expanded
+  Expanded(usize),
+  /// node at index was collapsed
+  Collapsed(usize),
+  /// the whole outline might have changed
+  Reset
+}
+impl OutlineEvent {
+  /// returns the name of this event kind as used by the python API
+  pub fn name(&self) -> &'static str {
+    match self {
+      OutlineEvent::Inserted{..} => "inserted",
+      OutlineEvent::Removed{..} => "removed",
+      OutlineEvent::Changed{..} => "changed",
+      OutlineEvent::HeadChanged(_) => "head",
+      OutlineEvent::BodyChanged(_) => "body",
+      OutlineEvent::FlagsChanged(_) => "flags",
+      OutlineEvent::Expanded(_) => "expanded",
+      OutlineEvent::Collapsed(_) => "collapsed",
+      OutlineEvent::Reset => "reset"
+    }
+  }
+}
+/// returns events describing the given change string. If undo is true,
+/// events describe the undoing of the change. Malformed change strings
+/// are reported as Reset.
+pub fn change_events(change:&str, undo:bool) -> Vec<OutlineEvent> {
+  match decode_changes(change) {
+    Ok(chs) => {
+      let mut res = Vec::new();
+      if undo {
+        for c in chs.iter().rev() { record_events(c, true, &mut res) }
+      } else {
+        for c in chs.iter() { record_events(c, false, &mut res) }
+      }
+      res
+    },
+    Err(_) => vec![OutlineEvent::Reset]
+  }
+}
+fn record_events(c:&Change, undo:bool, res:&mut Vec<OutlineEvent>) {
+  match c {
+    Change::InsertParts { marks, data, .. } => {
+      let size = data.len() / marks.len();
+      let blocks = marks.iter().enumerate().map(|(i, m)| m + i * size);
+      if undo {
+        let v:Vec<usize> = blocks.collect();
+        for index in v.into_iter().rev() {
+          res.push(OutlineEvent::Removed { index, count: size });
+        }
+      } else {
+        for index in blocks {
+          res.push(OutlineEvent::Inserted { index, count: size });
+        }
+      }
+    },
+    Change::DeleteBlocks { size, marks, .. } => {
+      if undo {
+        for index in marks.iter() {
+          res.push(OutlineEvent::Inserted { index: *index, count: *size });
+        }
+      } else {
+        for index in marks.iter().rev() {
+          res.push(OutlineEvent::Removed { index: *index, count: *size });
+        }
+      }
+    },
+    Change::ShiftBlocks { size, marks, .. } |
+    Change::SetNodes { size, marks, .. } => {
+      for index in marks.iter() {
+        res.push(OutlineEvent::Changed { index: *index, count: *size });
+      }
+    },
+    Change::AddVNode { .. } => (),
+    Change::UpdateVNode { old, new } => {
+      if old.h != new.h { res.push(OutlineEvent::HeadChanged(new.ignx)) }
+      if old.b != new.b { res.push(OutlineEvent::BodyChanged(new.ignx)) }
+      if old.flags != new.flags { res.push(OutlineEvent::FlagsChanged(new.ignx)) }
+    },
+    Change::Collapse(i) => res.push(
+      if undo { OutlineEvent::Expanded(*i) } else { OutlineEvent::Collapsed(*i) }),
+    Change::Expand(i) => res.push(
+      if undo { OutlineEvent::Collapsed(*i) } else { OutlineEvent::Expanded(*i) })
+  }
+}
+/// Registry of callbacks interested in outline events.
+///
+/// Each registered callback gets an id which can be used to remove it.
+pub struct Observers<T> {
+  last_id: usize,
+  items: Vec<(usize, T)>
+}
+impl<T> Observers<T> {
+  pub fn new() -> Self {
+    Observers { last_id: 0, items: Vec::new() }
+  }
+  /// registers callback f and returns its id
+  pub fn add(&mut self, f:T) -> usize {
+    self.last_id += 1;
+    self.items.push((self.last_id, f));
+    self.last_id
+  }
+  /// removes callback with the given id, returns false if there is none
+  pub fn remove(&mut self, id:usize) -> bool {
+    let n = self.items.len();
+    self.items.retain(|x|x.0 != id);
+    n != self.items.len()
+  }
+  pub fn is_empty(&self) -> bool { self.items.is_empty() }
+
+  pub fn iter(&self) -> impl Iterator<Item=&T> {
+    self.items.iter().map(|x|&x.1)
+  }
+}
+impl Observers<Box<dyn Fn(&[OutlineEvent]) + Send>> {
+  /// calls every registered callback with the given events
+  pub fn notify(&self, events:&[OutlineEvent]) {
+    if events.is_empty() { return }
+    for f in self.iter() {
+      f(events);
+    }
+  }
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::from_leo_content;
+  use crate::model::{Outline, clone_node, delete_node};
+  use crate::utils::{insert_parts, delete_blocks};
+  use std::sync::{Arc, Mutex};
+
+  // replays events on a copy of the old outline, which is enough to
+  // check that indices are valid when each event is applied
+  fn replay(old:&Outline, new:&Outline, events:&[OutlineEvent]) -> Outline {
+    let mut o = old.clone();
+    for e in events {
+      match e {
+        OutlineEvent::Inserted { index, count } =>
+          insert_parts(&mut o, &vec![*index], &new[*index..*index+count].to_vec()),
+        OutlineEvent::Removed { index, count } => delete_blocks(&mut o, &vec![*index], *count),
+        _ => ()
+      }
+    }
+    o
+  }
+  #[test]
+  fn test_change_events() {
+    let (mut o, _) = from_leo_content(r#"<leo_file><vnodes>
+<v t="a"><vh>A</vh><v t="b"><vh>B</vh></v></v>
+<v t="c"><vh>C</vh></v>
+</vnodes><tnodes></tnodes></leo_file>"#);
+    let o1 = o.clone();
+    let (_, s) = clone_node(&mut o, 2);
+    let evs = change_events(&s, false);
+    assert_eq!(evs, vec![OutlineEvent::Inserted { index: 3, count: 1 }]);
+    let o2 = o.clone();
+    let s = delete_node(&mut o, 1).unwrap();
+    let evs = change_events(&s, false);
+    assert_eq!(replay(&o2, &o, &evs)[1..], o[1..]);
+    let evs = change_events(&s, true);
+    assert_eq!(evs, vec![OutlineEvent::Inserted { index: 1, count: 3 }]);
+    assert_eq!(replay(&o, &o2, &evs)[1..], o2[1..]);
+    assert_eq!(o1.len() + 1, o2.len());
+  }
+  #[test]
+  fn test_observers() {
+    let seen = Arc::new(Mutex::new(0));
+    let mut obs:Observers<Box<dyn Fn(&[OutlineEvent]) + Send>> = Observers::new();
+    let s2 = seen.clone();
+    let id = obs.add(Box::new(move |evs| { *s2.lock().unwrap() += evs.len() }));
+    obs.notify(&change_events("expand:1\ncollapse:2", false));
+    assert_eq!(*seen.lock().unwrap(), 2);
+    assert!(obs.remove(id));
+    obs.notify(&change_events("expand:1", false));
+    assert_eq!(*seen.lock().unwrap(), 2);
+  }
+}
@@ -0,0 +1,325 @@
+use crate::model::{VData, Outline, OutlineOps, LevGnx, LevGnxOps, gnx_index,
+                   find_derived_files, find_clean_files, vnode_updates};
+use crate::atclean::update_atclean_tree;
+use crate::parsing::from_derived_file_content;
+use crate::snapshot::FileStamp;
+use crate::diff::diff_outlines;
+use crate::changes::Change;
+use crate::utils::{FNV_OFFSET, fnv1a};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// External file of an outline: stamp of the file taken when it was
+/// read, gnx of its node and hash of the subtree built from it. The
+/// hash tells whether the subtree has been edited since.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ExternalFile {
+  pub stamp: FileStamp,
+  pub gnx: String,
+  pub tree: u64
+}
+/// How an external file differs from its subtree
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ExternalStatus {
+  /// file has changed on disk
+  Changed,
+  /// file doesn't exist anymore
+  Deleted,
+  /// file has changed on disk and its subtree has been edited too
+  Conflict
+}
+impl ExternalStatus {
+  pub fn name(&self) -> &'static str {
+    match self {
+      ExternalStatus::Changed => "changed",
+      ExternalStatus::Deleted => "deleted",
+      ExternalStatus::Conflict => "conflict"
+    }
+  }
+}
+#[derive(Debug, PartialEq, Clone)]
+pub struct ExternalChange {
+  pub path: String,
+  /// index of the file node, None if it has been deleted from the outline
+  pub index: Option<usize>,
+  pub status: ExternalStatus
+}
+/// returns hash of levels, gnxes, headlines and bodies in the subtree
+/// of the node at index i
+pub fn subtree_hash(o:&Outline, nodes:&Vec<VData>, i:usize) -> u64 {
+  let zlev = o[i].level();
+  o[i..i + o.subtree_size(i)].iter().fold(FNV_OFFSET, |h, x| {
+    let v = &nodes[x.ignx() as usize];
+    let h = fnv1a(h, &[x.level() - zlev]);
+    let h = fnv1a(fnv1a(h, v.gnx.as_bytes()), &[0]);
+    let h = fnv1a(fnv1a(h, v.h.as_bytes()), &[0]);
+    fnv1a(fnv1a(h, v.b.as_bytes()), &[0])
+  })
+}
+impl ExternalFile {
+  /// takes stamp of the file at path which belongs to the node at index i
+  pub fn of(path:&str, o:&Outline, nodes:&Vec<VData>, i:usize) -> Option<Self> {
+    Some(ExternalFile {
+      stamp: FileStamp::of(path).ok()?,
+      gnx: nodes[o[i].ignx() as usize].gnx.clone(),
+      tree: subtree_hash(o, nodes, i)
+    })
+  }
+}
+/// returns all existing `at-file` and `at-clean` files of the outline
+pub fn external_files(folder:&Path, o:&Outline, nodes:&Vec<VData>) -> Vec<ExternalFile> {
+  find_derived_files(folder, o, nodes).into_iter()
+    .chain(find_clean_files(folder, o, nodes))
+    .filter_map(|(f, i)|ExternalFile::of(&f, o, nodes, i))
+    .collect()
+}
+/// returns external files of the outline whose stamps were taken
+/// earlier, e.g. when a snapshot was written. Stamps of files that are
+/// not in the outline are ignored.
+pub fn external_files_from_stamps(folder:&Path, o:&Outline, nodes:&Vec<VData>, stamps:&[FileStamp]) -> Vec<ExternalFile> {
+  let index:HashMap<String, usize> = find_derived_files(folder, o, nodes).into_iter()
+    .chain(find_clean_files(folder, o, nodes))
+    .collect();
+  stamps.iter().filter_map(|s|index.get(&s.path).map(|i|ExternalFile {
+    stamp: s.clone(),
+    gnx: nodes[o[*i].ignx() as usize].gnx.clone(),
+    tree: subtree_hash(o, nodes, *i)
+  })).collect()
+}
+/// returns external files which have changed since they were read
+pub fn external_changes(o:&Outline, nodes:&Vec<VData>, files:&[ExternalFile]) -> Vec<ExternalChange> {
+  let index = gnx_index(nodes);
+  files.iter().filter(|f|!f.stamp.is_fresh()).map(|f| {
+    let i = index.get(f.gnx.as_str()).and_then(|ignx|o.find(*ignx)).filter(|i|*i > 0);
+    let status = if !Path::new(&f.stamp.path).exists() {
+      ExternalStatus::Deleted
+    } else if i.map(|i|subtree_hash(o, nodes, i) != f.tree).unwrap_or(false) {
+      ExternalStatus::Conflict
+    } else {
+      ExternalStatus::Changed
+    };
+    ExternalChange { path: f.stamp.path.clone(), index: i, status }
+  }).collect()
+}
+/// returns report of the differences between the subtree of the node
+/// at index i and the outline read from the derived file content.
+/// Report is empty if they are the same.
+pub fn atfile_conflicts(o:&Outline, nodes:&Vec<VData>, i:usize, content:&str) -> String {
+  let (o2, n2) = from_derived_file_content(content);
+  let mut ob = vec![LevGnx::make(0, 0, 0)];
+  ob.extend(o2);
+  diff_outlines(&top_subtree(o, i), nodes, &ob, &n2).report()
+}
+/// returns report of the changes that reading the `at-clean` file content
+/// would make in bodies of the subtree of the node at index i. Report
+/// is empty if there are none.
+pub fn atclean_conflicts(o:&Outline, nodes:&Vec<VData>, i:usize, content:&str) -> String {
+  let mut n2 = nodes.clone();
+  update_atclean_tree(o, &mut n2, i, content);
+  let oa = top_subtree(o, i);
+  diff_outlines(&oa, nodes, &oa, &n2).report()
+}
+// outline containing only the subtree of the node at index i as its
+// single top level node
+fn top_subtree(o:&Outline, i:usize) -> Outline {
+  let zlev = o[i].level();
+  let mut res = vec![o[0]];
+  res.extend(o[i..i + o.subtree_size(i)].iter().map(|x|{
+    let mut y = *x;
+    y.set_level(x.level() - zlev + 1);
+    y
+  }));
+  res
+}
+/// updates bodies in the `at-clean` subtree of the node at index i from
+/// the file content, see update_atclean_tree.
+///
+/// Returns undo_info, which is empty if nothing has changed.
+pub fn reload_atclean(o:&Outline, nodes:&mut Vec<VData>, i:usize, content:&str) -> String {
+  let mut seen = HashSet::new();
+  let old:Vec<VData> = o[i..i + o.subtree_size(i)].iter()
+    .filter(|x|seen.insert(x.ignx()))
+    .map(|x|nodes[x.ignx() as usize].clone())
+    .collect();
+  update_atclean_tree(o, nodes, i, content);
+  vnode_updates(&old, nodes)
+}
+/// replaces the subtree of the `at-file` node at index i with the
+/// outline read from the derived file content. Vnodes are matched by
+/// gnx; changed headlines and bodies are updated and new vnodes are
+/// appended. Every occurrence of a vnode from the file gets its new
+/// children, also clones outside of the subtree. Subtrees that haven't
+/// changed keep their positions, other nodes keep expanded state of the
+/// vnode they were showing.
+///
+/// Returns undo_info, which is empty if nothing has changed.
+pub fn reload_atfile(o:&mut Outline, nodes:&mut Vec<VData>, i:usize, content:&str) -> Result<String, String> {
+  let (o2, n2) = from_derived_file_content(content);
+  if o2.is_empty() { return Err("no nodes in the file".to_string()) }
+  let mut chs:Vec<Change> = Vec::new();
+  // (relative level, ignx) of the new subtree
+  let mut tree:Vec<(u8, u32)> = Vec::with_capacity(o2.len());
+  {
+    let mut index:HashMap<&str, u32> = gnx_index(nodes);
+    let mut seen:HashSet<u32> = HashSet::new();
+    let mut n = nodes.len() as u32;
+    for (k, x) in o2.iter().enumerate() {
+      let v2 = &n2[x.ignx() as usize];
+      let (ignx, old) = match index.get(v2.gnx.as_str()) {
+        Some(ignx) if k > 0 => (*ignx, nodes[*ignx as usize].clone()),
+        _ if k == 0 => (o[i].ignx(), nodes[o[i].ignx() as usize].clone()),
+        _ => {
+          chs.push(Change::AddVNode { gnx: v2.gnx.clone() });
+          index.insert(v2.gnx.as_str(), n);
+          let mut v = VData::new(&v2.gnx);
+          v.ignx = n;
+          n += 1;
+          (v.ignx, v)
+        }
+      };
+      tree.push((x.level() - 1, ignx));
+      if !seen.insert(ignx) { continue }
+      let mut new = old.clone();
+      new.h.replace_range(.., &v2.h);
+      new.b.replace_range(.., &v2.b);
+      if new != old { chs.push(Change::UpdateVNode { old, new }) }
+    }
+  }
+  // span of the first occurrence of every vnode in the new subtree
+  let mut spans:HashMap<u32, (usize, usize)> = HashMap::new();
+  for (k, (lev, ignx)) in tree.iter().enumerate() {
+    spans.entry(*ignx).or_insert_with(||{
+      (k, k + 1 + tree[k + 1..].iter().take_while(|x|x.0 > *lev).count())
+    });
+  }
+  let mut label = o[0].label();
+  let mut res:Outline = vec![o[0]];
+  let mut j = 1;
+  while j < o.len() {
+    let x = o[j];
+    let (a, b) = match spans.get(&x.ignx()) {
+      Some(span) => *span,
+      None => { res.push(x); j += 1; continue }
+    };
+    let sz = o.subtree_size(j);
+    let (zlev, st) = (x.level(), &tree[a..b]);
+    let same = sz == st.len() && o[j..j + sz].iter().zip(st.iter())
+      .all(|(y, (lev, ignx))|y.level() - zlev == lev - st[0].0 && y.ignx() == *ignx);
+    if same {
+      res.extend_from_slice(&o[j..j + sz]);
+    } else {
+      let expanded:HashSet<u32> = o[j..j + sz].iter()
+        .filter(|y|y.is_expanded())
+        .map(|y|y.ignx())
+        .collect();
+      res.push(x);
+      for (lev, ignx) in st[1..].iter() {
+        label += 1;
+        let mut y = LevGnx::make(zlev + lev - st[0].0, *ignx, label);
+        if expanded.contains(ignx) { y.expand() }
+        res.push(y);
+      }
+    }
+    j += sz;
+  }
+  // replace the part of the outline that differs
+  let p = (1..o.len().min(res.len())).find(|k|o[*k] != res[*k]).unwrap_or(o.len().min(res.len()));
+  let s = o[p..].iter().rev().zip(res[p..].iter().rev()).take_while(|(y, z)|y == z).count();
+  if o.len() > p + s {
+    chs.push(Change::DeleteBlocks { size: o.len() - p - s, marks: vec![p], data: o[p..o.len() - s].to_vec() });
+  }
+  if res.len() > p + s {
+    chs.push(Change::InsertParts { zlabel: o[0].label(), marks: vec![p], data: res[p..res.len() - s].to_vec() });
+  }
+  for c in chs.iter() { c.redo(o, nodes) }
+  Ok(chs.iter().map(|c|c.to_text()).collect::<Vec<String>>().join("\n"))
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::from_leo_content;
+  use crate::changes::{decode_changes, undo_changes, redo_changes};
+  use std::fs;
+  const ATFILE:&str = "#@+leo-ver=5-thin\n#@+node:f: * @file x.py\n#@@language python\n#@+others\n\
+#@+node:a: ** A\ndef a(): pass\n#@+node:b: ** B\ndef b(): pass\n#@-others\n#@-leo\n";
+  #[test]
+  fn test_external_changes_and_reload() {
+    let (mut o, mut nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="f"><vh>@file x.py</vh><v t="a"><vh>A</vh></v></v><v t="c"><vh>C</vh></v>
+</vnodes><tnodes><t tx="a">def a(): pass
+</t></tnodes></leo_file>"#);
+    let dir = std::env::temp_dir().join("mini_leo_external_test");
+    let _ = fs::create_dir(&dir);
+    let path = dir.join("x.py");
+    fs::write(&path, "x = 1\n").unwrap();
+    let files = external_files(&dir, &o, &nodes);
+    assert_eq!(files.len(), 1);
+    assert!(external_changes(&o, &nodes, &files).is_empty());
+    fs::write(&path, ATFILE).unwrap();
+    let chs = external_changes(&o, &nodes, &files);
+    assert_eq!(chs, vec![ExternalChange {
+      path: path.to_str().unwrap().to_string(), index: Some(1), status: ExternalStatus::Changed }]);
+    nodes[2].b.push_str("# edited\n");
+    assert_eq!(external_changes(&o, &nodes, &files)[0].status, ExternalStatus::Conflict);
+    let report = atfile_conflicts(&o, &nodes, 1, ATFILE);
+    assert!(report.contains("inserted 'B' under '@file x.py'"));
+    reload_atfile(&mut o, &mut nodes, 1, ATFILE).unwrap();
+    let hs:Vec<&str> = o.iter().skip(1).map(|x|nodes[x.ignx() as usize].h.as_str()).collect();
+    assert_eq!(hs, vec!["@file x.py", "A", "B", "C"]);
+    assert_eq!(nodes[2].b, "def a(): pass\n");
+    assert!(o[0].label() >= o.iter().skip(1).map(|x|x.label()).max().unwrap());
+    assert_eq!(atfile_conflicts(&o, &nodes, 1, ATFILE), "");
+    let stamps:Vec<FileStamp> = files.iter().map(|x|x.stamp.clone()).collect();
+    assert_eq!(external_files_from_stamps(&dir, &o, &nodes, &stamps)[0].stamp, files[0].stamp);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(external_changes(&o, &nodes, &files)[0].status, ExternalStatus::Deleted);
+    let _ = fs::remove_dir(&dir);
+  }
+  #[test]
+  fn test_reload_with_clones() {
+    let (mut o, mut nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="f"><vh>@file x.py</vh><v t="a"><vh>A</vh></v></v><v t="c"><vh>C</vh><v t="a"/></v>
+</vnodes><tnodes><t tx="a">def a(): pass
+</t></tnodes></leo_file>"#);
+    o[3].expand();
+    let ldf = "#@+leo-ver=5-thin\n#@+node:f: * @file x.py\n#@+others\n#@+node:a: ** A\ndef a(): return 1\n\
+#@+others\n#@+node:b: *3* B\ndef b(): pass\n#@-others\n#@-others\n#@-leo\n";
+    let (o0, n0) = (o.clone(), nodes.clone());
+    let u = reload_atfile(&mut o, &mut nodes, 1, ldf).unwrap();
+    let hs:Vec<&str> = o.iter().skip(1).map(|x|nodes[x.ignx() as usize].h.as_str()).collect();
+    assert_eq!(hs, vec!["@file x.py", "A", "B", "C", "A", "B"]);
+    assert_eq!(nodes[2].b, "def a(): return 1\n@others\n");
+    assert_eq!(crate::check::check_outline(&o, &nodes), vec![]);
+    assert!(o[1].label() == o0[1].label() && o[4].label() == o0[3].label());
+    let (o1, n1) = (o.clone(), nodes.clone());
+    let chs = decode_changes(&u).unwrap();
+    undo_changes(&mut o, &mut nodes, &chs);
+    assert_eq!((&o, &nodes), (&o0, &n0));
+    redo_changes(&mut o, &mut nodes, &chs);
+    assert_eq!((&o, &nodes), (&o1, &n1));
+    // nothing changes when the file is read again
+    assert_eq!(reload_atfile(&mut o, &mut nodes, 1, ldf).unwrap(), "");
+  }
+  #[test]
+  fn test_reload_atclean() {
+    let (mut o, mut nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="c"><vh>@clean x.py</vh><v t="a"><vh>A</vh></v></v>
+</vnodes><tnodes><t tx="c">@others
+x = 1
+</t><t tx="a">def a(): pass
+</t></tnodes></leo_file>"#);
+    let content = "def a(): return 1\nx = 1\n";
+    assert_eq!(atclean_conflicts(&o, &nodes, 1, "def a(): pass\nx = 1\n"), "");
+    let report = atclean_conflicts(&o, &nodes, 1, content);
+    assert!(report.contains("changed body of 'A'"));
+    assert!(report.contains("+def a(): return 1"));
+    let n0 = nodes.clone();
+    let u = reload_atclean(&o, &mut nodes, 1, content);
+    assert_eq!(nodes[2].b, "def a(): return 1\n");
+    assert_eq!(nodes[1].b, n0[1].b);
+    let chs = decode_changes(&u).unwrap();
+    undo_changes(&mut o, &mut nodes, &chs);
+    assert_eq!(nodes, n0);
+    assert_eq!(reload_atclean(&o, &mut nodes, 1, "def a(): pass\nx = 1\n"), "");
+  }
+}
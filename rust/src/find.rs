@@ -0,0 +1,179 @@
+use crate::model::{VData, Outline, OutlineOps, LevGnxOps, TreeError, update_node,
+                   clone_under_new_node};
+use regex::{Regex, RegexBuilder, NoExpand};
+use std::collections::HashSet;
+
+/// Options for find_all and replace_all
+#[derive(Debug, Clone)]
+pub struct FindOptions {
+  /// pattern is a regular expression, otherwise it is plain text
+  pub regex: bool,
+  /// match only whole words
+  pub whole_word: bool,
+  pub ignore_case: bool,
+  pub search_head: bool,
+  pub search_body: bool,
+  /// index of the node whose subtree is searched, 0 means whole outline
+  pub root: usize
+}
+impl Default for FindOptions {
+  fn default() -> Self {
+    FindOptions {
+      regex: false,
+      whole_word: false,
+      ignore_case: false,
+      search_head: true,
+      search_body: true,
+      root: 0
+    }
+  }
+}
+/// One match found by find_all. Spans are in characters.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FindMatch {
+  /// outline index of the node
+  pub index: usize,
+  /// true if the match is in the headline, otherwise it is in the body
+  pub in_head: bool,
+  pub start: usize,
+  pub end: usize
+}
+/// builds regex for the given pattern and options
+pub fn find_regex(pattern:&str, opts:&FindOptions) -> Result<Regex, TreeError> {
+  let mut pat = if opts.regex { pattern.to_string() } else { regex::escape(pattern) };
+  if opts.whole_word {
+    pat = format!(r"\b(?:{})\b", pat);
+  }
+  RegexBuilder::new(&pat)
+    .case_insensitive(opts.ignore_case)
+    .multi_line(true)
+    .build()
+    .map_err(|e|TreeError(e.to_string()))
+}
+// indices of nodes to search, each vnode only once
+fn search_range(o:&Outline, root:usize) -> Vec<usize> {
+  let (a, b) = if root == 0 { (1, o.len()) } else { (root, root + o.subtree_size(root)) };
+  let mut seen:HashSet<u32> = HashSet::new();
+  (a..b).filter(|i|seen.insert(o[*i].ignx())).collect()
+}
+fn char_spans(re:&Regex, s:&str, index:usize, in_head:bool, res:&mut Vec<FindMatch>) {
+  for m in re.find_iter(s) {
+    if m.start() == m.end() { continue }
+    let start = s[..m.start()].chars().count();
+    let end = start + m.as_str().chars().count();
+    res.push(FindMatch { index, in_head, start, end });
+  }
+}
+/// returns all matches of the pattern in headlines and/or bodies.
+/// Cloned nodes are searched once, at their first position.
+pub fn find_all(o:&Outline, nodes:&Vec<VData>, pattern:&str, opts:&FindOptions)
+    -> Result<Vec<FindMatch>, TreeError> {
+  let re = find_regex(pattern, opts)?;
+  let mut res = Vec::new();
+  for i in search_range(o, opts.root) {
+    let v = &nodes[o[i].ignx() as usize];
+    if opts.search_head { char_spans(&re, &v.h, i, true, &mut res) }
+    if opts.search_body { char_spans(&re, &v.b, i, false, &mut res) }
+  }
+  Ok(res)
+}
+/// replaces all matches of the pattern with repl. If opts.regex is true,
+/// repl may contain group references like $1.
+/// Returns number of changed nodes and undo_info, which is empty if
+/// nothing has changed
+pub fn replace_all(o:&Outline, nodes:&mut Vec<VData>, pattern:&str, repl:&str, opts:&FindOptions)
+    -> Result<(usize, String), TreeError> {
+  let re = find_regex(pattern, opts)?;
+  let mut res:Vec<String> = Vec::new();
+  for i in search_range(o, opts.root) {
+    let mut v = nodes[o[i].ignx() as usize].clone();
+    let rep = |s:&str| if opts.regex {
+        re.replace_all(s, repl).into_owned()
+      } else {
+        re.replace_all(s, NoExpand(repl)).into_owned()
+      };
+    if opts.search_head { v.h = rep(&v.h) }
+    if opts.search_body { v.b = rep(&v.b) }
+    if v != nodes[v.ignx as usize] {
+      if let Some(s) = update_node(nodes, &v) { res.push(s) }
+    }
+  }
+  Ok((res.len(), res.join("\n")))
+}
+/// runs find_all and inserts a new top level node with the given gnx,
+/// whose children are clones of all matching vnodes. Unless flatten is
+/// true, matches inside the subtree of another match are left out, just
+/// like Leo's clone-find-all does; clone-find-all-flattened keeps them.
+/// Returns label of the new node and undo_info, or None if nothing has
+/// been found
+pub fn clone_find_all(o:&mut Outline, nodes:&mut Vec<VData>, pattern:&str, opts:&FindOptions,
+    flatten:bool, gnx:&str) -> Result<Option<(u32, String)>, TreeError> {
+  let mut found:Vec<u32> = Vec::new();
+  let mut end = 0;
+  for m in find_all(o, nodes, pattern, opts)? {
+    let i = m.index;
+    if !flatten && i < end { continue }
+    if i >= end { end = i + o.subtree_size(i) }
+    if !found.contains(&o[i].ignx()) { found.push(o[i].ignx()) }
+  }
+  let h = format!("Found: {}", pattern);
+  Ok(clone_under_new_node(o, nodes, gnx, &h, &found))
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::from_leo_content;
+  use crate::changes::{decode_changes, undo_changes};
+  #[test]
+  fn test_find_and_replace() {
+    let (o, mut nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="a"><vh>Čaj cat</vh><v t="b"><vh>B</vh></v></v>
+<v t="c"><vh>concat</vh><v t="b"/></v>
+</vnodes><tnodes><t tx="b">Cat
+category</t></tnodes></leo_file>"#);
+    let mut opts = FindOptions::default();
+    let m = find_all(&o, &nodes, "cat", &opts).unwrap();
+    assert_eq!(m.len(), 3);
+    assert_eq!(m[0], FindMatch { index: 1, in_head: true, start: 4, end: 7 });
+    opts.ignore_case = true;
+    opts.whole_word = true;
+    let m = find_all(&o, &nodes, "cat", &opts).unwrap();
+    assert_eq!(m.iter().map(|x|x.index).collect::<Vec<_>>(), vec![1, 2]);
+    opts.search_head = false;
+    opts.root = 3;
+    let m = find_all(&o, &nodes, "cat", &opts).unwrap();
+    assert_eq!(m, vec![FindMatch { index: 4, in_head: false, start: 0, end: 3 }]);
+    opts.regex = true;
+    assert!(find_all(&o, &nodes, "(", &opts).is_err());
+
+    let n0 = nodes.clone();
+    let opts = FindOptions { regex: true, ..FindOptions::default() };
+    let (n, s) = replace_all(&o, &mut nodes, "c(at)", "d$1", &opts).unwrap();
+    assert_eq!(n, 3);
+    assert_eq!(nodes[1].h, "Čaj dat");
+    assert_eq!(nodes[2].b, "Cat\ndategory");
+    assert_eq!(nodes[3].h, "condat");
+    let mut o = o;
+    undo_changes(&mut o, &mut nodes, &decode_changes(&s).unwrap());
+    assert_eq!(nodes, n0);
+  }
+  #[test]
+  fn test_clone_find_all() {
+    let (mut o, mut nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="a"><vh>x</vh><v t="b"><vh>x</vh></v></v>
+<v t="c"><vh>y</vh><v t="b"/></v>
+</vnodes><tnodes></tnodes></leo_file>"#);
+    let (o0, n0) = (o.clone(), nodes.clone());
+    let opts = FindOptions::default();
+    let (p, s) = clone_find_all(&mut o, &mut nodes, "x", &opts, false, "f1").unwrap().unwrap();
+    let i = o.label_index(p).unwrap();
+    assert_eq!(nodes[o[i].ignx() as usize].h, "Found: x");
+    assert_eq!(o.children(i), vec![1]);
+    undo_changes(&mut o, &mut nodes, &decode_changes(&s).unwrap());
+    assert_eq!((&o, &nodes), (&o0, &n0));
+    let (p, _) = clone_find_all(&mut o, &mut nodes, "x", &opts, true, "f2").unwrap().unwrap();
+    let i = o.label_index(p).unwrap();
+    assert_eq!(o.children(i), vec![1, 2]);
+    assert!(clone_find_all(&mut o, &mut nodes, "z", &opts, true, "f3").unwrap().is_none());
+  }
+}
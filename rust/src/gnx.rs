@@ -0,0 +1,99 @@
+use crate::model::{VData, Outline, insert_new_node, gnx_index};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Generates new gnx following Leo's scheme `id.YYYYMMDDhhmmss.n`.
+///
+/// The first gnx generated in a given second has no `.n` suffix, the
+/// following ones are numbered 1, 2, ... just like Leo does. Timestamps
+/// are in UTC.
+pub struct GnxGenerator {
+  id: String,
+  stamp: String,
+  n: usize
+}
+impl GnxGenerator {
+  pub fn new(id:&str) -> Self {
+    GnxGenerator { id: id.to_string(), stamp: String::new(), n: 0 }
+  }
+  pub fn id(&self) -> &str { &self.id }
+
+  pub fn set_id(&mut self, id:&str) {
+    self.id = id.to_string();
+    self.stamp.clear();
+    self.n = 0;
+  }
+  /// returns new gnx which is not present in nodes
+  pub fn next(&mut self, nodes:&Vec<VData>) -> String {
+    let index = gnx_index(nodes);
+    self.next_at(now_secs(), |g| index.contains_key(g))
+  }
+  /// returns new gnx for the time given in seconds since the epoch,
+  /// skipping every gnx for which is_used returns true
+  pub fn next_at(&mut self, secs:u64, is_used:impl Fn(&str) -> bool) -> String {
+    let stamp = timestamp(secs);
+    if stamp != self.stamp {
+      self.stamp = stamp;
+      self.n = 0;
+    }
+    loop {
+      let gnx = if self.n == 0 {
+        format!("{}.{}", self.id, self.stamp)
+      } else {
+        format!("{}.{}.{}", self.id, self.stamp, self.n)
+      };
+      self.n += 1;
+      if !is_used(&gnx) { return gnx }
+    }
+  }
+}
+/// returns current time in seconds since the epoch
+pub fn now_secs() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d|d.as_secs())
+    .unwrap_or(0)
+}
+/// formats seconds since the epoch as YYYYMMDDhhmmss (UTC)
+pub fn timestamp(secs:u64) -> String {
+  let days = (secs / 86400) as i64;
+  let t = secs % 86400;
+  // civil date from days since 1970-01-01
+  let z = days + 719468;
+  let era = z.div_euclid(146097);
+  let doe = z - era * 146097;
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let d = doy - (153 * mp + 2) / 5 + 1;
+  let m = if mp < 10 { mp + 3 } else { mp - 9 };
+  let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+  format!("{:04}{:02}{:02}{:02}{:02}{:02}", y, m, d, t / 3600, t / 60 % 60, t % 60)
+}
+/// same as insert_new_node, but the new node gets gnx from the generator
+pub fn insert_generated_node(o:&mut Outline, nodes:&mut Vec<VData>, i:usize, g:&mut GnxGenerator) -> (u32, String) {
+  let gnx = g.next(nodes);
+  insert_new_node(o, nodes, i, &gnx)
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::from_leo_content;
+  #[test]
+  fn test_gnx_generator() {
+    assert_eq!(timestamp(0), "19700101000000");
+    assert_eq!(timestamp(951782400 + 3723), "20000229010203");
+    let mut g = GnxGenerator::new("vitalije");
+    let used = ["vitalije.20000229010203.1"];
+    let t = 951782400 + 3723;
+    assert_eq!(g.next_at(t, |x|used.contains(&x)), "vitalije.20000229010203");
+    assert_eq!(g.next_at(t, |x|used.contains(&x)), "vitalije.20000229010203.2");
+    assert_eq!(g.next_at(t + 1, |x|used.contains(&x)), "vitalije.20000229010204");
+
+    let (mut o, mut nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="a"><vh>A</vh></v><v t="b"><vh>B</vh></v>
+</vnodes><tnodes></tnodes></leo_file>"#);
+    let (_, s) = insert_generated_node(&mut o, &mut nodes, 1, &mut g);
+    assert!(nodes[3].gnx.starts_with("vitalije."));
+    assert!(s.starts_with(&format!("addv:{}\n", nodes[3].gnx)));
+  }
+}
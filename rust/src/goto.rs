@@ -0,0 +1,247 @@
+use crate::model::{VData, Outline, OutlineOps, LevGnxOps, Tree, find_derived_files,
+                   find_clean_files};
+use crate::utils::extract_section_ref;
+use crate::parsing::ldf_parse;
+use crate::atclean::atclean_line_owners;
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// For every line of an external file, the outline index of the node
+/// owning it and the line number in that node's body, starting from 0.
+/// Sentinel lines have no owner.
+pub type LineTable = Vec<Option<(usize, usize)>>;
+
+/// builds line table of the `at-file` node at index fi from the content
+/// of its external file, using line information from ldf_parse.
+/// Nodes in the file are matched to the nodes in the subtree of fi
+/// by their gnx.
+pub fn atfile_line_table(o:&Outline, nodes:&Vec<VData>, fi:usize, content:&str) -> LineTable {
+  let (vnodes, lines, _, _) = ldf_parse(content);
+  let nl:Vec<usize> = content.match_indices('\n').map(|x|x.0).collect();
+  let line_of = |a:usize| match nl.binary_search(&a) { Ok(k) => k, Err(k) => k };
+  let mut res:LineTable = vec![None; nl.len() + 1];
+  // file node -> outline index, matching k-th occurrence of gnx
+  let end = fi + o.subtree_size(fi);
+  let mut seen:HashMap<&str, usize> = HashMap::new();
+  let mut positions:Vec<Option<usize>> = vec![None; vnodes.len()];
+  for (j, (lev, a, b, _, _)) in vnodes.iter().enumerate() {
+    if *lev == 0 { continue }
+    let gnx = &content[*a..*b];
+    let k = seen.entry(gnx).or_insert(0);
+    let mut found = (fi..end).filter(|i|nodes[o[*i].ignx() as usize].gnx == gnx);
+    positions[j] = found.clone().nth(*k).or_else(||found.next());
+    *k += 1;
+  }
+  let mut body_lines:Vec<usize> = vec![0; vnodes.len()];
+  for (ni, a, b, op) in lines {
+    if ni == 0 || ni > vnodes.len() { continue }
+    let (pref, suf) = op.unwrap_or(("", ""));
+    let bl = &mut body_lines[ni - 1];
+    *bl += pref.matches('\n').count();
+    let mut fl = line_of(a.min(content.len()));
+    for c in content[a..b].bytes() {
+      if let (Some(i), None) = (positions[ni - 1], res[fl]) {
+        res[fl] = Some((i, *bl));
+      }
+      if c == b'\n' { fl += 1; *bl += 1; }
+    }
+    *bl += suf.matches('\n').count();
+  }
+  res
+}
+/// builds line table of the `at-clean` node at index fi
+pub fn atclean_line_table(o:&Outline, nodes:&Vec<VData>, fi:usize) -> LineTable {
+  atclean_line_owners(o, nodes, fi).into_iter().map(Some).collect()
+}
+/// returns the node and body line owning the given line (starting
+/// from 1, as in compiler messages). Sentinel lines belong to the
+/// closest preceding line with an owner.
+pub fn goto_global_line(table:&LineTable, line:usize) -> Option<(usize, usize)> {
+  if line == 0 { return None }
+  table.iter().take(line).rev().filter_map(|x|*x).next()
+    .or_else(||table.iter().filter_map(|x|*x).next())
+}
+/// returns external file line (starting from 1) where the given body
+/// line (starting from 0) of the node at outline index i is written.
+/// Lines which are not written themselves are mapped to the start of
+/// their expansion: `@others` to the first line of the first child,
+/// section reference to the first line of the section definition,
+/// including the opening sentinels in `at-file` output. Other lines,
+/// like directives, are mapped to the line after the previous written
+/// line of the same node.
+pub fn external_line(o:&Outline, nodes:&Vec<VData>, table:&LineTable, i:usize, body_line:usize)
+    -> Option<usize> {
+  if let Some(k) = table.iter().position(|x|*x == Some((i, body_line))) {
+    return Some(k + 1);
+  }
+  let line = nodes[o[i].ignx() as usize].b.lines().nth(body_line)?;
+  let start = table.iter()
+    .rposition(|x|matches!(x, Some((j, bl)) if *j == i && *bl < body_line))
+    .map(|k|k + 1)
+    .unwrap_or(0);
+  let is_def = |j:usize| nodes[o[j].ignx() as usize].section_ref().is_some();
+  let (a, b) = if let Some(name) = extract_section_ref(line) {
+    let d = Tree::new(o, nodes).find_section(name, i)?;
+    (d, d + o.subtree_size(d))
+  } else if line.trim_start().starts_with("@others") {
+    (i + 1, i + o.subtree_size(i))
+  } else {
+    return Some(start + 1);
+  };
+  let inside = |j:usize| {
+    if j < a || j >= b { return false }
+    if a > i { return true }
+    // @others skips section definitions and their subtrees
+    let mut k = j;
+    while k > i {
+      if is_def(k) { return false }
+      k = o.parent_index(k);
+    }
+    true
+  };
+  let mut k = start + table[start..].iter()
+    .position(|x|matches!(x, Some((j, _)) if inside(*j)))?;
+  while k > start && table[k - 1].is_none() { k -= 1 }
+  Some(k + 1)
+}
+/// returns index of the closest `at-file` or `at-clean` node containing
+/// the node at index i, which may be the node itself
+pub fn file_node_index(o:&Outline, nodes:&Vec<VData>, i:usize) -> Option<usize> {
+  let is_file = |j:usize| {
+    let h = &nodes[o[j].ignx() as usize].h;
+    h.starts_with("@file ") || h.starts_with("@clean ")
+  };
+  let mut j = i;
+  while j > 0 {
+    if is_file(j) { return Some(j) }
+    j = o.parent_index(j);
+  }
+  None
+}
+/// returns the line table of the `at-file` or `at-clean` node at index fi,
+/// reading `at-file` external files from the folder
+pub fn line_table(o:&Outline, nodes:&Vec<VData>, folder:&Path, fi:usize) -> Option<LineTable> {
+  let h = &nodes[o[fi].ignx() as usize].h;
+  if h.starts_with("@clean ") {
+    return Some(atclean_line_table(o, nodes, fi));
+  }
+  if !h.starts_with("@file ") { return None }
+  let (f, _) = find_derived_files(folder, o, nodes).into_iter().find(|x|x.1 == fi)?;
+  let content = std::fs::read_to_string(f).ok()?.replace("\r\n", "\n");
+  Some(atfile_line_table(o, nodes, fi, &content))
+}
+/// finds file names and line numbers in the output of rustc, gcc and
+/// similar compilers, and in Python tracebacks
+pub fn parse_error_locations(txt:&str) -> Vec<(String, usize)> {
+  lazy_static! {
+    static ref PY:Regex = Regex::new(r#"^\s*File "([^"]+)", line (\d+)"#).unwrap();
+    static ref RUSTC:Regex = Regex::new(r"^\s*--> ([^:\s]+):(\d+)(?::\d+)?").unwrap();
+    static ref GCC:Regex = Regex::new(r"^([^:\s][^:]*):(\d+)(?::\d+)?:").unwrap();
+  }
+  let mut res = Vec::new();
+  for line in txt.lines() {
+    for re in [&*PY, &*RUSTC, &*GCC].iter() {
+      if let Some(c) = re.captures(line) {
+        if let Ok(n) = c[2].parse::<usize>() {
+          res.push((c[1].to_string(), n));
+          break;
+        }
+      }
+    }
+  }
+  res
+}
+/// for every location found in the text returns file name, line and
+/// the node with the body line owning it, if the file is one of the
+/// `at-file` or `at-clean` files in the outline
+pub fn locate_errors(o:&Outline, nodes:&Vec<VData>, folder:&Path, txt:&str)
+    -> Vec<(String, usize, Option<(usize, usize)>)> {
+  let mut files = find_derived_files(folder, o, nodes);
+  files.extend(find_clean_files(folder, o, nodes));
+  let norm = |s:&str| s.replace('\\', "/");
+  let mut tables:HashMap<usize, Option<LineTable>> = HashMap::new();
+  parse_error_locations(txt).into_iter().map(|(f, line)| {
+    let nf = norm(&f);
+    let target = files.iter()
+      .filter(|x|{
+        let p = norm(&x.0);
+        p == nf || p.ends_with(&format!("/{}", nf.trim_start_matches("./")))
+      })
+      .map(|x|x.1)
+      .next();
+    let loc = target.and_then(|fi| {
+      tables.entry(fi)
+        .or_insert_with(||line_table(o, nodes, folder, fi))
+        .as_ref()
+        .and_then(|t|goto_global_line(t, line))
+    });
+    (f, line, loc)
+  }).collect()
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::{from_leo_content, from_derived_file_content};
+  const LDF:&str = "#@+leo-ver=5-thin\n#@+node:a.1: * @file x.py\nimport os\n#@+others\n#@+node:a.2: ** f\ndef f():\n    return 1\n#@-others\nprint(f())\n#@-leo\n";
+  #[test]
+  fn test_atfile_line_table() {
+    // outline built from derived file has no hidden root, so
+    // at-file node is at index 0
+    let (o, nodes) = from_derived_file_content(LDF);
+    let t = atfile_line_table(&o, &nodes, 0, LDF);
+    assert_eq!(goto_global_line(&t, 3), Some((0, 0)));
+    assert_eq!(goto_global_line(&t, 7), Some((1, 1)));
+    assert_eq!(goto_global_line(&t, 9), Some((0, 2)));
+  }
+  #[test]
+  fn test_atclean_and_errors() {
+    let (o, nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="r"><vh>@clean a.py</vh><v t="f"><vh>f</vh></v></v>
+</vnodes><tnodes><t tx="r">import os
+  @others
+x = 1
+</t><t tx="f">def f():
+    pass
+</t></tnodes></leo_file>"#);
+    let t = atclean_line_table(&o, &nodes, 1);
+    assert_eq!(goto_global_line(&t, 3), Some((2, 1)));
+    assert_eq!(goto_global_line(&t, 4), Some((1, 2)));
+    let errs = parse_error_locations(concat!(
+      "Traceback (most recent call last):\n",
+      "  File \"/tmp/a.py\", line 3, in <module>\n",
+      "error[E0425]: cannot find value\n",
+      "  --> src/lib.rs:12:5\n",
+      "foo.c:7:3: error: expected ';'\n"));
+    assert_eq!(errs, vec![("/tmp/a.py".to_string(), 3),
+                          ("src/lib.rs".to_string(), 12),
+                          ("foo.c".to_string(), 7)]);
+    let locs = locate_errors(&o, &nodes, Path::new("/tmp"), "  File \"/tmp/a.py\", line 3\n");
+    assert_eq!(locs[0].2, Some((2, 1)));
+  }
+  #[test]
+  fn test_external_line() {
+    let (o, nodes) = from_derived_file_content(LDF);
+    let t = atfile_line_table(&o, &nodes, 0, LDF);
+    // `@others` maps to its sentinel line
+    assert_eq!(external_line(&o, &nodes, &t, 0, 1), Some(4));
+    assert_eq!(external_line(&o, &nodes, &t, 0, 2), Some(9));
+    assert_eq!(external_line(&o, &nodes, &t, 1, 1), Some(7));
+    let (o, nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="r"><vh>@clean a.py</vh><v t="f"><vh>f</vh></v>
+<v t="s"><vh>&lt;&lt; s &gt;&gt;</vh></v></v>
+</vnodes><tnodes><t tx="r">class A:
+    @others
+&lt;&lt; s &gt;&gt;
+</t><t tx="f">def f(self):
+    pass
+</t><t tx="s">s = 1
+</t></tnodes></leo_file>"#);
+    assert_eq!(file_node_index(&o, &nodes, 2), Some(1));
+    let t = atclean_line_table(&o, &nodes, 1);
+    assert_eq!(external_line(&o, &nodes, &t, 2, 1), Some(3));
+    assert_eq!(external_line(&o, &nodes, &t, 1, 1), Some(2));
+    assert_eq!(external_line(&o, &nodes, &t, 1, 2), Some(4));
+    assert_eq!(external_line(&o, &nodes, &t, 3, 0), Some(4));
+  }
+}
@@ -0,0 +1,124 @@
+use crate::model::{VData, Outline, OutlineOps, LevGnxOps};
+
+/// Hoist stack of an outline view.
+///
+/// Hoisted nodes are kept by their labels, so the stack remains valid
+/// while the outline changes. Entries whose nodes have been deleted are
+/// ignored. Hoisting never changes the outline itself.
+#[derive(Debug, Default, Clone)]
+pub struct Hoist {
+  // (label, true if the hoisted node itself is shown)
+  stack: Vec<(u32, bool)>
+}
+impl Hoist {
+  pub fn new() -> Self { Hoist { stack: Vec::new() } }
+
+  /// hoists node with given label, the view shows this node and its subtree
+  pub fn push(&mut self, label:u32) { self.stack.push((label, true)) }
+
+  /// removes the last hoisted node and returns its label
+  pub fn pop(&mut self) -> Option<u32> { self.stack.pop().map(|x|x.0) }
+
+  pub fn clear(&mut self) { self.stack.clear() }
+
+  /// returns all entries of the stack as (label, show) pairs
+  pub fn entries(&self) -> &[(u32, bool)] { &self.stack }
+
+  /// replaces the whole stack with the given (label, show) pairs
+  pub fn set_entries(&mut self, entries:Vec<(u32, bool)>) { self.stack = entries }
+
+  /// returns index of the currently hoisted node and whether it is shown
+  /// in the view, or None if the view shows the whole outline
+  pub fn top(&self, o:&Outline) -> Option<(usize, bool)> {
+    self.stack.iter().rev()
+      .filter_map(|(label, show)|o.label_index(*label).map(|i|(i, *show)))
+      .next()
+  }
+  /// returns label of the currently hoisted node
+  pub fn hoisted(&self, o:&Outline) -> Option<u32> {
+    self.top(o).map(|(i, _)|o[i].label())
+  }
+  /// returns true if the node at index i is inside the view
+  pub fn contains(&self, o:&Outline, i:usize) -> bool {
+    match self.top(o) {
+      Some((h, show)) => (if show { h } else { h + 1 }) <= i && i < h + o.subtree_size(h),
+      None => i > 0 && i < o.len()
+    }
+  }
+  /// returns indices of all nodes visible in the view. The hoisted node
+  /// is always treated as expanded.
+  pub fn visible_indices(&self, o:&Outline) -> Vec<usize> {
+    match self.top(o) {
+      Some((h, show)) => {
+        let mut res = if show { vec![h] } else { Vec::new() };
+        res.extend(visible_in(o, h + 1, h + o.subtree_size(h)));
+        res
+      },
+      None => o.visible_indices()
+    }
+  }
+  /// selects the chapter with the given name. Chapter `main` is the whole
+  /// outline, other chapters show the children of the `@chapter name`
+  /// node. Returns false if there is no such chapter.
+  pub fn select_chapter(&mut self, o:&Outline, nodes:&Vec<VData>, name:&str) -> bool {
+    if name == "main" {
+      self.clear();
+      return true;
+    }
+    match chapters(o, nodes).into_iter().find(|x|x.0 == name) {
+      Some((_, i)) => {
+        self.stack = vec![(o[i].label(), false)];
+        true
+      },
+      None => false
+    }
+  }
+}
+fn visible_in(o:&Outline, a:usize, b:usize) -> Vec<usize> {
+  let mut res = Vec::new();
+  let mut skip_level = 255u8;
+  for i in a..b {
+    if o[i].level() > skip_level { continue }
+    skip_level = if o[i].is_expanded() { 255u8 } else { o[i].level() };
+    res.push(i);
+  }
+  res
+}
+/// returns names and indices of chapters, that is of children of the
+/// first `@chapters` node whose headlines start with `@chapter `
+pub fn chapters(o:&Outline, nodes:&Vec<VData>) -> Vec<(String, usize)> {
+  let h = |i:usize| nodes[o[i].ignx() as usize].h.as_str();
+  let ci = match (1..o.len()).find(|i|h(*i).starts_with("@chapters")) {
+    Some(i) => i,
+    None => return Vec::new()
+  };
+  let lev = o[ci].level() + 1;
+  (ci + 1..ci + o.subtree_size(ci))
+    .filter(|i|o[*i].level() == lev && h(*i).starts_with("@chapter "))
+    .map(|i|(h(i)[9..].trim().to_string(), i))
+    .collect()
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::from_leo_content;
+  #[test]
+  fn test_hoist_and_chapters() {
+    let (mut o, nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="a"><vh>A</vh><v t="b"><vh>B</vh><v t="c"><vh>C</vh></v></v></v>
+<v t="d"><vh>@chapters</vh><v t="e"><vh>@chapter notes</vh><v t="f"><vh>F</vh></v></v></v>
+</vnodes><tnodes></tnodes></leo_file>"#);
+    o[4].expand();
+    let mut h = Hoist::new();
+    assert_eq!(h.visible_indices(&o), vec![1, 4, 5]);
+    h.push(o[2].label());
+    assert_eq!(h.visible_indices(&o), vec![2, 3]);
+    assert!(h.contains(&o, 3) && !h.contains(&o, 1));
+    assert_eq!(chapters(&o, &nodes), vec![("notes".to_string(), 5)]);
+    assert!(h.select_chapter(&o, &nodes, "notes"));
+    assert_eq!(h.visible_indices(&o), vec![6]);
+    assert!(!h.select_chapter(&o, &nodes, "other"));
+    assert!(h.select_chapter(&o, &nodes, "main"));
+    assert_eq!(h.hoisted(&o), None);
+  }
+}
@@ -0,0 +1,258 @@
+use crate::model::VData;
+use crate::changes::{Change, decode_changes};
+use crate::utils::{FNV_OFFSET, fnv1a};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::{fs, io, path::Path};
+
+/// Inverted index of words in headlines and bodies.
+///
+/// Words are lowercased runs of alphanumeric characters. For every word
+/// index keeps the ignx of vnodes containing it and the word positions,
+/// which are needed for phrase queries. Headline and body positions
+/// are separated by a gap so that phrases never span both.
+///
+/// Each indexed vnode has a hash of its gnx, headline and body, so the
+/// index can be cached on disk and brought up to date by reindexing
+/// only vnodes that have changed since.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+  words: BTreeMap<String, BTreeMap<u32, Vec<u32>>>,
+  docs: HashMap<u32, (u64, Vec<String>)>
+}
+pub const INDEX_HEADER:&str = "#mini_leo-index:1";
+
+/// splits text into lowercase words
+pub fn tokenize(s:&str) -> Vec<String> {
+  s.split(|c:char|!c.is_alphanumeric())
+   .filter(|w|!w.is_empty())
+   .map(|w|w.to_lowercase())
+   .collect()
+}
+/// FNV-1a hash of the vnode content, stable between runs
+fn content_hash(v:&VData) -> u64 {
+  [&v.gnx, &v.h, &v.b].iter().fold(FNV_OFFSET, |h, part|fnv1a(fnv1a(h, part.as_bytes()), &[0]))
+}
+impl SearchIndex {
+  pub fn new() -> Self { SearchIndex::default() }
+
+  /// builds index of all vnodes except the hidden root
+  pub fn build(nodes:&Vec<VData>) -> Self {
+    let mut res = SearchIndex::new();
+    res.refresh(nodes);
+    res
+  }
+  /// number of indexed vnodes
+  pub fn len(&self) -> usize { self.docs.len() }
+
+  pub fn is_empty(&self) -> bool { self.docs.is_empty() }
+
+  fn insert(&mut self, ignx:u32, hash:u64, toks:Vec<String>) {
+    for (i, w) in toks.iter().enumerate() {
+      if w.is_empty() { continue }
+      self.words
+        .entry(w.clone())
+        .or_insert_with(BTreeMap::new)
+        .entry(ignx)
+        .or_insert_with(Vec::new)
+        .push(i as u32);
+    }
+    self.docs.insert(ignx, (hash, toks));
+  }
+  /// removes vnode from the index
+  pub fn remove(&mut self, ignx:u32) {
+    if let Some((_, toks)) = self.docs.remove(&ignx) {
+      for w in toks.iter().collect::<HashSet<_>>() {
+        if let Some(m) = self.words.get_mut(w) {
+          m.remove(&ignx);
+          if m.is_empty() { self.words.remove(w); }
+        }
+      }
+    }
+  }
+  /// indexes v again if its content has changed.
+  /// Returns true if v has been reindexed
+  pub fn update(&mut self, v:&VData) -> bool {
+    let hash = content_hash(v);
+    if self.docs.get(&v.ignx).map(|x|x.0) == Some(hash) { return false }
+    self.remove(v.ignx);
+    let mut toks = tokenize(&v.h);
+    // empty token separates headline from body
+    toks.push(String::new());
+    toks.extend(tokenize(&v.b));
+    self.insert(v.ignx, hash, toks);
+    true
+  }
+  /// brings index up to date with nodes, returns number of reindexed vnodes
+  pub fn refresh(&mut self, nodes:&Vec<VData>) -> usize {
+    let n = nodes.len() as u32;
+    let gone:Vec<u32> = self.docs.keys().filter(|i|**i >= n || **i == 0).copied().collect();
+    for i in gone { self.remove(i) }
+    nodes.iter().skip(1).filter(|v|self.update(v)).count()
+  }
+  /// updates index after the given change has been done or undone
+  pub fn apply_change(&mut self, nodes:&Vec<VData>, change:&str) {
+    let chs = match decode_changes(change) {
+      Ok(chs) => chs,
+      Err(_) => { self.refresh(nodes); return }
+    };
+    let n = nodes.len() as u32;
+    let gone:Vec<u32> = self.docs.keys().filter(|i|**i >= n).copied().collect();
+    for i in gone { self.remove(i) }
+    let mut added = false;
+    for c in chs.iter() {
+      match c {
+        Change::UpdateVNode { new, .. } => if let Some(v) = nodes.get(new.ignx as usize) {
+          self.update(v);
+        },
+        Change::AddVNode { .. } => added = true,
+        _ => ()
+      }
+    }
+    if added {
+      let k = self.docs.keys().max().copied().unwrap_or(0) as usize;
+      for v in nodes.iter().skip(k + 1) { self.update(v); }
+    }
+  }
+  /// returns ignx of vnodes containing the word
+  fn word(&self, w:&str) -> HashSet<u32> {
+    self.words.get(w).map(|m|m.keys().copied().collect()).unwrap_or_default()
+  }
+  /// returns ignx of vnodes containing a word starting with prefix
+  fn prefix(&self, p:&str) -> HashSet<u32> {
+    self.words
+      .range(p.to_string()..)
+      .take_while(|x|x.0.starts_with(p))
+      .flat_map(|x|x.1.keys().copied())
+      .collect()
+  }
+  /// returns ignx of vnodes containing the words one after another
+  fn phrase(&self, ws:&[String]) -> HashSet<u32> {
+    let first = match ws.first().and_then(|w|self.words.get(w)) {
+      Some(m) => m,
+      None => return HashSet::new()
+    };
+    first.iter()
+      .filter(|(ignx, poss)| poss.iter().any(|p| {
+        ws.iter().enumerate().skip(1).all(|(k, w)| {
+          self.words.get(w)
+            .and_then(|m|m.get(ignx))
+            .map(|v|v.binary_search(&(p + k as u32)).is_ok())
+            .unwrap_or(false)
+        })
+      }))
+      .map(|x|*x.0)
+      .collect()
+  }
+  /// returns sorted ignx of vnodes matching all terms of the query.
+  /// Term `word*` matches words starting with `word`, and text between
+  /// double quotes matches a phrase.
+  pub fn search(&self, query:&str) -> Vec<u32> {
+    let mut res:Option<HashSet<u32>> = None;
+    for (k, part) in query.split('"').enumerate() {
+      let mut sets:Vec<HashSet<u32>> = Vec::new();
+      if k % 2 == 1 {
+        let ws = tokenize(part);
+        if !ws.is_empty() { sets.push(self.phrase(&ws)) }
+      } else {
+        for t in part.split_whitespace() {
+          let pref = t.ends_with('*');
+          for w in tokenize(t) {
+            sets.push(if pref { self.prefix(&w) } else { self.word(&w) });
+          }
+        }
+      }
+      for s in sets {
+        res = Some(match res {
+          Some(r) => r.intersection(&s).copied().collect(),
+          None => s
+        });
+      }
+    }
+    let mut res:Vec<u32> = res.unwrap_or_default().into_iter().collect();
+    res.sort();
+    res
+  }
+  /// writes index to the given file. Vnodes are stored by gnx.
+  pub fn save(&self, nodes:&Vec<VData>, fname:&Path) -> io::Result<()> {
+    let mut buf = String::from(INDEX_HEADER);
+    buf.push('\n');
+    let mut ks:Vec<&u32> = self.docs.keys().collect();
+    ks.sort();
+    for i in ks {
+      let (hash, toks) = &self.docs[i];
+      let gnx = match nodes.get(*i as usize) { Some(v) => &v.gnx, None => continue };
+      buf.push_str(gnx);
+      buf.push('\t');
+      buf.push_str(&format!("{:016x}", hash));
+      buf.push('\t');
+      buf.push_str(&toks.join(" "));
+      buf.push('\n');
+    }
+    fs::write(fname, buf)
+  }
+  /// reads index from the given file and refreshes it with nodes.
+  /// Returns index and the number of reindexed vnodes
+  pub fn load(nodes:&Vec<VData>, fname:&Path) -> io::Result<(Self, usize)> {
+    let txt = fs::read_to_string(fname)?;
+    let mut lines = txt.lines();
+    if lines.next() != Some(INDEX_HEADER) {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "not a mini_leo index"));
+    }
+    let gnxs:HashMap<&str, u32> = nodes.iter().enumerate().skip(1)
+      .map(|(i, v)|(v.gnx.as_str(), i as u32)).collect();
+    let mut res = SearchIndex::new();
+    for line in lines {
+      let mut it = line.splitn(3, '\t');
+      let (gnx, hash, toks) = match (it.next(), it.next(), it.next()) {
+        (Some(a), Some(b), Some(c)) => (a, b, c),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed index line"))
+      };
+      let hash = u64::from_str_radix(hash, 16)
+        .map_err(|e|io::Error::new(io::ErrorKind::InvalidData, e))?;
+      if let Some(ignx) = gnxs.get(gnx) {
+        let toks:Vec<String> = toks.split(' ').map(|x|x.to_string()).collect();
+        res.insert(*ignx, hash, toks);
+      }
+    }
+    let n = res.refresh(nodes);
+    Ok((res, n))
+  }
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::from_leo_content;
+  use crate::model::{insert_new_node, update_node};
+  #[test]
+  fn test_search_index() {
+    let (mut o, mut nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="a"><vh>Quick brown</vh></v>
+<v t="b"><vh>Fox</vh></v>
+</vnodes><tnodes><t tx="b">the quick brown fox jumps</t></tnodes></leo_file>"#);
+    let mut ix = SearchIndex::build(&nodes);
+    assert_eq!(ix.len(), 2);
+    assert_eq!(ix.search("quick"), vec![1, 2]);
+    assert_eq!(ix.search("qui* fox"), vec![2]);
+    assert_eq!(ix.search("\"brown fox\""), vec![2]);
+    // phrases don't span headline and body
+    assert_eq!(ix.search("\"fox the\""), Vec::<u32>::new());
+
+    let (_, s) = insert_new_node(&mut o, &mut nodes, 1, "c");
+    ix.apply_change(&nodes, &s);
+    assert_eq!(ix.len(), 3);
+    let mut v = nodes[3].clone();
+    v.h.push_str("Lazy dog");
+    let s = update_node(&mut nodes, &v).unwrap();
+    ix.apply_change(&nodes, &s);
+    assert_eq!(ix.search("lazy"), vec![3]);
+
+    let f = std::env::temp_dir().join("mini_leo_test.idx");
+    ix.save(&nodes, &f).unwrap();
+    nodes[1].h = "Slow".to_string();
+    let (ix2, n) = SearchIndex::load(&nodes, &f).unwrap();
+    let _ = fs::remove_file(&f);
+    assert_eq!(n, 1);
+    assert_eq!(ix2.search("quick"), vec![2]);
+    assert_eq!(ix2.search("dog"), vec![3]);
+  }
+}
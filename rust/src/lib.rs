@@ -6,14 +6,78 @@ mod utils;
 mod parsing;
 #[path="atclean.rs"]
 mod atclean;
-use std::collections::HashMap;
+#[path="changes.rs"]
+mod changes;
+#[path="transaction.rs"]
+mod transaction;
+#[path="events.rs"]
+mod events;
+#[path="writing.rs"]
+mod writing;
+#[path="clipboard.rs"]
+mod clipboard;
+#[path="gnx.rs"]
+mod gnx;
+#[path="hoist.rs"]
+mod hoist;
+#[path="marks.rs"]
+mod marks;
+#[path="find.rs"]
+mod find;
+#[path="index.rs"]
+mod index;
+#[path="sections.rs"]
+mod sections;
+#[path="goto.rs"]
+mod goto;
+#[path="diff.rs"]
+mod diff;
+#[path="merge.rs"]
+mod merge;
+#[path="check.rs"]
+mod check;
+#[path="compact.rs"]
+mod compact;
+#[path="snapshot.rs"]
+mod snapshot;
+#[path="session.rs"]
+mod session;
+#[path="external.rs"]
+mod external;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Mutex};
 pub use parsing::{ldf_parse,from_derived_file_content, from_derived_file,
-                  from_leo_file, from_leo_content, load_with_external_files,
+                  from_leo_file, from_leo_content, try_from_leo_content, load_with_external_files,
+                  load_document_with_external_files,
                   /*from_zip_archive,*/
                   };
 pub use atclean::{atclean_to_string, update_atclean_tree};
+use parsing::read_file_as_in_linux;
 pub use utils::{b64int, b64str, b64write, partition};
+pub use changes::{Change, ChangeError, CHANGES_VERSION, decode_changes, encode_changes,
+                  changes_to_bytes, changes_from_bytes, undo_changes, redo_changes,
+                  change_epoch, with_epoch, text_to_bytes, bytes_to_text};
+pub use transaction::Transaction;
+pub use events::{OutlineEvent, Observers, change_events};
+pub use writing::{to_leo_content, to_leo_document, LeoExtras};
+pub use clipboard::{copy_outline, paste_outline};
+pub use gnx::{GnxGenerator, insert_generated_node, now_secs};
+pub use hoist::{Hoist, chapters};
+pub use find::{FindOptions, FindMatch, find_all, replace_all, clone_find_all};
+pub use index::{SearchIndex, tokenize};
+pub use sections::{SectionRef, SectionReport, analyze_sections, atclean_to_string_checked};
+pub use goto::{LineTable, line_table, goto_global_line, parse_error_locations, locate_errors,
+               external_line, file_node_index};
+pub use diff::{OutlineDiff, NodeDiff, DiffKind, DiffLine, diff_outlines, diff_lines};
+pub use merge::{MergeResult, MergeConflict, ConflictKind, merge_outlines, merge_lines};
+pub use check::{Problem, check_outline, repair_outline};
+pub use compact::{Remap, compact, remap_changes, to_current_epoch};
+pub use snapshot::{Snapshot, FileStamp, external_file_stamps};
+pub use session::{Session, ArchivedPosition, archive_position, resolve_position, session_file};
+pub use external::{ExternalFile, ExternalChange, ExternalStatus, external_files, external_changes,
+                   external_files_from_stamps, subtree_hash, atfile_conflicts, reload_atfile,
+                   atclean_conflicts, reload_atclean};
+pub use marks::{MARKED, is_marked, set_mark, marked_indices, next_marked, prev_marked, clone_marked};
 pub use model::{VData, Outline, OutlineOps, LevGnx, LevGnxOps, gnx_index,
                 find_derived_files, find_edit_files,
                 find_auto_files, find_clean_files,
@@ -22,20 +86,22 @@ pub use model::{VData, Outline, OutlineOps, LevGnx, LevGnxOps, gnx_index,
                 break_link, undo_delete_blocks, redo_delete_blocks,
                 undo_shift_blocks, redo_shift_blocks,
                 undo_set_nodes, redo_set_nodes,
-                insert_new_node, redo_insert_new_node,
-                undo_update_node, redo_update_node,
+                insert_new_node, redo_insert_new_node, InsertPlace, insert_node_at,
+                update_node, undo_update_node, redo_update_node, vnode_updates,
                 move_node_right, move_node_left, move_node_up, move_node_down,
                 clone_node, delete_node,
+                SortKey, sort_children, reverse_children, promote, demote, move_node_to,
                 valid_operations,
                 extract_subtree, INDENT};
 use pyo3::prelude::*;
 use pyo3::PyIterProtocol;
 use pyo3::exceptions::{PyValueError, PyIOError};
+use pyo3::types::{PyBytes, PyDict};
 
 //use pyo3::{wrap_pyfunction};
 //use pyo3::type_object::PyTypeObject;
 //use xml::reader::{ParserConfig, XmlEvent};
-use std::path::{Path};
+use std::path::{Path, PathBuf};
 #[macro_use]
 extern crate lazy_static;
 
@@ -46,6 +112,63 @@ pub fn a_function_from_rust() -> PyResult<i32> {
 pub struct Tree {
   outline: Outline,
   nodes: Vec<VData>,
+  transaction: Option<Transaction>,
+  rolled_back: bool,
+  gnxgen: GnxGenerator,
+  hoist: Hoist,
+  index: Option<SearchIndex>,
+  /// folder of the Leo document, used for resolving external files
+  folder: Option<PathBuf>,
+  /// remap tables of all compactions, their number is the current epoch
+  remaps: Vec<Remap>,
+  /// external files read when the outline was loaded
+  externals: Vec<ExternalFile>,
+  /// parts of the Leo document that are written back on save
+  extras: LeoExtras,
+}
+impl Tree {
+  fn new(outline:Outline, nodes:Vec<VData>) -> Self {
+    Tree { outline, nodes, transaction: None, rolled_back: false,
+           gnxgen: GnxGenerator::new("mini_leo"), hoist: Hoist::new(),
+           index: None, folder: None, remaps: Vec::new(), externals: Vec::new(),
+           extras: LeoExtras::default() }
+  }
+  /// sets folder of the outline to the folder containing fname
+  fn with_folder(mut self, fname:&str) -> Self {
+    self.folder = std::fs::canonicalize(fname).ok()
+      .and_then(|p|p.parent().map(|x|x.to_path_buf()));
+    self
+  }
+  /// records the change made by an operation in the open transaction.
+  /// If the operation has failed (res is None), the open transaction
+  /// is rolled back. Returns the change marked with the current epoch.
+  fn track(&mut self, res:Option<String>) -> Option<String> {
+    match res {
+      Some(s) => self.record(Some(s)),
+      None => { self.abort(); None }
+    }
+  }
+  /// records the change made by an operation in the open transaction.
+  /// If the transaction can't take the change, it is rolled back and
+  /// None is returned. Returns the change marked with the current epoch.
+  fn record(&mut self, res:Option<String>) -> Option<String> {
+    if let (Some(s), Some(tr)) = (&res, self.transaction.as_mut()) {
+      if tr.record(s).is_err() { self.abort(); return None }
+    }
+    res.map(|s|self.stamp(&s))
+  }
+  /// rolls back the open transaction, if there is one
+  fn abort(&mut self) {
+    if let Some(tr) = self.transaction.take() {
+      tr.rollback(&mut self.outline, &mut self.nodes);
+      self.rolled_back = true;
+    }
+  }
+  /// marks the change records with the current epoch, so that undo
+  /// and redo can tell if they were made before a compaction
+  fn stamp(&self, s:&str) -> String {
+    with_epoch(self.remaps.len(), s)
+  }
 }
 #[pyclass]
 struct TreeIterator {
@@ -78,11 +201,227 @@ impl PyIterProtocol for TreeIterator {
 }
 lazy_static! {
   static ref TREES:Mutex<Box<HashMap<usize,Tree>>> = Mutex::new(Box::new(HashMap::new()));
+  static ref LISTENERS:Mutex<HashMap<usize, Observers<PyObject>>> = Mutex::new(HashMap::new());
+}
+/// inserts new node with given gnx at place relative to the node p
+fn insert_at(py:Python, tid:usize, p:u32, gnx:&str, place:InsertPlace) -> Option<(u32, String)> {
+  let res = TREES
+    .lock()
+    .unwrap()
+    .get_mut(&tid)
+    .and_then(|t|{
+      let i = t.outline.label_index(p).unwrap_or(0);
+      if i == 0 { t.track(None); return None }
+      let (p, s) = insert_node_at(&mut t.outline, &mut t.nodes, i, gnx, place);
+      t.track(Some(s)).map(|s|(p, s))
+    });
+  notify(py, tid, res.as_ref().map(|x|x.1.as_str()), false);
+  res
+}
+/// returns index, path and content of the at-file or at-clean node p,
+/// and whether it is an at-clean node
+fn read_atfile(t:&Tree, p:u32) -> PyResult<(usize, String, String, bool)> {
+  let i = t.outline.label_index(p).ok_or_else(||PyValueError::new_err("no such node"))?;
+  let folder = t.folder.as_ref().ok_or_else(||PyValueError::new_err("outline has no folder"))?;
+  let found = |fs:Vec<(String, usize)>|fs.into_iter().find(|x|x.1 == i).map(|x|x.0);
+  let (f, clean) = match found(find_derived_files(folder, &t.outline, &t.nodes)) {
+    Some(f) => (f, false),
+    None => match found(find_clean_files(folder, &t.outline, &t.nodes)) {
+      Some(f) => (f, true),
+      None => return Err(PyValueError::new_err("not an at-file or at-clean node"))
+    }
+  };
+  let cont = read_file_as_in_linux(Path::new(&f)).map_err(|e|PyIOError::new_err(e.to_string()))?;
+  Ok((i, f, cont, clean))
+}
+/// replaces the subtree of the at-file node at index i with the file
+/// content, or updates bodies of the at-clean node, and takes a new
+/// stamp of the file f
+fn reload_at(t:&mut Tree, i:usize, f:&str, cont:&str, clean:bool) -> PyResult<String> {
+  let ignx = t.outline[i].ignx();
+  let u = if clean {
+    reload_atclean(&t.outline, &mut t.nodes, i, cont)
+  } else {
+    match reload_atfile(&mut t.outline, &mut t.nodes, i, cont) {
+      Ok(u) => u,
+      Err(e) => { t.track(None); return Err(PyValueError::new_err(e)) }
+    }
+  };
+  let u = if u.is_empty() { u } else { t.record(Some(u)).unwrap_or_default() };
+  // changed clones before the node may have moved it
+  let i = t.outline.find(ignx).unwrap_or(i);
+  if let Some(x) = ExternalFile::of(f, &t.outline, &t.nodes, i) {
+    match t.externals.iter().position(|y|y.gnx == x.gnx) {
+      Some(k) => t.externals[k] = x,
+      None => t.externals.push(x)
+    }
+  }
+  Ok(u)
+}
+/// marks, unmarks (on is Some(true/false)) or toggles (on is None)
+/// the node p
+fn mark_node(py:Python, tid:usize, p:u32, on:Option<bool>) -> Option<String> {
+  let res = TREES
+    .lock()
+    .unwrap()
+    .get_mut(&tid)
+    .and_then(|t|{
+      let i = t.outline.label_index(p)?;
+      let ignx = t.outline[i].ignx();
+      let on = on.unwrap_or_else(||!is_marked(&t.nodes[ignx as usize]));
+      let res = set_mark(&mut t.nodes, ignx, on);
+      t.record(res)
+    });
+  notify(py, tid, res.as_deref(), false);
+  res
+}
+/// reads find options from the python dict
+fn find_options(o:&Outline, options:Option<&PyDict>) -> PyResult<FindOptions> {
+  let mut opts = FindOptions::default();
+  if let Some(d) = options {
+    let flag = |k:&str, v:bool| -> PyResult<bool> {
+      match d.get_item(k) {
+        Some(x) => x.extract(),
+        None => Ok(v)
+      }
+    };
+    opts.regex = flag("regex", false)?;
+    opts.whole_word = flag("whole_word", false)?;
+    opts.ignore_case = flag("ignore_case", false)?;
+    opts.search_head = flag("head", true)?;
+    opts.search_body = flag("body", true)?;
+    if let Some(x) = d.get_item("root") {
+      let p:u32 = x.extract()?;
+      opts.root = o.label_index(p).ok_or_else(||PyValueError::new_err("no such node"))?;
+    }
+  }
+  Ok(opts)
+}
+/// keeps the search index of the outline tid in sync with its nodes.
+/// If change is None, the whole index is refreshed.
+fn update_index(tid:usize, change:Option<&str>) {
+  if let Some(t) = TREES.lock().unwrap().get_mut(&tid) {
+    if let Some(ix) = t.index.as_mut() {
+      match change {
+        Some(ch) => ix.apply_change(&t.nodes, ch),
+        None => { ix.refresh(&t.nodes); }
+      }
+    }
+  }
+}
+/// runs f with the diff of outlines tid_a and tid_b
+fn with_diff<T>(tid_a:usize, tid_b:usize, f:impl Fn(OutlineDiff) -> T) -> PyResult<T> {
+  let m = TREES.lock().unwrap();
+  match (m.get(&tid_a), m.get(&tid_b)) {
+    (Some(a), Some(b)) => Ok(f(diff_outlines(&a.outline, &a.nodes, &b.outline, &b.nodes))),
+    _ => Err(PyValueError::new_err("unknown tree id"))
+  }
+}
+/// sends events describing the given change to all listeners of the
+/// outline tid. If change is None (operation has failed) or empty,
+/// listeners are notified only if an open transaction has been rolled
+/// back.
+///
+/// Must be called after TREES lock has been released, because
+/// listeners may call functions of this module.
+fn notify(py:Python, tid:usize, change:Option<&str>, undo:bool) {
+  let events = match change.filter(|ch|!ch.is_empty()) {
+    Some(ch) => { update_index(tid, Some(ch)); change_events(ch, undo) },
+    None => {
+      let reset = TREES
+        .lock()
+        .unwrap()
+        .get_mut(&tid)
+        .map(|t|std::mem::replace(&mut t.rolled_back, false))
+        .unwrap_or(false);
+      if !reset { return }
+      vec![OutlineEvent::Reset]
+    }
+  };
+  notify_events(py, tid, events);
+}
+/// calls every listener of the outline tid with the list of events,
+/// each event is a tuple (name, args...)
+fn notify_events(py:Python, tid:usize, events:Vec<OutlineEvent>) {
+  if events.is_empty() { return }
+  if events.contains(&OutlineEvent::Reset) { update_index(tid, None) }
+  let cbs:Vec<PyObject> = match LISTENERS.lock().unwrap().get(&tid) {
+    Some(obs) => obs.iter().map(|f|f.clone_ref(py)).collect(),
+    None => return
+  };
+  if cbs.is_empty() { return }
+  let evs:Vec<PyObject> = events
+    .iter()
+    .map(|e| match e {
+      OutlineEvent::Inserted { index, count } |
+      OutlineEvent::Removed { index, count } |
+      OutlineEvent::Changed { index, count } => (e.name(), *index, *count).into_py(py),
+      OutlineEvent::HeadChanged(i) |
+      OutlineEvent::BodyChanged(i) |
+      OutlineEvent::FlagsChanged(i) => (e.name(), *i).into_py(py),
+      OutlineEvent::Expanded(i) |
+      OutlineEvent::Collapsed(i) => (e.name(), *i).into_py(py),
+      OutlineEvent::Reset => (e.name(),).into_py(py)
+    })
+    .collect();
+  for cb in cbs {
+    if let Err(e) = cb.call1(py, (tid, evs.clone())) {
+      e.print(py);
+    }
+  }
+}
+/// undoes (in reverse order) or redoes the change records. Records
+/// made before a compaction are remapped first. They are applied to a
+/// copy of the outline and nodes, which replaces the tree only if all
+/// of them fit, so a rejected change leaves the tree untouched.
+///
+/// Returns the records as they were applied, in the current epoch.
+fn apply_changes(t:&mut Tree, data:&str, undo:bool) -> PyResult<String> {
+  let data = to_current_epoch(data, &t.remaps).map_err(|e|PyValueError::new_err(e.to_string()))?;
+  let chs = decode_changes(&data).map_err(|e|PyValueError::new_err(e.to_string()))?;
+  let (mut o, mut nodes) = (t.outline.clone(), t.nodes.clone());
+  let apply = |x:&Change| -> PyResult<()> {
+    if !x.fits(&o, &nodes) {
+      return Err(PyValueError::new_err("change doesn't fit the outline"));
+    }
+    if undo { x.undo(&mut o, &mut nodes) } else { x.redo(&mut o, &mut nodes) }
+    Ok(())
+  };
+  if undo {
+    chs.iter().rev().try_for_each(apply)?;
+  } else {
+    chs.iter().try_for_each(apply)?;
+  }
+  t.outline = o;
+  t.nodes = nodes;
+  Ok(data)
 }
 #[pymodule]
 fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   /// creates outline from str
   #[pyfn(m)]
+  #[pyo3(name="add_listener", text_signature="(tid, callback)")]
+  /// Registers callback to be called after every operation that
+  /// changes the outline identified by tid, including undo and redo.
+  /// Callback is called as callback(tid, events) where events is a
+  /// list of tuples:
+  ///   ("inserted", index, count), ("removed", index, count),
+  ///   ("changed", index, count), ("head", ignx), ("body", ignx),
+  ///   ("flags", ignx), ("expanded", index), ("collapsed", index),
+  ///   ("reset",)
+  /// Events should be applied in the given order.
+  ///
+  /// Returns id of the listener which can be used to remove it.
+  ///
+  fn add_listener(_py: Python, tid:usize, callback:PyObject) -> usize {
+    LISTENERS
+      .lock()
+      .unwrap()
+      .entry(tid)
+      .or_insert_with(Observers::new)
+      .add(callback)
+  }
+  #[pyfn(m)]
   #[pyo3(name="at_files")]
   fn at_files(_py:Python, tid:usize, folder:&str) -> PyResult<Vec<(String, usize)>> {
     match TREES.lock().unwrap().get(&tid).map(|t|{
@@ -103,6 +442,27 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
     }
   }
   #[pyfn(m)]
+  #[pyo3(name="begin_transaction", text_signature="(tid)")]
+  /// Starts a transaction on the outline identified by tid.
+  /// All operations performed until commit or rollback are
+  /// combined into one undoable change. If any of them fails
+  /// the transaction is rolled back.
+  ///
+  /// Raises ValueError if there is no such outline or if it
+  /// already has an open transaction.
+  ///
+  fn begin_transaction(_py: Python, tid:usize) -> PyResult<()> {
+    match TREES.lock().unwrap().get_mut(&tid) {
+      Some(t) if t.transaction.is_some() =>
+        Err(PyValueError::new_err("transaction is already open")),
+      Some(t) => {
+        t.transaction = Some(Transaction::begin(&t.outline, &t.nodes));
+        Ok(())
+      },
+      None => Err(PyValueError::new_err("unknown tree id"))
+    }
+  }
+  #[pyfn(m)]
   #[pyo3(name="check_tree", text_signature="(tid)")]
   /// Returns None if the given tree is valid.
   /// In case an error has been found, throws ValueError
@@ -130,6 +490,43 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
     Ok(())
   }
   #[pyfn(m)]
+  #[pyo3(name="check_outline", text_signature="(tid)")]
+  /// Checks the outline identified by tid and returns list of
+  /// messages describing every problem found. Unlike check_tree,
+  /// it doesn't stop at the first problem and it also checks vnodes,
+  /// their gnx and ignx, and clone cycles.
+  ///
+  /// Raises ValueError if the outline is missing
+  ///
+  fn pycheck_outline(_py: Python, tid:usize) -> PyResult<Vec<String>> {
+    match TREES.lock().unwrap().get(&tid) {
+      Some(t) => Ok(check_outline(&t.outline, &t.nodes).iter().map(|p|p.to_string()).collect()),
+      None => Err(PyValueError::new_err("unknown tree id"))
+    }
+  }
+  #[pyfn(m)]
+  #[pyo3(name="repair_outline", text_signature="(tid)")]
+  /// Fixes the problems in the outline identified by tid which can be
+  /// fixed without losing data: level jumps, duplicate positions, root
+  /// position and vnode ignx values. The change can't be undone.
+  ///
+  /// Returns tuple (repairs, problems) with descriptions of the repairs
+  /// made and of the problems that remain.
+  ///
+  /// Raises ValueError if the outline is missing
+  ///
+  fn pyrepair_outline(py: Python, tid:usize) -> PyResult<(Vec<String>, Vec<String>)> {
+    let res = match TREES.lock().unwrap().get_mut(&tid) {
+      Some(t) => {
+        let (done, rest) = repair_outline(&mut t.outline, &mut t.nodes);
+        (done, rest.iter().map(|p|p.to_string()).collect::<Vec<String>>())
+      },
+      None => return Err(PyValueError::new_err("unknown tree id"))
+    };
+    if !res.0.is_empty() { notify_events(py, tid, vec![OutlineEvent::Reset]) }
+    Ok(res)
+  }
+  #[pyfn(m)]
   #[pyo3(name="children")]
   fn children(_py:Python, tid:usize, ni:usize) -> PyResult<Vec<VData>> {
     match TREES.lock().unwrap().get(&tid).map(|t|{
@@ -143,6 +540,29 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
     }
   }
   #[pyfn(m)]
+  #[pyo3(name="changes_to_bytes", text_signature="(data)")]
+  /// Converts text encoded change records (as returned by
+  /// outline operations) into the compact binary encoding.
+  ///
+  /// Raises ValueError if data contains malformed records.
+  ///
+  fn pychanges_to_bytes(py: Python, data:&str) -> PyResult<PyObject> {
+    match text_to_bytes(data) {
+      Ok(b) => Ok(PyBytes::new(py, &b).into()),
+      Err(e) => Err(PyValueError::new_err(e.to_string()))
+    }
+  }
+  #[pyfn(m)]
+  #[pyo3(name="changes_from_bytes", text_signature="(data)")]
+  /// Converts binary encoded change records back into
+  /// their versioned text encoding.
+  ///
+  /// Raises ValueError if data contains malformed records.
+  ///
+  fn pychanges_from_bytes(_py: Python, data:&[u8]) -> PyResult<String> {
+    bytes_to_text(data).map_err(|e|PyValueError::new_err(e.to_string()))
+  }
+  #[pyfn(m)]
   #[pyo3(name="clean_files")]
   fn clean_files(_py:Python, tid:usize, folder:&str) -> PyResult<Vec<(String, usize)>> {
     match TREES.lock().unwrap().get(&tid).map(|t|{
@@ -159,13 +579,13 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   /// 
   /// Returns None if the tid outline is missing or there isn't a node at 
   /// the given position p, or node was already collapsed
-  fn collapse_node(_py: Python, tid:usize, label:u32) -> Option<String> {
-    TREES
+  fn collapse_node(py: Python, tid:usize, label:u32) -> Option<String> {
+    let res = TREES
       .lock()
       .unwrap()
       .get_mut(&tid)
       .and_then(|t|{
-        t.outline
+        let res = t.outline
           .iter_mut()
           .enumerate()
           .filter(|x|x.1.label() == label)
@@ -176,8 +596,46 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
             b64write(x.0 as u64, &mut s);
             x.1.collapse();
             s
-          })
-      })
+          });
+        t.record(res)
+      });
+    notify(py, tid, res.as_deref(), false);
+    res
+  }
+  #[pyfn(m)]
+  #[pyo3(name="commit", text_signature="(tid)")]
+  /// Closes the open transaction on the outline identified by tid
+  /// and returns one string describing all changes made inside it,
+  /// that can be later used for undo/redo operations.
+  ///
+  /// Raises ValueError if there is no such outline or it has no
+  /// open transaction (for example because it was rolled back after
+  /// a failed operation).
+  ///
+  fn pycommit(_py: Python, tid:usize) -> PyResult<String> {
+    match TREES.lock().unwrap().get_mut(&tid) {
+      Some(t) => match t.transaction.take() {
+        Some(tr) => Ok(t.stamp(&tr.commit())),
+        None => Err(PyValueError::new_err("no open transaction"))
+      },
+      None => Err(PyValueError::new_err("unknown tree id"))
+    }
+  }
+  #[pyfn(m)]
+  #[pyo3(name="copy_outline", text_signature="(tid, p)")]
+  /// Returns Leo clipboard xml for the node at position p
+  /// and its subtree in the outline identified by tid.
+  ///
+  /// Raises ValueError if there is no such outline or node.
+  ///
+  fn pycopy_outline(_py: Python, tid:usize, p:u32) -> PyResult<String> {
+    match TREES.lock().unwrap().get(&tid) {
+      Some(t) => match t.outline.label_index(p) {
+        Some(i) => Ok(copy_outline(&t.outline, &t.nodes, i)),
+        None => Err(PyValueError::new_err("no such node"))
+      },
+      None => Err(PyValueError::new_err("unknown tree id"))
+    }
   }
   #[pyfn(m)]
   #[pyo3(name="debug_str", text_signature="(tid)")]
@@ -213,6 +671,7 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   #[pyfn(m)]
   #[pyo3(name="drop_tree")]
   fn drop_tree(_py:Python, tid:usize) -> PyResult<bool> {
+    LISTENERS.lock().unwrap().remove(&tid);
     Ok(TREES.lock().unwrap().remove(&tid).is_some())
   }
   #[pyfn(m)]
@@ -232,13 +691,13 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   /// 
   /// Returns None if the tid outline is missing or there isn't a node at 
   /// the given position p, or node was already expanded
-  fn expand_node(_py: Python, tid:usize, label:u32) -> Option<String> {
-    TREES
+  fn expand_node(py: Python, tid:usize, label:u32) -> Option<String> {
+    let res = TREES
       .lock()
       .unwrap()
       .get_mut(&tid)
       .and_then(|t|{
-        t.outline
+        let res = t.outline
           .iter_mut()
           .enumerate()
           .filter(|x|x.1.label() == label)
@@ -249,8 +708,11 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
             b64write(x.0 as u64, &mut s);
             x.1.expand();
             s
-          })
-      })
+          });
+        t.record(res)
+      });
+    notify(py, tid, res.as_deref(), false);
+    res
   }
   #[pyfn(m)]
   #[pyo3(name="extract_subtree")]
@@ -259,7 +721,7 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
       Some(t) => extract_subtree(&t.outline, &t.nodes, ni),
       None => return Err(PyValueError::new_err("unknown tree id"))
     };
-    let t = Tree {outline, nodes};
+    let t = Tree::new(outline, nodes);
     let mut m = TREES.lock().unwrap();
     let ntid = m.len();
     m.insert(ntid, t);
@@ -298,9 +760,13 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   /// doesn't contain valid Leo document.
   ///
   fn load_leo(_py: Python, fname:&str) -> PyResult<usize> {
-    match load_with_external_files(fname) {
-      Ok((outline, nodes)) => {
-        let t = Tree {outline, nodes};
+    match load_document_with_external_files(fname) {
+      Ok((outline, nodes, extras)) => {
+        let mut t = Tree::new(outline, nodes).with_folder(fname);
+        t.extras = extras;
+        if let Some(f) = t.folder.as_ref() {
+          t.externals = external_files(f, &t.outline, &t.nodes);
+        }
         let mut m = TREES.lock().unwrap();
         let tid = m.len();
         m.insert(tid, t);
@@ -311,6 +777,19 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
     }
   }
   #[pyfn(m)]
+  #[pyo3(name="remove_listener", text_signature="(tid, id)")]
+  /// Removes listener with the given id from the outline identified
+  /// by tid. Returns False if there was no such listener.
+  ///
+  fn remove_listener(_py: Python, tid:usize, id:usize) -> bool {
+    LISTENERS
+      .lock()
+      .unwrap()
+      .get_mut(&tid)
+      .map(|obs|obs.remove(id))
+      .unwrap_or(false)
+  }
+  #[pyfn(m)]
   #[pyo3(name="move_node_right", text_signature="(tid, label)")]
   /// Moves node right identified by given label in the outline
   /// identified by tid. If the movement would result in the
@@ -321,8 +800,8 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   ///
   /// Returns None if the outline or node is missing
   ///
-  fn pymove_node_right(_py: Python, tid:usize, label:u32) -> Option<String> {
-    TREES
+  fn pymove_node_right(py: Python, tid:usize, label:u32) -> Option<String> {
+    let res = TREES
       .lock()
       .unwrap()
       .get_mut(&tid)
@@ -332,9 +811,11 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
          .skip(1)
          .position(|x|x.label() == label)
          .unwrap_or(0);
-        if i == 0 {return None}
-        move_node_right(&mut t.outline, i+1)
-      })
+        let res = if i == 0 { None } else { move_node_right(&mut t.outline, i+1) };
+        t.track(res)
+      });
+    notify(py, tid, res.as_deref(), false);
+    res
   }
   #[pyfn(m)]
   #[pyo3(name="move_node_left", text_signature="(tid, label)")]
@@ -347,8 +828,8 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   ///
   /// Returns None if the outline or node is missing
   ///
-  fn pymove_node_left(_py: Python, tid:usize, label:u32) -> Option<String> {
-    TREES
+  fn pymove_node_left(py: Python, tid:usize, label:u32) -> Option<String> {
+    let res = TREES
       .lock()
       .unwrap()
       .get_mut(&tid)
@@ -359,9 +840,11 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
          .position(|x|x.label() == label)
          .map(|x|x+1)
          .unwrap_or(0);
-        if i == 0 { return None }
-        move_node_left(&mut t.outline, i)
-      })
+        let res = if i == 0 { None } else { move_node_left(&mut t.outline, i) };
+        t.track(res)
+      });
+    notify(py, tid, res.as_deref(), false);
+    res
   }
   #[pyfn(m)]
   #[pyo3(name="move_node_up", text_signature="(tid, label)")]
@@ -374,8 +857,8 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   ///
   /// Returns None if the outline or node is missing
   ///
-  fn pymove_node_up(_py: Python, tid:usize, label:u32) -> Option<String> {
-    TREES
+  fn pymove_node_up(py: Python, tid:usize, label:u32) -> Option<String> {
+    let res = TREES
       .lock()
       .unwrap()
       .get_mut(&tid)
@@ -385,9 +868,11 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
          .skip(1)
          .position(|x|x.label() == label)
          .unwrap_or(0);
-        if i == 0 {return None}
-        move_node_up(&mut t.outline, i+1)
-      })
+        let res = if i == 0 { None } else { move_node_up(&mut t.outline, i+1) };
+        t.track(res)
+      });
+    notify(py, tid, res.as_deref(), false);
+    res
   }
   #[pyfn(m)]
   #[pyo3(name="move_node_down", text_signature="(tid, label)")]
@@ -400,8 +885,8 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   ///
   /// Returns None if the outline or node is missing
   ///
-  fn pymove_node_down(_py: Python, tid:usize, label:u32) -> Option<String> {
-    TREES
+  fn pymove_node_down(py: Python, tid:usize, label:u32) -> Option<String> {
+    let res = TREES
       .lock()
       .unwrap()
       .get_mut(&tid)
@@ -411,9 +896,11 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
          .skip(1)
          .position(|x|x.label() == label)
          .unwrap_or(0);
-        if i == 0 {return None}
-        move_node_down(&mut t.outline, i+1)
-      })
+        let res = if i == 0 { None } else { move_node_down(&mut t.outline, i+1) };
+        t.track(res)
+      });
+    notify(py, tid, res.as_deref(), false);
+    res
   }
   #[pyfn(m)]
   #[pyo3(name="node_at", text_signature="(tid, i)")]
@@ -442,7 +929,7 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   fn outline_from_file(_py: Python, fname:&str) -> PyResult<usize> {
     match from_derived_file(&Path::new(fname)) {
       Ok((outline, nodes)) => {
-        let t = Tree {outline, nodes};
+        let t = Tree::new(outline, nodes);
         let mut m = TREES.lock().unwrap();
         let tid = m.len();
         m.insert(tid, t);
@@ -454,8 +941,9 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   #[pyfn(m)]
   #[pyo3(name="outline_from_leo_str")]
   fn outline_from_leo_str(_py: Python, txt:&str) -> PyResult<usize> {
-    let (outline, nodes) = from_leo_content(txt);
-    let t = Tree {outline, nodes};
+    let (outline, nodes) = try_from_leo_content(txt).map_err(PyValueError::new_err)?;
+    let mut t = Tree::new(outline, nodes);
+    t.extras = LeoExtras::from_content(txt).unwrap_or_default();
     let mut m = TREES.lock().unwrap();
     let tid = m.len();
     m.insert(tid, t);
@@ -466,7 +954,7 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   fn outline_from_leo_file(_py: Python, txt:&str) -> PyResult<usize> {
     match from_leo_file(&Path::new(txt)) {
       Ok((outline, nodes)) => {
-        let t = Tree {outline, nodes};
+        let t = Tree::new(outline, nodes).with_folder(txt);
         let mut m = TREES.lock().unwrap();
         let tid = m.len();
         m.insert(tid, t);
@@ -484,7 +972,7 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   ///
   fn outline_from_string(_py: Python, txt:&str) -> PyResult<usize> {
     let (outline, nodes) = from_derived_file_content(txt);
-    let t = Tree {outline, nodes};
+    let t = Tree::new(outline, nodes);
     let mut m = TREES.lock().unwrap();
     let tid = m.len();
     m.insert(tid, t);
@@ -496,7 +984,7 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   fn outline_from_zipped_leo(_py: Python, arch:&str, fname:&str) -> PyResult<usize> {
     match from_zip_archive(&Path::new(arch), fname) {
       Ok((outline, nodes)) => {
-        let t = Tree {outline, nodes};
+        let t = Tree::new(outline, nodes);
         let mut m = TREES.lock().unwrap();
         let tid = m.len();
         m.insert(tid, t);
@@ -507,6 +995,42 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   }
   */
   #[pyfn(m)]
+  #[pyo3(name="paste_outline", text_signature="(tid, p, xml, retain_gnx)")]
+  /// Pastes outline given as Leo clipboard xml after the node at
+  /// position p in the outline identified by tid.
+  ///
+  /// If retain_gnx is True pasted nodes keep their gnx and become
+  /// clones of the existing nodes with the same gnx. Otherwise all
+  /// pasted nodes get new unique gnx.
+  ///
+  /// Returns tuple (newp, undo_info).
+  ///
+  /// Raises ValueError if there is no such outline or node, if xml
+  /// is not a valid Leo outline or if pasting would make a node
+  /// its own descendant.
+  ///
+  fn pypaste_outline(py: Python, tid:usize, p:u32, xml:&str, retain_gnx:bool)
+      -> PyResult<(u32, String)> {
+    let res = match TREES.lock().unwrap().get_mut(&tid) {
+      Some(t) => {
+        let i = t.outline.label_index(p).unwrap_or(0);
+        let mut known:HashSet<String> = t.nodes.iter().map(|v|v.gnx.clone()).collect();
+        let g = &mut t.gnxgen;
+        let mut new_gnx = |_:&str| {
+          let x = g.next_at(now_secs(), |x|known.contains(x));
+          known.insert(x.clone());
+          x
+        };
+        let res = paste_outline(&mut t.outline, &mut t.nodes, i, xml, retain_gnx, &mut new_gnx);
+        let s = t.track(res.as_ref().ok().map(|x|x.1.clone()));
+        res.map(|x|(x.0, s.unwrap_or_default())).map_err(|e|PyValueError::new_err(e.0))
+      },
+      None => return Err(PyValueError::new_err("unknown tree id"))
+    };
+    notify(py, tid, res.as_ref().ok().map(|x|x.1.as_str()), false);
+    res
+  }
+  #[pyfn(m)]
   #[pyo3(name="p_index", text_signature="(tid, p)")]
   /// Returns a index of node with given position p in outline
   /// identified by tid.
@@ -570,13 +1094,16 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   /// if it doesn't have child with the childIndex, returns None
   /// else returns string description of the changes made.
   ///
-  fn pybreak_link(_py: Python, tid:usize, v1:&VData, ci:usize) -> Option<String> {
-    TREES.lock().unwrap().get_mut(&tid).and_then(|t|{
-      return break_link( &mut t.outline
-                        , v1.ignx
-                        , ci
-                        )
-    })
+  fn pybreak_link(py: Python, tid:usize, v1:&VData, ci:usize) -> Option<String> {
+    let res = TREES.lock().unwrap().get_mut(&tid).and_then(|t|{
+      let res = break_link( &mut t.outline
+                          , v1.ignx
+                          , ci
+                          );
+      t.track(res)
+    });
+    notify(py, tid, res.as_deref(), false);
+    res
   }
   #[pyfn(m)]
   #[pyo3(name="clone_node", text_signature="(tid, p)")]
@@ -585,16 +1112,19 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   /// 
   /// Returns None if the tid outline is missing or there isn't a node at 
   /// the given position p
-  fn pyclone_node(_py: Python, tid:usize, p:u32) -> Option<(u32, String)> {
-    TREES
+  fn pyclone_node(py: Python, tid:usize, p:u32) -> Option<(u32, String)> {
+    let res = TREES
       .lock()
       .unwrap()
       .get_mut(&tid)
       .and_then(|t|{
         let i = t.outline.label_index(p).unwrap_or(0);
-        if i == 0 {return None}
-        Some(clone_node(&mut t.outline, i))
-      })
+        if i == 0 { t.track(None); return None }
+        let (p, s) = clone_node(&mut t.outline, i);
+        t.track(Some(s)).map(|s|(p, s))
+      });
+    notify(py, tid, res.as_ref().map(|x|x.1.as_str()), false);
+    res
   }
   #[pyfn(m)]
   #[pyo3(name="create_link", text_signature="(tid, parent, childIndex, child)")]
@@ -605,14 +1135,156 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   /// Returns None if the tid outline is missing or parent and child
   /// are not part of the outline
   ///
-  fn pycreate_link(_py: Python, tid:usize, v1:&VData, ci:usize, v2:&VData) -> Option<String> {
-    TREES.lock().unwrap().get_mut(&tid).and_then(|t|{
-      return create_link( &mut t.outline
-                        , v1.ignx
-                        , ci
-                        , v2.ignx
-                        )
-    })
+  fn pycreate_link(py: Python, tid:usize, v1:&VData, ci:usize, v2:&VData) -> Option<String> {
+    let res = TREES.lock().unwrap().get_mut(&tid).and_then(|t|{
+      let res = create_link( &mut t.outline
+                           , v1.ignx
+                           , ci
+                           , v2.ignx
+                           );
+      t.track(res)
+    });
+    notify(py, tid, res.as_deref(), false);
+    res
+  }
+  #[pyfn(m)]
+  #[pyo3(name="sort_children", text_signature="(tid, p, key)")]
+  /// Sorts children of the node at position p in the outline
+  /// identified by tid. Key can be 'headline', 'nocase' (headline
+  /// ignoring case) or 'natural' (numbers in headlines are compared
+  /// by their value).
+  ///
+  /// Returns string representation of the performed changes, that
+  /// can be later used for undo/redo operations.
+  ///
+  /// Returns None if the tid outline is missing, there isn't a node at
+  /// the given position p or its children are already sorted.
+  ///
+  /// Raises ValueError if key is unknown
+  ///
+  fn pysort_children(py: Python, tid:usize, p:u32, key:&str) -> PyResult<Option<String>> {
+    let key = match SortKey::from_str(key) {
+      Some(k) => k,
+      None => return Err(PyValueError::new_err(format!("unknown sort key: {}", key)))
+    };
+    let res = TREES
+      .lock()
+      .unwrap()
+      .get_mut(&tid)
+      .and_then(|t|{
+        let i = t.outline.label_index(p).unwrap_or(0);
+        let res = if i == 0 { None } else { sort_children(&mut t.outline, &t.nodes, i, key) };
+        t.record(res)
+      });
+    notify(py, tid, res.as_deref(), false);
+    Ok(res)
+  }
+  #[pyfn(m)]
+  #[pyo3(name="reverse_children", text_signature="(tid, p)")]
+  /// Reverses the order of children of the node at position p in
+  /// the outline identified by tid.
+  ///
+  /// Returns string representation of the performed changes, that
+  /// can be later used for undo/redo operations.
+  ///
+  /// Returns None if the tid outline is missing, there isn't a node at
+  /// the given position p or it has less than two children.
+  ///
+  fn pyreverse_children(py: Python, tid:usize, p:u32) -> Option<String> {
+    let res = TREES
+      .lock()
+      .unwrap()
+      .get_mut(&tid)
+      .and_then(|t|{
+        let i = t.outline.label_index(p).unwrap_or(0);
+        let res = if i == 0 { None } else { reverse_children(&mut t.outline, i) };
+        t.record(res)
+      });
+    notify(py, tid, res.as_deref(), false);
+    res
+  }
+  #[pyfn(m)]
+  #[pyo3(name="promote", text_signature="(tid, p)")]
+  /// Makes all children of the node at position p in the outline
+  /// identified by tid its following siblings.
+  /// If the operation would result in the invalid outline, returns
+  /// None, without making any change.
+  ///
+  /// Returns string representation of the performed changes, that
+  /// can be later used for undo/redo operations.
+  ///
+  /// Returns None if the outline or node is missing
+  ///
+  fn pypromote(py: Python, tid:usize, p:u32) -> Option<String> {
+    let res = TREES
+      .lock()
+      .unwrap()
+      .get_mut(&tid)
+      .and_then(|t|{
+        let i = t.outline.label_index(p).unwrap_or(0);
+        let res = if i == 0 { None } else { promote(&mut t.outline, i) };
+        t.track(res)
+      });
+    notify(py, tid, res.as_deref(), false);
+    res
+  }
+  #[pyfn(m)]
+  #[pyo3(name="demote", text_signature="(tid, p)")]
+  /// Makes all following siblings of the node at position p in the
+  /// outline identified by tid its children.
+  /// If the operation would result in the invalid outline, returns
+  /// None, without making any change.
+  ///
+  /// Returns string representation of the performed changes, that
+  /// can be later used for undo/redo operations.
+  ///
+  /// Returns None if the outline or node is missing
+  ///
+  fn pydemote(py: Python, tid:usize, p:u32) -> Option<String> {
+    let res = TREES
+      .lock()
+      .unwrap()
+      .get_mut(&tid)
+      .and_then(|t|{
+        let i = t.outline.label_index(p).unwrap_or(0);
+        let res = if i == 0 { None } else { demote(&mut t.outline, i) };
+        t.track(res)
+      });
+    notify(py, tid, res.as_deref(), false);
+    res
+  }
+  #[pyfn(m)]
+  #[pyo3(name="move_node_to", text_signature="(tid, p, new_parent_gnx, child_index)")]
+  /// Moves node at position p in the outline identified by tid so
+  /// that it becomes child of the node with the given gnx at the
+  /// given child index. Use 'hidden-root-vnode-gnx' to move node to
+  /// the top level. All clones of the old and the new parent are
+  /// updated.
+  ///
+  /// Returns tuple (newp, undo_info) if successful.
+  ///
+  /// Returns None if the outline, node or new parent is missing, if
+  /// the new parent is inside the moved subtree, or if child_index is
+  /// out of range
+  ///
+  fn pymove_node_to(py: Python, tid:usize, p:u32, new_parent_gnx:&str, child_index:usize)
+      -> Option<(u32, String)> {
+    let res = TREES
+      .lock()
+      .unwrap()
+      .get_mut(&tid)
+      .and_then(|t|{
+        let i = t.outline.label_index(p).unwrap_or(0);
+        let npignx = crate::model::gnx_index(&t.nodes).get(new_parent_gnx).copied();
+        let res = match npignx {
+          Some(x) if i > 0 => move_node_to(&mut t.outline, i, x, child_index),
+          _ => None
+        };
+        let s = t.track(res.as_ref().map(|x|x.1.clone()));
+        res.zip(s).map(|(x, s)|(x.0, s))
+      });
+    notify(py, tid, res.as_ref().map(|x|x.1.as_str()), false);
+    res
   }
   #[pyfn(m)]
   #[pyo3(name="delete_node", text_signature="(tid, p)")]
@@ -620,16 +1292,18 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   /// 
   /// Returns None if the tid outline is missing or there isn't a node at 
   /// the given position p
-  fn pydelete_node(_py: Python, tid:usize, p:u32) -> Option<String> {
-    TREES
+  fn pydelete_node(py: Python, tid:usize, p:u32) -> Option<String> {
+    let res = TREES
       .lock()
       .unwrap()
       .get_mut(&tid)
       .and_then(|t|{
         let i = t.outline.label_index(p).unwrap_or(0);
-        if i == 0 {return None}
-        delete_node(&mut t.outline, i)
-      })
+        let res = if i == 0 { None } else { delete_node(&mut t.outline, i) };
+        t.track(res)
+      });
+    notify(py, tid, res.as_deref(), false);
+    res
   }
   #[pyfn(m)]
   #[pyo3(name="insert_new_node", text_signature="(tid, p, gnx)")]
@@ -640,106 +1314,446 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   /// 
   /// Returns None if the tid outline is missing or there isn't a node at 
   /// the given position p
-  fn pyinsert_new_node(_py: Python, tid:usize, p:u32, gnx:&str) -> Option<(u32, String)> {
-    TREES
+  fn pyinsert_new_node(py: Python, tid:usize, p:u32, gnx:&str) -> Option<(u32, String)> {
+    let res = TREES
       .lock()
       .unwrap()
       .get_mut(&tid)
       .and_then(|t|{
         let i = t.outline.label_index(p).unwrap_or(0);
-        if i == 0 {return None}
-        Some(insert_new_node(&mut t.outline, &mut t.nodes, i, gnx))
-      })
+        if i == 0 { t.track(None); return None }
+        let (p, s) = insert_new_node(&mut t.outline, &mut t.nodes, i, gnx);
+        t.track(Some(s)).map(|s|(p, s))
+      });
+    notify(py, tid, res.as_ref().map(|x|x.1.as_str()), false);
+    res
   }
   #[pyfn(m)]
-  #[pyo3(name="redo", text_signature="(tid, data)")]
-  /// Redoes change described by the data parameter
-  /// previously undone to the outline identified by tid.
-  /// 
-  fn pyredo(_py: Python, tid:usize, data:&str) {
-    TREES.lock().unwrap().get_mut(&tid).map(|t|{
-      for x in data.split('\n') {
-        if x.starts_with("ip:") {
-          redo_insert_parts(&mut t.outline, x);
-        } else if x.starts_with("db:") {
-          redo_delete_blocks(&mut t.outline, x);
-        } else if x.starts_with("sb:") {
-          redo_shift_blocks(&mut t.outline, x);
-        } else if x.starts_with("sn:") {
-          redo_set_nodes(&mut t.outline, x);
-        } else if x.starts_with("addv:") {
-          redo_insert_new_node(&mut t.nodes, x);
-        } else if x.starts_with("vupd:") {
-          redo_update_node(&mut t.nodes, x);
-        } else if x.starts_with("collapse:") {
-          let i = b64int(&x[9..]) as usize;
-          t.outline[i].collapse();
-        } else if x.starts_with("expand:") {
-          let i = b64int(&x[7..]) as usize;
-          t.outline[i].expand();
-        }
-      }
-    });
+  #[pyo3(name="insert_before", text_signature="(tid, p, gnx)")]
+  /// Inserts new node with given gnx in the outline identified by tid,
+  /// as the previous sibling of p.
+  /// Returns tuple (newp, undo_info) if successful.
+  ///
+  /// Returns None if the tid outline is missing or there isn't a node at
+  /// the given position p
+  fn pyinsert_before(py: Python, tid:usize, p:u32, gnx:&str) -> Option<(u32, String)> {
+    insert_at(py, tid, p, gnx, InsertPlace::Before)
   }
   #[pyfn(m)]
-  #[pyo3(name="undo", text_signature="(tid, data)")]
-  /// Undoes change described by the data parameter
-  /// previously done to the outline identified by tid.
-  /// 
-  fn pyundo(_py: Python, tid:usize, data:&str) {
-    TREES.lock().unwrap().get_mut(&tid).map(|t|{
-      for x in data.split('\n').rev() {
-        if x.starts_with("ip:") {
-          undo_insert_parts(&mut t.outline, x);
-        } else if x.starts_with("db:") {
-          undo_delete_blocks(&mut t.outline, x);
-        } else if x.starts_with("sb:") {
-          undo_shift_blocks(&mut t.outline, x);
-        } else if x.starts_with("sn:") {
-          undo_set_nodes(&mut t.outline, x);
-        } else if x.starts_with("addv:") {
-          t.nodes.pop();
-        } else if x.starts_with("vupd:") {
-          undo_update_node(&mut t.nodes, x);
-        } else if x.starts_with("collapse:") {
-          let i = b64int(&x[9..]) as usize;
-          t.outline[i].expand();
-        } else if x.starts_with("expand:") {
-          let i = b64int(&x[7..]) as usize;
-          t.outline[i].collapse();
-        }
-      }
-    });
+  #[pyo3(name="insert_after", text_signature="(tid, p, gnx)")]
+  /// Inserts new node with given gnx in the outline identified by tid,
+  /// as the next sibling of p.
+  /// Returns tuple (newp, undo_info) if successful.
+  ///
+  /// Returns None if the tid outline is missing or there isn't a node at
+  /// the given position p
+  fn pyinsert_after(py: Python, tid:usize, p:u32, gnx:&str) -> Option<(u32, String)> {
+    insert_at(py, tid, p, gnx, InsertPlace::After)
   }
   #[pyfn(m)]
-  #[pyo3(name="update_atclean")]
-  fn pyupdate_atclean(_py: Python, tid: usize, ni:usize, cont:&str) -> PyResult<()> {
-    match TREES.lock().unwrap().get_mut(&tid).map(|t|{
-      update_atclean_tree(&t.outline, &mut t.nodes, ni, cont)
-    }) {
-      Some(()) => Ok(()),
-      None => Err(PyValueError::new_err("unknown tree id"))
-    }
+  #[pyo3(name="insert_as_first_child", text_signature="(tid, p, gnx)")]
+  /// Inserts new node with given gnx in the outline identified by tid,
+  /// as the first child of p.
+  /// Returns tuple (newp, undo_info) if successful.
+  ///
+  /// Returns None if the tid outline is missing or there isn't a node at
+  /// the given position p
+  fn pyinsert_as_first_child(py: Python, tid:usize, p:u32, gnx:&str) -> Option<(u32, String)> {
+    insert_at(py, tid, p, gnx, InsertPlace::FirstChild)
   }
   #[pyfn(m)]
-  #[pyo3(name="subtree_size")]
-  fn subtree_seize(_py:Python, tid:usize, ni:usize) -> PyResult<usize> {
-    match TREES.lock().unwrap().get(&tid).map(|t|{
-      t.outline.subtree_size(ni)
-    }){
-      Some(x) => Ok(x),
-      None => Err(PyValueError::new_err("unknown tree id"))
-    }
+  #[pyo3(name="insert_as_last_child", text_signature="(tid, p, gnx)")]
+  /// Inserts new node with given gnx in the outline identified by tid,
+  /// as the last child of p.
+  /// Returns tuple (newp, undo_info) if successful.
+  ///
+  /// Returns None if the tid outline is missing or there isn't a node at
+  /// the given position p
+  fn pyinsert_as_last_child(py: Python, tid:usize, p:u32, gnx:&str) -> Option<(u32, String)> {
+    insert_at(py, tid, p, gnx, InsertPlace::LastChild)
   }
   #[pyfn(m)]
-  #[pyo3(name="tree_len", text_signature="(tid)")]
-  /// Returns the number of different positions in the
-  /// outline identified by tid.
+  #[pyo3(name="insert_generated_node", text_signature="(tid, p)")]
+  /// Same as insert_new_node, but the new node gets a new unique
+  /// gnx in Leo format `id.YYYYMMDDhhmmss.n`.
+  /// Returns tuple (newp, undo_info) if successful.
   ///
-  /// Raises ValueError if there is no outline (tid).
+  /// Returns None if the tid outline is missing or there isn't a node at
+  /// the given position p
+  fn pyinsert_generated_node(py: Python, tid:usize, p:u32) -> Option<(u32, String)> {
+    let res = TREES
+      .lock()
+      .unwrap()
+      .get_mut(&tid)
+      .and_then(|t|{
+        let i = t.outline.label_index(p).unwrap_or(0);
+        if i == 0 { t.track(None); return None }
+        let (p, s) = insert_generated_node(&mut t.outline, &mut t.nodes, i, &mut t.gnxgen);
+        t.track(Some(s)).map(|s|(p, s))
+      });
+    notify(py, tid, res.as_ref().map(|x|x.1.as_str()), false);
+    res
+  }
+  #[pyfn(m)]
+  #[pyo3(name="new_gnx", text_signature="(tid)")]
+  /// Returns a new gnx which is unique in the outline identified by tid.
   ///
-  fn tree_len(_py:Python, tid:usize) -> PyResult<usize> {
-    match TREES.lock().unwrap().get(&tid).map(|t|{
+  /// Returns None if the tid outline is missing
+  fn pynew_gnx(_py: Python, tid:usize) -> Option<String> {
+    TREES.lock().unwrap().get_mut(&tid).map(|t|t.gnxgen.next(&t.nodes))
+  }
+  #[pyfn(m)]
+  #[pyo3(name="set_gnx_id", text_signature="(tid, id)")]
+  /// Sets the user id used as a prefix of gnx generated for the
+  /// outline identified by tid. Default id is 'mini_leo'.
+  ///
+  /// Returns False if the tid outline is missing
+  fn pyset_gnx_id(_py: Python, tid:usize, id:&str) -> bool {
+    TREES.lock().unwrap().get_mut(&tid).map(|t|t.gnxgen.set_id(id)).is_some()
+  }
+  #[pyfn(m)]
+  #[pyo3(name="redo", text_signature="(tid, data)")]
+  /// Redoes change described by the data parameter
+  /// previously undone to the outline identified by tid.
+  /// Changes made before a compaction are remapped first.
+  ///
+  /// Raises ValueError if data contains malformed change records,
+  /// records that refer to vnodes dropped by compaction or records
+  /// that don't fit this outline, in which case the outline is left
+  /// unchanged.
+  ///
+  fn pyredo(py: Python, tid:usize, data:&str) -> PyResult<()> {
+    let res = match TREES.lock().unwrap().get_mut(&tid) {
+      Some(t) if t.transaction.is_some() =>
+        return Err(PyValueError::new_err("transaction is open")),
+      Some(t) => apply_changes(t, data, false)?,
+      None => return Err(PyValueError::new_err("unknown tree id"))
+    };
+    notify(py, tid, Some(&res), false);
+    Ok(())
+  }
+  #[pyfn(m)]
+  #[pyo3(name="rollback", text_signature="(tid)")]
+  /// Closes the open transaction on the outline identified by tid
+  /// restoring the outline and its nodes as they were before the
+  /// transaction began.
+  ///
+  /// Returns False if there was no open transaction.
+  ///
+  fn pyrollback(py: Python, tid:usize) -> PyResult<bool> {
+    let res = match TREES.lock().unwrap().get_mut(&tid) {
+      Some(t) => match t.transaction.take() {
+        Some(tr) => {
+          tr.rollback(&mut t.outline, &mut t.nodes);
+          true
+        },
+        None => false
+      },
+      None => return Err(PyValueError::new_err("unknown tree id"))
+    };
+    if res {
+      notify_events(py, tid, vec![OutlineEvent::Reset]);
+    }
+    Ok(res)
+  }
+  #[pyfn(m)]
+  #[pyo3(name="undo", text_signature="(tid, data)")]
+  /// Undoes change described by the data parameter
+  /// previously done to the outline identified by tid.
+  /// Changes made before a compaction are remapped first.
+  ///
+  /// Raises ValueError if data contains malformed change records,
+  /// records that refer to vnodes dropped by compaction or records
+  /// that don't fit this outline, in which case the outline is left
+  /// unchanged.
+  ///
+  fn pyundo(py: Python, tid:usize, data:&str) -> PyResult<()> {
+    let res = match TREES.lock().unwrap().get_mut(&tid) {
+      Some(t) if t.transaction.is_some() =>
+        return Err(PyValueError::new_err("transaction is open")),
+      Some(t) => apply_changes(t, data, true)?,
+      None => return Err(PyValueError::new_err("unknown tree id"))
+    };
+    notify(py, tid, Some(&res), true);
+    Ok(())
+  }
+  #[pyfn(m)]
+  #[pyo3(name="compact", text_signature="(tid)")]
+  /// Drops vnodes which are not used in the outline identified by tid
+  /// and renumbers the remaining ones. Positions don't change.
+  ///
+  /// Every compaction increments the epoch of the outline. Change
+  /// records returned by operations are marked with the epoch in which
+  /// they were made, and undo and redo remap older ones to the current
+  /// numbering, or reject them if they refer to dropped vnodes.
+  ///
+  /// Returns tuple (remap, epoch), where remap[old_ignx] is the new
+  /// ignx or None if the vnode was dropped, and epoch is the new epoch.
+  ///
+  /// Raises ValueError if the outline is missing or has an open
+  /// transaction
+  ///
+  fn pycompact(py: Python, tid:usize) -> PyResult<(Vec<Option<u32>>, usize)> {
+    let res = match TREES.lock().unwrap().get_mut(&tid) {
+      Some(t) if t.transaction.is_some() =>
+        return Err(PyValueError::new_err("transaction is open")),
+      Some(t) => {
+        let r = compact(&mut t.outline, &mut t.nodes);
+        let table = r.table.clone();
+        t.remaps.push(r);
+        (table, t.remaps.len())
+      },
+      None => return Err(PyValueError::new_err("unknown tree id"))
+    };
+    notify_events(py, tid, vec![OutlineEvent::Reset]);
+    Ok(res)
+  }
+  #[pyfn(m)]
+  #[pyo3(name="epoch", text_signature="(tid)")]
+  /// Returns the number of compactions of the outline identified by
+  /// tid, or None if the outline is missing. Change records made after
+  /// compaction start with a line `#epoch:e` telling their epoch.
+  ///
+  fn pyepoch(_py: Python, tid:usize) -> Option<usize> {
+    TREES.lock().unwrap().get(&tid).map(|t|t.remaps.len())
+  }
+  #[pyfn(m)]
+  #[pyo3(name="remap_changes", text_signature="(tid, data, epoch)")]
+  /// Converts change records made in the given epoch of the outline
+  /// identified by tid, so that they can be undone or redone in the
+  /// current epoch. Records marked with their epoch must be marked
+  /// with the given one. Returned records are marked with the current
+  /// epoch.
+  ///
+  /// Raises ValueError if the outline is missing, if the epoch is
+  /// unknown or doesn't match, or if records refer to vnodes dropped
+  /// by compaction. In the latter case, this change and all older ones
+  /// can't be undone anymore.
+  ///
+  fn pyremap_changes(_py: Python, tid:usize, data:&str, epoch:usize) -> PyResult<String> {
+    let e = change_epoch(data).map_err(|e|PyValueError::new_err(e.to_string()))?.0;
+    if e != 0 && e != epoch {
+      return Err(PyValueError::new_err(format!("records are from epoch {}", e)));
+    }
+    match TREES.lock().unwrap().get(&tid) {
+      Some(t) => to_current_epoch(&with_epoch(epoch, data), &t.remaps)
+        .map_err(|e|PyValueError::new_err(e.to_string())),
+      None => Err(PyValueError::new_err("unknown tree id"))
+    }
+  }
+  #[pyfn(m)]
+  #[pyo3(name="save_snapshot", text_signature="(tid, path)")]
+  /// Writes the outline identified by tid with all its vnodes to the
+  /// given path in a binary format, together with modification times
+  /// and hashes of its external at-file and at-clean files.
+  ///
+  /// Raises ValueError if the outline is missing and IOError if the
+  /// file can't be written
+  ///
+  fn pysave_snapshot(_py: Python, tid:usize, path:&str) -> PyResult<()> {
+    let snap = match TREES.lock().unwrap().get(&tid) {
+      Some(t) => Snapshot {
+        folder: t.folder.as_ref().map(|f|f.to_string_lossy().to_string()),
+        outline: t.outline.clone(),
+        nodes: t.nodes.clone(),
+        files: t.folder.as_ref()
+          .map(|f|external_file_stamps(f, &t.outline, &t.nodes))
+          .unwrap_or_default()
+      },
+      None => return Err(PyValueError::new_err("unknown tree id"))
+    };
+    snap.save(Path::new(path)).map_err(|e|PyIOError::new_err(e.to_string()))
+  }
+  #[pyfn(m)]
+  #[pyo3(name="load_snapshot", text_signature="(path)")]
+  /// Loads the outline from the snapshot written by save_snapshot.
+  ///
+  /// Returns tuple (tid, stale), where stale is a list of external
+  /// files that have changed or disappeared since the snapshot was
+  /// written. If it is not empty, the outline is out of date. The same
+  /// files are then listed by check_external_changes and can be read
+  /// again by reload_external.
+  ///
+  /// Raises IOError if the file can't be read or if it isn't a valid
+  /// snapshot
+  ///
+  fn pyload_snapshot(_py: Python, path:&str) -> PyResult<(usize, Vec<String>)> {
+    let snap = Snapshot::load(Path::new(path)).map_err(|e|PyIOError::new_err(e.to_string()))?;
+    let stale = snap.stale_files();
+    let mut t = Tree::new(snap.outline, snap.nodes);
+    t.folder = snap.folder.map(PathBuf::from);
+    if let Some(f) = t.folder.as_ref() {
+      t.externals = external_files_from_stamps(f, &t.outline, &t.nodes, &snap.files);
+    }
+    let mut m = TREES.lock().unwrap();
+    let tid = m.len();
+    m.insert(tid, t);
+    Ok((tid, stale))
+  }
+  #[pyfn(m)]
+  #[pyo3(name="check_external_changes", text_signature="(tid)")]
+  /// Lists external files of the outline identified by tid which have
+  /// changed since they were loaded by load_leo or, for an outline
+  /// loaded by load_snapshot, since the snapshot was written.
+  ///
+  /// Returns list of tuples (path, p, status), where p is position of
+  /// the file node or None if it isn't in the outline anymore, and
+  /// status is one of 'changed', 'deleted' or 'conflict'. Conflict means
+  /// that the subtree of the file node has been edited too.
+  ///
+  /// Raises ValueError if the outline is missing
+  ///
+  fn pycheck_external_changes(_py: Python, tid:usize) -> PyResult<Vec<(String, Option<u32>, &'static str)>> {
+    match TREES.lock().unwrap().get(&tid) {
+      Some(t) => Ok(external_changes(&t.outline, &t.nodes, &t.externals)
+        .into_iter()
+        .map(|c|(c.path, c.index.map(|i|t.outline[i].label()), c.status.name()))
+        .collect()),
+      None => Err(PyValueError::new_err("unknown tree id"))
+    }
+  }
+  #[pyfn(m)]
+  #[pyo3(name="reload_external", text_signature="(tid, p, force)")]
+  /// Reads again the at-file or at-clean file of the node at position p
+  /// in the outline identified by tid and updates its subtree, like
+  /// refresh_from_disk.
+  ///
+  /// If the subtree has been edited since the file was loaded and force
+  /// is not True, nothing is changed and the returned tuple is
+  /// (False, report), where report describes how the subtree differs
+  /// from the file. Otherwise returns (True, undo_info).
+  ///
+  /// Raises ValueError if the outline or node is missing, if the outline
+  /// wasn't loaded from a file or if the node isn't an at-file or
+  /// at-clean node, and IOError if the file can't be read
+  ///
+  fn pyreload_external(py: Python, tid:usize, p:u32, force:Option<bool>) -> PyResult<(bool, String)> {
+    let res = match TREES.lock().unwrap().get_mut(&tid) {
+      Some(t) => read_atfile(t, p).and_then(|(i, f, cont, clean)|{
+        let gnx = &t.nodes[t.outline[i].ignx() as usize].gnx;
+        let edited = t.externals.iter()
+          .find(|x|x.gnx == *gnx)
+          .map(|x|x.tree != subtree_hash(&t.outline, &t.nodes, i))
+          .unwrap_or(false);
+        if edited && !force.unwrap_or(false) {
+          let report = if clean { atclean_conflicts } else { atfile_conflicts };
+          return Ok((false, report(&t.outline, &t.nodes, i, &cont)));
+        }
+        reload_at(t, i, &f, &cont, clean).map(|u|(true, u))
+      }),
+      None => return Err(PyValueError::new_err("unknown tree id"))
+    };
+    notify(py, tid, res.as_ref().ok().filter(|x|x.0).map(|x|x.1.as_str()), false);
+    res
+  }
+  #[pyfn(m)]
+  #[pyo3(name="refresh_from_disk", text_signature="(tid, p)")]
+  /// Reads again the at-file file of the node at position p in the
+  /// outline identified by tid and replaces its subtree with the
+  /// nodes from the file. Headlines and bodies of existing vnodes are
+  /// updated, and all clones of the nodes from the file, also those
+  /// outside of the subtree, get their new children. For an at-clean
+  /// node only the bodies in its subtree are updated, as update_atclean
+  /// does.
+  ///
+  /// Returns undo_info, which is empty if the subtree was already
+  /// up to date.
+  ///
+  /// Raises ValueError if the outline or node is missing, if the outline
+  /// wasn't loaded from a file or if the node isn't an at-file or
+  /// at-clean node, and IOError if the file can't be read
+  ///
+  fn pyrefresh_from_disk(py: Python, tid:usize, p:u32) -> PyResult<String> {
+    let res = match TREES.lock().unwrap().get_mut(&tid) {
+      Some(t) => read_atfile(t, p).and_then(|(i, f, cont, clean)|reload_at(t, i, &f, &cont, clean)),
+      None => return Err(PyValueError::new_err("unknown tree id"))
+    };
+    notify(py, tid, res.as_ref().ok().map(|x|x.as_str()), false);
+    res
+  }
+  #[pyfn(m)]
+  #[pyo3(name="check_sections", text_signature="(tid, ni)")]
+  /// Analyzes section references in the subtree of the node at the
+  /// given index `ni` in the outline identified by `tid`.
+  ///
+  /// Returns tuple (refs, unused, duplicates), where refs is a list of
+  /// tuples (index, line, name, def_index or None) for every reference,
+  /// unused is a list of indices of definitions that are never used
+  /// and duplicates is a list of tuples (name, [indices]) for sections
+  /// defined more than once.
+  ///
+  /// Raises ValueError if there is no such outline or if there
+  /// is no such node.
+  ///
+  fn pycheck_sections(_py: Python, tid: usize, ni: usize)
+      -> PyResult<(Vec<(usize, usize, String, Option<usize>)>, Vec<usize>, Vec<(String, Vec<usize>)>)> {
+    match TREES.lock().unwrap().get(&tid) {
+      Some(t) if t.outline.len() > ni => {
+        let r = analyze_sections(&t.outline, &t.nodes, ni);
+        let refs = r.refs.into_iter().map(|x|(x.index, x.line, x.name, x.def)).collect();
+        Ok((refs, r.unused, r.duplicates))
+      },
+      Some(_) => Err(PyValueError::new_err("no such node")),
+      None => Err(PyValueError::new_err("unknown tree id"))
+    }
+  }
+  #[pyfn(m)]
+  #[pyo3(name="atclean_to_str_checked", text_signature="(tid, ni)")]
+  /// Same as atclean_to_str, but returns tuple (text, problems) where
+  /// problems is a list of messages describing undefined, unused and
+  /// duplicate sections. Lines with undefined section references are
+  /// written unchanged.
+  ///
+  /// Raises ValueError if there is no such outline or if there
+  /// is no such node.
+  ///
+  fn pyatclean_to_str_checked(_py: Python, tid: usize, ni: usize) -> PyResult<(String, Vec<String>)> {
+    match TREES.lock().unwrap().get(&tid) {
+      Some(t) if t.outline.len() > ni => Ok(atclean_to_string_checked(&t.outline, &t.nodes, ni)),
+      Some(_) => Err(PyValueError::new_err("no such node")),
+      None => Err(PyValueError::new_err("unknown tree id"))
+    }
+  }
+  #[pyfn(m)]
+  #[pyo3(name="update_atclean", text_signature="(tid, ni, cont)")]
+  /// Updates bodies in the @clean subtree of the node at index ni in
+  /// the outline identified by tid to match the file content cont.
+  ///
+  /// Returns undo_info, which is empty if nothing has changed.
+  ///
+  /// Raises ValueError if the outline or the node is missing
+  ///
+  fn pyupdate_atclean(py: Python, tid: usize, ni:usize, cont:&str) -> PyResult<String> {
+    let u = match TREES.lock().unwrap().get_mut(&tid) {
+      Some(t) if ni > 0 && ni < t.outline.len() => {
+        let u = reload_atclean(&t.outline, &mut t.nodes, ni, cont);
+        if u.is_empty() { u } else { t.record(Some(u)).unwrap_or_default() }
+      },
+      Some(_) => return Err(PyValueError::new_err("no such node")),
+      None => return Err(PyValueError::new_err("unknown tree id"))
+    };
+    if !u.is_empty() { notify(py, tid, Some(&u), false) }
+    Ok(u)
+  }
+  #[pyfn(m)]
+  #[pyo3(name="subtree_size")]
+  fn subtree_seize(_py:Python, tid:usize, ni:usize) -> PyResult<usize> {
+    match TREES.lock().unwrap().get(&tid).map(|t|{
+      t.outline.subtree_size(ni)
+    }){
+      Some(x) => Ok(x),
+      None => Err(PyValueError::new_err("unknown tree id"))
+    }
+  }
+  #[pyfn(m)]
+  #[pyo3(name="tree_len", text_signature="(tid)")]
+  /// Returns the number of different positions in the
+  /// outline identified by tid.
+  ///
+  /// Raises ValueError if there is no outline (tid).
+  ///
+  fn tree_len(_py:Python, tid:usize) -> PyResult<usize> {
+    match TREES.lock().unwrap().get(&tid).map(|t|{
       let i = t.outline.len();
       if i > 0 {i - 1} else { 0 }
     }){
@@ -756,25 +1770,17 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   /// contain given vnode (* when (ignx, gnx) pair doesn't match
   /// this outline)
   ///
-  fn update_node(_py: Python, tid:usize, v:&VData) -> Option<String> {
-    TREES
+  fn pyupdate_node(py: Python, tid:usize, v:&VData) -> Option<String> {
+    let res = TREES
       .lock()
       .unwrap()
       .get_mut(&tid)
       .and_then(|t|{
-        let i = v.ignx as usize;
-        if i >= t.nodes.len() { return None }
-        let v0 = &mut t.nodes[i];
-        if v0.gnx != v.gnx { return None }
-        let mut buf = "vupd:".to_string();
-        v0.write_to(&mut buf);
-        buf.push('\t');
-        v.write_to(&mut buf);
-        v0.h.replace_range(.., &v.h);
-        v0.b.replace_range(.., &v.b);
-        v0.flags = v.flags;
-        Some(buf)
-      })
+        let res = update_node(&mut t.nodes, v);
+        t.track(res)
+      });
+    notify(py, tid, res.as_deref(), false);
+    res
   }
   #[pyfn(m)]
   #[pyo3(name="valid_operations", text_signature="(tid, p)")]
@@ -800,6 +1806,8 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   #[pyo3(name="visible_nodes", text_signature="(tid)")]
   /// Returns list of tuples for all visible positions in the outline
   /// identified by tid. Each tuple contains (level, p, v)
+  /// If the outline is hoisted, only nodes in the hoisted subtree
+  /// are returned.
   /// 
   fn visible_nodes(_py: Python, tid:usize) -> Option<Vec<(u8, u32,VData)>> {
     TREES
@@ -807,7 +1815,7 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
       .unwrap()
       .get(&tid)
       .map(|t|{
-        let inds = t.outline.visible_indices();
+        let inds = t.hoist.visible_indices(&t.outline);
         let res:Vec<(u8, u32, VData)> = inds
           .iter()
           .map(|x|{let z = t.outline[*x];(z.level(), z.label(), t.nodes[z.ignx() as usize].clone())})
@@ -819,6 +1827,8 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
   #[pyo3(name="visible_positions", text_signature="(tid)")]
   /// Returns list of tuples for all visible positions in the outline
   /// identified by tid. Each tuple contains (level, p, ignx)
+  /// If the outline is hoisted, only nodes in the hoisted subtree
+  /// are returned.
   /// 
   fn visible_positions(_py: Python, tid:usize) -> Option<Vec<(u8, u32, u32)>> {
     TREES
@@ -826,7 +1836,7 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
       .unwrap()
       .get(&tid)
       .map(|t|{
-        let inds = t.outline.visible_indices();
+        let inds = t.hoist.visible_indices(&t.outline);
         let res:Vec<(u8, u32, u32)> = inds
           .iter()
           .map(|x|{let z = t.outline[*x];(z.level(), z.label(), z.ignx())})
@@ -834,6 +1844,562 @@ fn _minileo(_py: Python, m:&PyModule) -> PyResult<()> {
         res
       })
   }
+  #[pyfn(m)]
+  #[pyo3(name="hoist", text_signature="(tid, p)")]
+  /// Hoists node at position p in the outline identified by tid.
+  /// Visible nodes and navigation are limited to the subtree of p
+  /// until it is dehoisted.
+  ///
+  /// Returns False if the tid outline is missing or there isn't a node
+  /// at the given position p
+  fn pyhoist(_py: Python, tid:usize, p:u32) -> bool {
+    match TREES.lock().unwrap().get_mut(&tid) {
+      Some(t) if t.outline.label_index(p).is_some() => { t.hoist.push(p); true },
+      _ => false
+    }
+  }
+  #[pyfn(m)]
+  #[pyo3(name="dehoist", text_signature="(tid)")]
+  /// Removes the last hoist in the outline identified by tid.
+  ///
+  /// Returns position of the node that was hoisted, or None if the
+  /// outline is missing or it wasn't hoisted
+  fn pydehoist(_py: Python, tid:usize) -> Option<u32> {
+    TREES.lock().unwrap().get_mut(&tid).and_then(|t|t.hoist.pop())
+  }
+  #[pyfn(m)]
+  #[pyo3(name="hoisted", text_signature="(tid)")]
+  /// Returns position of the currently hoisted node in the outline
+  /// identified by tid, or None if the outline is not hoisted
+  fn pyhoisted(_py: Python, tid:usize) -> Option<u32> {
+    TREES.lock().unwrap().get(&tid).and_then(|t|t.hoist.hoisted(&t.outline))
+  }
+  #[pyfn(m)]
+  #[pyo3(name="chapters", text_signature="(tid)")]
+  /// Returns list of chapter names in the outline identified by tid.
+  /// The first one is always 'main', the others are found in the
+  /// '@chapter name' children of the '@chapters' node.
+  ///
+  /// Returns None if the tid outline is missing
+  fn pychapters(_py: Python, tid:usize) -> Option<Vec<String>> {
+    TREES.lock().unwrap().get(&tid).map(|t|{
+      let mut res = vec!["main".to_string()];
+      res.extend(chapters(&t.outline, &t.nodes).into_iter().map(|x|x.0));
+      res
+    })
+  }
+  #[pyfn(m)]
+  #[pyo3(name="select_chapter", text_signature="(tid, name)")]
+  /// Selects chapter with the given name in the outline identified by
+  /// tid. Selecting a chapter replaces all hoists; visible nodes are
+  /// then the children of the '@chapter name' node. Chapter 'main'
+  /// shows the whole outline.
+  ///
+  /// Returns False if the outline or chapter is missing
+  fn pyselect_chapter(_py: Python, tid:usize, name:&str) -> bool {
+    match TREES.lock().unwrap().get_mut(&tid) {
+      Some(t) => t.hoist.select_chapter(&t.outline, &t.nodes, name),
+      None => false
+    }
+  }
+  #[pyfn(m)]
+  #[pyo3(name="save_session", text_signature="(tid, leo_fname, p, top)")]
+  /// Writes the session state of the outline identified by tid to the
+  /// sidecar file leo_fname + '.session': expanded nodes, the current
+  /// position p, the position top of the node shown at the top of the
+  /// view (both may be None) and the hoist stack. Nodes are stored as
+  /// paths of child indices and gnxes, so that they can be found again
+  /// after the outline has been changed.
+  ///
+  /// Raises ValueError if the outline is missing and IOError if the
+  /// file can't be written
+  ///
+  fn pysave_session(_py: Python, tid:usize, leo_fname:&str, p:Option<u32>, top:Option<u32>) -> PyResult<()> {
+    let s = match TREES.lock().unwrap().get(&tid) {
+      Some(t) => {
+        let index = |p:Option<u32>|p.and_then(|p|t.outline.label_index(p)).unwrap_or(0);
+        Session::capture(&t.outline, &t.nodes, &t.hoist, index(p), index(top))
+      },
+      None => return Err(PyValueError::new_err("unknown tree id"))
+    };
+    s.save(&session_file(leo_fname)).map_err(|e|PyIOError::new_err(e.to_string()))
+  }
+  #[pyfn(m)]
+  #[pyo3(name="restore_session", text_signature="(tid, leo_fname)")]
+  /// Restores the session state saved by save_session in the outline
+  /// identified by tid. Only the saved nodes remain expanded and the
+  /// hoist stack is replaced. Nodes that can't be found anymore are
+  /// ignored.
+  ///
+  /// Returns a tuple (current, top) of the saved current position and
+  /// the position of the node at the top of the view, each None if it
+  /// is not found.
+  ///
+  /// Raises ValueError if the outline is missing and IOError if the
+  /// file can't be read or isn't a valid session file
+  ///
+  fn pyrestore_session(py: Python, tid:usize, leo_fname:&str) -> PyResult<(Option<u32>, Option<u32>)> {
+    let s = Session::load(&session_file(leo_fname)).map_err(|e|PyIOError::new_err(e.to_string()))?;
+    let res = match TREES.lock().unwrap().get_mut(&tid) {
+      Some(t) => {
+        let (cur, top) = s.apply(&mut t.outline, &t.nodes, &mut t.hoist);
+        let label = |i:usize|t.outline[i].label();
+        (cur.map(label), top.map(label))
+      },
+      None => return Err(PyValueError::new_err("unknown tree id"))
+    };
+    notify_events(py, tid, vec![OutlineEvent::Reset]);
+    Ok(res)
+  }
+  #[pyfn(m)]
+  #[pyo3(name="next_visible", text_signature="(tid, p)")]
+  /// Returns position of the visible node following p in the
+  /// outline identified by tid, staying inside the hoisted subtree.
+  ///
+  /// Returns None if p is the last visible node, or if the outline
+  /// or node is missing
+  fn pynext_visible(_py: Python, tid:usize, p:u32) -> Option<u32> {
+    TREES.lock().unwrap().get(&tid).and_then(|t|{
+      let inds = t.hoist.visible_indices(&t.outline);
+      let k = inds.iter().position(|i|t.outline[*i].label() == p)?;
+      inds.get(k + 1).map(|i|t.outline[*i].label())
+    })
+  }
+  #[pyfn(m)]
+  #[pyo3(name="prev_visible", text_signature="(tid, p)")]
+  /// Returns position of the visible node preceding p in the
+  /// outline identified by tid, staying inside the hoisted subtree.
+  ///
+  /// Returns None if p is the first visible node, or if the outline
+  /// or node is missing
+  fn pyprev_visible(_py: Python, tid:usize, p:u32) -> Option<u32> {
+    TREES.lock().unwrap().get(&tid).and_then(|t|{
+      let inds = t.hoist.visible_indices(&t.outline);
+      let k = inds.iter().position(|i|t.outline[*i].label() == p)?;
+      if k == 0 { None } else { Some(t.outline[inds[k - 1]].label()) }
+    })
+  }
+  #[pyfn(m)]
+  #[pyo3(name="mark", text_signature="(tid, p)")]
+  /// Marks node at position p in the outline identified by tid.
+  ///
+  /// Returns string representation of the performed change, that
+  /// can be later used for undo/redo operations.
+  ///
+  /// Returns None if the outline or node is missing or if the node
+  /// was already marked
+  fn pymark(py: Python, tid:usize, p:u32) -> Option<String> {
+    mark_node(py, tid, p, Some(true))
+  }
+  #[pyfn(m)]
+  #[pyo3(name="unmark", text_signature="(tid, p)")]
+  /// Unmarks node at position p in the outline identified by tid.
+  ///
+  /// Returns string representation of the performed change, that
+  /// can be later used for undo/redo operations.
+  ///
+  /// Returns None if the outline or node is missing or if the node
+  /// was already unmarked
+  fn pyunmark(py: Python, tid:usize, p:u32) -> Option<String> {
+    mark_node(py, tid, p, Some(false))
+  }
+  #[pyfn(m)]
+  #[pyo3(name="toggle_mark", text_signature="(tid, p)")]
+  /// Marks node at position p in the outline identified by tid if it
+  /// is not marked, otherwise unmarks it.
+  ///
+  /// Returns string representation of the performed change, that
+  /// can be later used for undo/redo operations.
+  ///
+  /// Returns None if the outline or node is missing
+  fn pytoggle_mark(py: Python, tid:usize, p:u32) -> Option<String> {
+    mark_node(py, tid, p, None)
+  }
+  #[pyfn(m)]
+  #[pyo3(name="marked_nodes", text_signature="(tid)")]
+  /// Returns list of tuples (p, v) for all marked positions in the
+  /// outline identified by tid, in outline order.
+  ///
+  /// Returns None if the tid outline is missing
+  fn pymarked_nodes(_py: Python, tid:usize) -> Option<Vec<(u32, VData)>> {
+    TREES.lock().unwrap().get(&tid).map(|t|{
+      marked_indices(&t.outline, &t.nodes)
+        .into_iter()
+        .map(|i|(t.outline[i].label(), t.nodes[t.outline[i].ignx() as usize].clone()))
+        .collect()
+    })
+  }
+  #[pyfn(m)]
+  #[pyo3(name="goto_next_marked", text_signature="(tid, p)")]
+  /// Returns the first marked position after p in outline order
+  /// in the outline identified by tid.
+  ///
+  /// Returns None if there are no marked nodes after p, or if the
+  /// outline or node is missing
+  fn pygoto_next_marked(_py: Python, tid:usize, p:u32) -> Option<u32> {
+    TREES.lock().unwrap().get(&tid).and_then(|t|{
+      let i = t.outline.label_index(p)?;
+      next_marked(&t.outline, &t.nodes, i).map(|j|t.outline[j].label())
+    })
+  }
+  #[pyfn(m)]
+  #[pyo3(name="goto_prev_marked", text_signature="(tid, p)")]
+  /// Returns the last marked position before p in outline order
+  /// in the outline identified by tid.
+  ///
+  /// Returns None if there are no marked nodes before p, or if the
+  /// outline or node is missing
+  fn pygoto_prev_marked(_py: Python, tid:usize, p:u32) -> Option<u32> {
+    TREES.lock().unwrap().get(&tid).and_then(|t|{
+      let i = t.outline.label_index(p)?;
+      prev_marked(&t.outline, &t.nodes, i).map(|j|t.outline[j].label())
+    })
+  }
+  #[pyfn(m)]
+  #[pyo3(name="clone_marked", text_signature="(tid)")]
+  /// Inserts new last top level node 'Clones of marked nodes' in the
+  /// outline identified by tid, and puts clones of all marked nodes
+  /// as its children.
+  ///
+  /// Returns tuple (newp, undo_info) if successful.
+  ///
+  /// Returns None if the outline is missing or no node is marked
+  fn pyclone_marked(py: Python, tid:usize) -> Option<(u32, String)> {
+    let res = TREES
+      .lock()
+      .unwrap()
+      .get_mut(&tid)
+      .and_then(|t|{
+        if marked_indices(&t.outline, &t.nodes).is_empty() { return None }
+        let gnx = t.gnxgen.next(&t.nodes);
+        let res = clone_marked(&mut t.outline, &mut t.nodes, &gnx);
+        let s = t.track(res.as_ref().map(|x|x.1.clone()));
+        res.zip(s).map(|(x, s)|(x.0, s))
+      });
+    notify(py, tid, res.as_ref().map(|x|x.1.as_str()), false);
+    res
+  }
+  #[pyfn(m)]
+  #[pyo3(name="save_leo", text_signature="(tid, fname)")]
+  /// Writes the outline identified by tid as Leo document to the
+  /// file with the given name. Expanded and marked nodes are stored
+  /// in the 'a' attribute of vnodes.
+  ///
+  /// Subtrees of @file, @auto and @edit nodes are not written, they
+  /// belong to their external files. Everything else the loaded
+  /// document contained (header, globals, preferences, unknown
+  /// attributes of nodes...) is written back unchanged.
+  ///
+  /// Raises IOError if the file can't be written or ValueError if
+  /// the outline is missing
+  ///
+  fn pysave_leo(_py: Python, tid:usize, fname:&str) -> PyResult<()> {
+    let content = match TREES.lock().unwrap().get(&tid) {
+      Some(t) => to_leo_document(&t.outline, &t.nodes, &t.extras),
+      None => return Err(PyValueError::new_err("unknown tree id"))
+    };
+    std::fs::write(fname, content).map_err(|e|PyIOError::new_err(e.to_string()))
+  }
+  #[pyfn(m)]
+  #[pyo3(name="find_all", text_signature="(tid, pattern, options)")]
+  /// Searches headlines and bodies in the outline identified by tid.
+  /// Options is None or a dict with the following optional keys:
+  ///   regex       - pattern is a regular expression (default False)
+  ///   whole_word  - match only whole words (default False)
+  ///   ignore_case - (default False)
+  ///   head        - search headlines (default True)
+  ///   body        - search bodies (default True)
+  ///   root        - position of the node whose subtree is searched
+  ///
+  /// Cloned nodes are reported only once.
+  /// Returns list of tuples (p, 'h' or 'b', start, end), where start
+  /// and end are character offsets of the match.
+  ///
+  /// Raises ValueError if the outline or root node is missing or if
+  /// the pattern is not a valid regular expression
+  ///
+  fn pyfind_all(_py: Python, tid:usize, pattern:&str, options:Option<&PyDict>)
+      -> PyResult<Vec<(u32, &'static str, usize, usize)>> {
+    match TREES.lock().unwrap().get(&tid) {
+      Some(t) => {
+        let opts = find_options(&t.outline, options)?;
+        let res = find_all(&t.outline, &t.nodes, pattern, &opts)
+          .map_err(|e|PyValueError::new_err(e.0))?;
+        Ok(res.iter()
+              .map(|m|( t.outline[m.index].label()
+                      , if m.in_head { "h" } else { "b" }
+                      , m.start
+                      , m.end))
+              .collect())
+      },
+      None => Err(PyValueError::new_err("unknown tree id"))
+    }
+  }
+  #[pyfn(m)]
+  #[pyo3(name="replace_all", text_signature="(tid, pattern, repl, options)")]
+  /// Replaces every match of the pattern with repl in the outline
+  /// identified by tid. Options are the same as in find_all. If
+  /// the pattern is a regular expression, repl can refer to groups
+  /// like $1 or ${name}.
+  ///
+  /// Returns tuple (count, undo_info), where count is the number of
+  /// changed nodes. All changes are undone/redone in one step.
+  ///
+  /// Raises ValueError if the outline or root node is missing or if
+  /// the pattern is not a valid regular expression
+  ///
+  fn pyreplace_all(py: Python, tid:usize, pattern:&str, repl:&str, options:Option<&PyDict>)
+      -> PyResult<(usize, String)> {
+    let res = match TREES.lock().unwrap().get_mut(&tid) {
+      Some(t) => {
+        let opts = find_options(&t.outline, options)?;
+        let res = replace_all(&t.outline, &mut t.nodes, pattern, repl, &opts)
+          .map_err(|e|PyValueError::new_err(e.0))?;
+        if res.0 > 0 { (res.0, t.record(Some(res.1)).unwrap_or_default()) } else { res }
+      },
+      None => return Err(PyValueError::new_err("unknown tree id"))
+    };
+    if res.0 > 0 { notify(py, tid, Some(&res.1), false) }
+    Ok(res)
+  }
+  #[pyfn(m)]
+  #[pyo3(name="clone_find_all", text_signature="(tid, pattern, options, flatten)")]
+  /// Searches the outline identified by tid like find_all does, and
+  /// inserts new last top level node 'Found: pattern' whose children
+  /// are clones of all found nodes. Unless flatten is True, nodes found
+  /// inside the subtree of another found node are not cloned.
+  ///
+  /// Returns tuple (newp, undo_info), or None if nothing was found.
+  ///
+  /// Raises ValueError if the outline or root node is missing or if
+  /// the pattern is not a valid regular expression
+  ///
+  fn pyclone_find_all(py: Python, tid:usize, pattern:&str, options:Option<&PyDict>, flatten:bool)
+      -> PyResult<Option<(u32, String)>> {
+    let res = match TREES.lock().unwrap().get_mut(&tid) {
+      Some(t) => {
+        let opts = find_options(&t.outline, options)?;
+        let gnx = t.gnxgen.next(&t.nodes);
+        let res = clone_find_all(&mut t.outline, &mut t.nodes, pattern, &opts, flatten, &gnx)
+          .map_err(|e|PyValueError::new_err(e.0))?;
+        res.map(|(p, s)|(p, t.record(Some(s)).unwrap_or_default()))
+      },
+      None => return Err(PyValueError::new_err("unknown tree id"))
+    };
+    if let Some((_, s)) = &res { notify(py, tid, Some(s), false) }
+    Ok(res)
+  }
+  #[pyfn(m)]
+  #[pyo3(name="enable_index", text_signature="(tid, leo_fname)")]
+  /// Builds full-text search index for the outline identified by tid.
+  /// If leo_fname is not None and the index cache leo_fname + '.idx'
+  /// exists, the index is read from it and only changed nodes are
+  /// indexed again. Index is kept up to date by all later operations.
+  ///
+  /// Returns the number of nodes that have been (re)indexed.
+  ///
+  /// Raises ValueError if the outline is missing
+  ///
+  fn pyenable_index(_py: Python, tid:usize, leo_fname:Option<&str>) -> PyResult<usize> {
+    match TREES.lock().unwrap().get_mut(&tid) {
+      Some(t) => {
+        let cached = leo_fname.and_then(|f|{
+          SearchIndex::load(&t.nodes, Path::new(&format!("{}.idx", f))).ok()
+        });
+        let (ix, n) = cached.unwrap_or_else(||{
+          let ix = SearchIndex::build(&t.nodes);
+          let n = ix.len();
+          (ix, n)
+        });
+        t.index = Some(ix);
+        Ok(n)
+      },
+      None => Err(PyValueError::new_err("unknown tree id"))
+    }
+  }
+  #[pyfn(m)]
+  #[pyo3(name="disable_index", text_signature="(tid)")]
+  /// Drops the search index of the outline identified by tid.
+  ///
+  /// Returns False if the outline is missing or it had no index
+  fn pydisable_index(_py: Python, tid:usize) -> bool {
+    TREES.lock().unwrap().get_mut(&tid).and_then(|t|t.index.take()).is_some()
+  }
+  #[pyfn(m)]
+  #[pyo3(name="save_index", text_signature="(tid, leo_fname)")]
+  /// Writes the search index of the outline identified by tid to
+  /// the cache file leo_fname + '.idx'.
+  ///
+  /// Raises ValueError if the outline is missing or has no index,
+  /// and IOError if the file can't be written
+  ///
+  fn pysave_index(_py: Python, tid:usize, leo_fname:&str) -> PyResult<()> {
+    match TREES.lock().unwrap().get(&tid) {
+      Some(Tree { index: Some(ix), nodes, .. }) =>
+        ix.save(nodes, Path::new(&format!("{}.idx", leo_fname)))
+          .map_err(|e|PyIOError::new_err(e.to_string())),
+      Some(_) => Err(PyValueError::new_err("outline has no index")),
+      None => Err(PyValueError::new_err("unknown tree id"))
+    }
+  }
+  #[pyfn(m)]
+  #[pyo3(name="search", text_signature="(tid, query)")]
+  /// Searches the index of the outline identified by tid. All words
+  /// in the query must be present in the headline or body of a node.
+  /// Word ending with '*' matches any word with this prefix, and
+  /// words between double quotes must appear one after another.
+  ///
+  /// Returns list of positions, one for each matching node, in
+  /// outline order.
+  ///
+  /// Raises ValueError if the outline is missing or has no index
+  ///
+  fn pysearch(_py: Python, tid:usize, query:&str) -> PyResult<Vec<u32>> {
+    match TREES.lock().unwrap().get(&tid) {
+      Some(Tree { index: Some(ix), outline, .. }) => {
+        let found:HashSet<u32> = ix.search(query).into_iter().collect();
+        let mut seen:HashSet<u32> = HashSet::new();
+        Ok(outline.iter().skip(1)
+          .filter(|x|found.contains(&x.ignx()) && seen.insert(x.ignx()))
+          .map(|x|x.label())
+          .collect())
+      },
+      Some(_) => Err(PyValueError::new_err("outline has no index")),
+      None => Err(PyValueError::new_err("unknown tree id"))
+    }
+  }
+  #[pyfn(m)]
+  #[pyo3(name="goto_global_line", text_signature="(tid, p, line)")]
+  /// Finds the node owning the given line (starting from 1) of the
+  /// external file written from the at-file or at-clean node at
+  /// position p in the outline identified by tid. At-file nodes are
+  /// mapped using the external file found next to the Leo document.
+  ///
+  /// Returns tuple (position, body_line), where body_line starts
+  /// from 0, or None if the line can't be found.
+  ///
+  /// Raises ValueError if the outline or node is missing or if the
+  /// node is not at-file or at-clean node
+  ///
+  fn pygoto_global_line(_py: Python, tid:usize, p:u32, line:usize) -> PyResult<Option<(u32, usize)>> {
+    match TREES.lock().unwrap().get(&tid) {
+      Some(t) => {
+        let i = t.outline.label_index(p)
+          .ok_or_else(||PyValueError::new_err("no such node"))?;
+        let folder = t.folder.clone().unwrap_or_else(||PathBuf::from("."));
+        let table = line_table(&t.outline, &t.nodes, &folder, i)
+          .ok_or_else(||PyValueError::new_err("not an at-file or at-clean node"))?;
+        Ok(goto_global_line(&table, line).map(|(j, bl)|(t.outline[j].label(), bl)))
+      },
+      None => Err(PyValueError::new_err("unknown tree id"))
+    }
+  }
+  #[pyfn(m)]
+  #[pyo3(name="external_line_for", text_signature="(tid, p, body_line)")]
+  /// Returns the line number (starting from 1) in the external file
+  /// where the given body line (starting from 0) of the node at
+  /// position p is written. The node may be an at-file or at-clean
+  /// node or any node in its subtree. Lines containing `@others` or
+  /// section references are mapped to the start of their expansion.
+  ///
+  /// Returns None if the node is not inside an at-file or at-clean
+  /// node, or if the line can't be found.
+  ///
+  /// Raises ValueError if the outline or node is missing
+  ///
+  fn pyexternal_line_for(_py: Python, tid:usize, p:u32, body_line:usize) -> PyResult<Option<usize>> {
+    match TREES.lock().unwrap().get(&tid) {
+      Some(t) => {
+        let i = t.outline.label_index(p)
+          .ok_or_else(||PyValueError::new_err("no such node"))?;
+        let folder = t.folder.clone().unwrap_or_else(||PathBuf::from("."));
+        Ok(file_node_index(&t.outline, &t.nodes, i)
+          .and_then(|fi|line_table(&t.outline, &t.nodes, &folder, fi))
+          .and_then(|table|external_line(&t.outline, &t.nodes, &table, i, body_line)))
+      },
+      None => Err(PyValueError::new_err("unknown tree id"))
+    }
+  }
+  #[pyfn(m)]
+  #[pyo3(name="find_error_locations", text_signature="(tid, txt)")]
+  /// Finds file locations in the given text, which may be output of
+  /// rustc, gcc or similar compilers, or a Python traceback.
+  ///
+  /// Returns list of tuples (fname, line, location), where location
+  /// is a tuple (position, body_line) if the file is one of the
+  /// at-file or at-clean files of the outline identified by tid,
+  /// otherwise None.
+  ///
+  /// Raises ValueError if the outline is missing
+  ///
+  fn pyfind_error_locations(_py: Python, tid:usize, txt:&str)
+      -> PyResult<Vec<(String, usize, Option<(u32, usize)>)>> {
+    match TREES.lock().unwrap().get(&tid) {
+      Some(t) => {
+        let folder = t.folder.clone().unwrap_or_else(||PathBuf::from("."));
+        Ok(locate_errors(&t.outline, &t.nodes, &folder, txt)
+          .into_iter()
+          .map(|(f, line, loc)|(f, line, loc.map(|(j, bl)|(t.outline[j].label(), bl))))
+          .collect())
+      },
+      None => Err(PyValueError::new_err("unknown tree id"))
+    }
+  }
+  #[pyfn(m)]
+  #[pyo3(name="diff_outlines", text_signature="(tid_a, tid_b)")]
+  /// Compares outline tid_a with outline tid_b, matching nodes by
+  /// their gnx.
+  ///
+  /// Returns json text with a list of changes. Every change is an
+  /// object with keys kind, gnx and h, where kind is one of:
+  ///   inserted, deleted (with parents),
+  ///   moved (with parent, old_index and new_index),
+  ///   reparented (with old_parents and new_parents),
+  ///   clone-added, clone-removed (with parents),
+  ///   head (with old headline),
+  ///   body (with lines prefixed by ' ', '+' or '-').
+  /// Parents are given by their gnx.
+  ///
+  /// Raises ValueError if any of the outlines is missing
+  ///
+  fn pydiff_outlines(_py: Python, tid_a:usize, tid_b:usize) -> PyResult<String> {
+    with_diff(tid_a, tid_b, |d|d.to_json())
+  }
+  #[pyfn(m)]
+  #[pyo3(name="diff_report", text_signature="(tid_a, tid_b)")]
+  /// Same as diff_outlines, but returns human readable report
+  ///
+  fn pydiff_report(_py: Python, tid_a:usize, tid_b:usize) -> PyResult<String> {
+    with_diff(tid_a, tid_b, |d|d.report())
+  }
+  #[pyfn(m)]
+  #[pyo3(name="merge_outlines", text_signature="(tid_base, tid_ours, tid_theirs)")]
+  /// Three way merge of outlines tid_ours and tid_theirs with their
+  /// common ancestor tid_base. Nodes are matched by gnx, bodies are
+  /// merged line by line.
+  ///
+  /// Returns tuple (tid, conflicts), where tid identifies the new
+  /// merged outline and conflicts is a list of tuples (kind, gnx, h).
+  /// If there are conflicts, the merged outline ends with the node
+  /// `Merge conflicts` describing each of them.
+  ///
+  /// Raises ValueError if any of the outlines is missing
+  ///
+  fn pymerge_outlines(_py: Python, tid_base:usize, tid_ours:usize, tid_theirs:usize)
+      -> PyResult<(usize, Vec<(&'static str, String, String)>)> {
+    let mut m = TREES.lock().unwrap();
+    let r = match (m.get(&tid_base), m.get(&tid_ours), m.get(&tid_theirs)) {
+      (Some(b), Some(o), Some(t)) => {
+        let mut g = GnxGenerator::new(o.gnxgen.id());
+        merge_outlines(&b.outline, &b.nodes, &o.outline, &o.nodes, &t.outline, &t.nodes, &mut g)
+      },
+      _ => return Err(PyValueError::new_err("unknown tree id"))
+    };
+    let conflicts = r.conflicts.into_iter().map(|c|(c.kind.name(), c.gnx, c.h)).collect();
+    let tid = m.len();
+    m.insert(tid, Tree::new(r.outline, r.nodes));
+    Ok((tid, conflicts))
+  }
   //m.add_wrapped(wrap_pyfunction!(a_function_from_rust))?;
   m.add("VData", _py.get_type::<VData>())?;
   Ok(())
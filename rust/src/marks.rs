@@ -0,0 +1,80 @@
+use crate::model::{VData, Outline, OutlineOps, LevGnxOps, update_node,
+                   clone_under_new_node};
+
+/// bit in VData.flags which is set for marked nodes
+pub const MARKED:u16 = 2;
+
+/// returns true if vnode v is marked
+pub fn is_marked(v:&VData) -> bool { v.flags & MARKED != 0 }
+
+/// marks or unmarks the vnode with the given ignx.
+/// Returns undo_info or None if the mark is already as requested
+pub fn set_mark(nodes:&mut Vec<VData>, ignx:u32, on:bool) -> Option<String> {
+  let mut v = nodes.get(ignx as usize)?.clone();
+  if is_marked(&v) == on { return None }
+  if on { v.flags |= MARKED } else { v.flags &= !MARKED }
+  update_node(nodes, &v)
+}
+/// returns indices of all nodes whose vnodes are marked, in outline order
+pub fn marked_indices(o:&Outline, nodes:&Vec<VData>) -> Vec<usize> {
+  (1..o.len())
+    .filter(|i|is_marked(&nodes[o[*i].ignx() as usize]))
+    .collect()
+}
+/// returns index of the first marked node after the node at index i
+pub fn next_marked(o:&Outline, nodes:&Vec<VData>, i:usize) -> Option<usize> {
+  (i+1..o.len()).find(|j|is_marked(&nodes[o[*j].ignx() as usize]))
+}
+/// returns index of the last marked node before the node at index i
+pub fn prev_marked(o:&Outline, nodes:&Vec<VData>, i:usize) -> Option<usize> {
+  (1..i).rev().find(|j|is_marked(&nodes[o[*j].ignx() as usize]))
+}
+/// inserts a new node with the given gnx as the last top level node and
+/// links clones of all marked vnodes as its children. Marked nodes which
+/// are inside the subtree of another marked node are not cloned.
+/// Returns label of the new node and undo_info or None if there are no
+/// marked nodes.
+pub fn clone_marked(o:&mut Outline, nodes:&mut Vec<VData>, gnx:&str) -> Option<(u32, String)> {
+  let mut found:Vec<u32> = Vec::new();
+  let mut end = 0;
+  for i in marked_indices(o, nodes) {
+    // skip nodes in the subtree of the last collected node
+    if i < end { continue }
+    end = i + o.subtree_size(i);
+    if !found.contains(&o[i].ignx()) { found.push(o[i].ignx()) }
+  }
+  clone_under_new_node(o, nodes, gnx, "Clones of marked nodes", &found)
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::from_leo_content;
+  use crate::writing::to_leo_content;
+  use crate::model::check_clones;
+  #[test]
+  fn test_marks() {
+    let (mut o, mut nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="a" a="M"><vh>A</vh><v t="b" a="EM"><vh>B</vh></v></v>
+<v t="c"><vh>C</vh><v t="d"><vh>D</vh></v></v>
+</vnodes><tnodes></tnodes></leo_file>"#);
+    assert_eq!(marked_indices(&o, &nodes), vec![1, 2]);
+    let s = set_mark(&mut nodes, o[4].ignx(), true).unwrap();
+    assert!(s.starts_with("vupd:"));
+    assert!(set_mark(&mut nodes, o[4].ignx(), true).is_none());
+    assert_eq!(next_marked(&o, &nodes, 2), Some(4));
+    assert_eq!(prev_marked(&o, &nodes, 4), Some(2));
+    assert_eq!(prev_marked(&o, &nodes, 1), None);
+
+    let xml = to_leo_content(&o, &nodes, 0);
+    assert!(xml.contains("<v t=\"d\" a=\"M\">"));
+    let (o2, nodes2) = from_leo_content(&xml);
+    assert_eq!(marked_indices(&o2, &nodes2), vec![1, 2, 4]);
+
+    let (label, _) = clone_marked(&mut o, &mut nodes, "m").unwrap();
+    let i = o.label_index(label).unwrap();
+    assert_eq!(nodes[o[i].ignx() as usize].h, "Clones of marked nodes");
+    // b is inside a, so only a and d are cloned
+    assert_eq!(o.children(i), vec![o[1].ignx(), o[4].ignx()]);
+    assert_eq!(check_clones(&o, &nodes), None);
+  }
+}
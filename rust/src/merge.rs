@@ -0,0 +1,394 @@
+use crate::model::{VData, Outline, OutlineOps, LevGnxOps};
+use crate::diff::{lcs, ROOT_GNX};
+use crate::gnx::GnxGenerator;
+use std::collections::{HashMap, HashSet};
+
+/// Kind of conflict found by merge_outlines
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ConflictKind {
+  /// headline changed differently on both sides, ours is kept
+  Headline,
+  /// the same body lines changed on both sides, body has conflict markers
+  Body,
+  /// node moved to different parents on both sides, ours are kept
+  Moved,
+  /// node deleted on one side and changed on the other, it is kept.
+  /// A node put under a parent that was deleted on the other side is
+  /// put under the conflict node.
+  DeletedChanged,
+  /// moves on both sides made the node its own ancestor, it is put
+  /// under the conflict node
+  Cycle
+}
+impl ConflictKind {
+  pub fn name(&self) -> &'static str {
+    match self {
+      ConflictKind::Headline => "headline",
+      ConflictKind::Body => "body",
+      ConflictKind::Moved => "moved",
+      ConflictKind::DeletedChanged => "deleted-changed",
+      ConflictKind::Cycle => "cycle"
+    }
+  }
+}
+#[derive(Debug, PartialEq, Clone)]
+pub struct MergeConflict {
+  pub gnx: String,
+  pub h: String,
+  pub kind: ConflictKind,
+  pub details: String
+}
+/// Result of merge_outlines. If there are conflicts, outline ends with
+/// a top level node `Merge conflicts` with a child describing each one.
+#[derive(Debug)]
+pub struct MergeResult {
+  pub outline: Outline,
+  pub nodes: Vec<VData>,
+  pub conflicts: Vec<MergeConflict>
+}
+pub const CONFLICTS_HEADLINE:&str = "Merge conflicts";
+
+/// merges changes of ours and theirs to base line by line.
+/// Returns merged text and true if there were conflicts, which are
+/// written between conflict markers like git does.
+pub fn merge_lines(base:&str, ours:&str, theirs:&str) -> (String, bool) {
+  let lb:Vec<&str> = base.split_inclusive('\n').collect();
+  let lo:Vec<&str> = ours.split_inclusive('\n').collect();
+  let lt:Vec<&str> = theirs.split_inclusive('\n').collect();
+  let mo:HashMap<usize, usize> = lcs(&lb, &lo).into_iter().collect();
+  let mt:HashMap<usize, usize> = lcs(&lb, &lt).into_iter().collect();
+  // base lines unchanged on both sides split texts into chunks
+  let mut syncs:Vec<(usize, usize, usize)> = (0..lb.len())
+    .filter_map(|i|Some((i, *mo.get(&i)?, *mt.get(&i)?)))
+    .collect();
+  syncs.push((lb.len(), lo.len(), lt.len()));
+  let mut res = String::new();
+  let mut conflict = false;
+  let (mut b0, mut o0, mut t0) = (0, 0, 0);
+  for (b1, o1, t1) in syncs {
+    let (cb, co, ct) = (&lb[b0..b1], &lo[o0..o1], &lt[t0..t1]);
+    if co == ct || co == cb {
+      res.push_str(&ct.concat());
+    } else if ct == cb {
+      res.push_str(&co.concat());
+    } else {
+      conflict = true;
+      for (m, part) in [("<<<<<<< ours\n", co), ("=======\n", ct)].iter() {
+        res.push_str(m);
+        res.push_str(&part.concat());
+        if !res.ends_with('\n') { res.push('\n') }
+      }
+      res.push_str(">>>>>>> theirs\n");
+    }
+    if b1 < lb.len() { res.push_str(lb[b1]) }
+    b0 = b1 + 1;
+    o0 = o1 + 1;
+    t0 = t1 + 1;
+  }
+  (res, conflict)
+}
+// vnodes, children and sorted parents by gnx
+struct Side<'a> {
+  v: HashMap<&'a str, &'a VData>,
+  children: HashMap<&'a str, Vec<&'a str>>,
+  parents: HashMap<&'a str, Vec<&'a str>>,
+  order: Vec<&'a str>
+}
+impl<'a> Side<'a> {
+  fn new(o:&Outline, nodes:&'a Vec<VData>) -> Self {
+    let mut s = Side { v: HashMap::new(), children: HashMap::new(), parents: HashMap::new(), order: Vec::new() };
+    let mut seen:HashSet<u32> = HashSet::new();
+    for (i, x) in o.iter().enumerate() {
+      if !seen.insert(x.ignx()) { continue }
+      let p = &nodes[x.ignx() as usize];
+      let pgnx = if i == 0 { ROOT_GNX } else { p.gnx.as_str() };
+      if i > 0 {
+        s.v.insert(pgnx, p);
+        s.order.push(pgnx);
+      }
+      let ch:Vec<&str> = o.children(i).into_iter().map(|c|nodes[c as usize].gnx.as_str()).collect();
+      for c in ch.iter() {
+        s.parents.entry(c).or_insert_with(Vec::new).push(pgnx);
+      }
+      s.children.insert(pgnx, ch);
+    }
+    for ps in s.parents.values_mut() { ps.sort() }
+    s
+  }
+  fn parents(&self, gnx:&str) -> Vec<&'a str> {
+    self.parents.get(gnx).cloned().unwrap_or_default()
+  }
+}
+// three way merge of a value, None means conflict
+fn merge3<T:PartialEq + Clone>(base:&T, ours:&T, theirs:&T) -> Option<T> {
+  if ours == theirs || theirs == base { Some(ours.clone()) }
+  else if ours == base { Some(theirs.clone()) }
+  else { None }
+}
+// orders kids following primary order, and inserting the rest after
+// their predecessor in secondary order
+fn order_children<'a>(kids:Vec<&'a str>, primary:&[&'a str], secondary:&[&'a str]) -> Vec<&'a str> {
+  let mut rest = kids;
+  let take = |g:&str, rest:&mut Vec<&'a str>| match rest.iter().position(|x|*x == g) {
+    Some(k) => Some(rest.remove(k)),
+    None => None
+  };
+  let mut res:Vec<&str> = primary.iter().filter_map(|g|take(g, &mut rest)).collect();
+  for (k, g) in secondary.iter().enumerate() {
+    if let Some(g) = take(g, &mut rest) {
+      let at = secondary[..k].iter().rev()
+        .find_map(|p|res.iter().rposition(|x|x == p))
+        .map(|i|i + 1)
+        .unwrap_or(0);
+      res.insert(at, g);
+    }
+  }
+  res.extend(rest);
+  res
+}
+/// three way merge of outlines ours and theirs with their common
+/// ancestor base. Nodes are matched by gnx, headlines are merged as
+/// a whole and bodies line by line. New gnx for conflict nodes are
+/// generated by g.
+pub fn merge_outlines(ob:&Outline, nb:&Vec<VData>, oo:&Outline, no:&Vec<VData>,
+    ot:&Outline, nt:&Vec<VData>, g:&mut GnxGenerator) -> MergeResult {
+  let (sb, so, st) = (Side::new(ob, nb), Side::new(oo, no), Side::new(ot, nt));
+  let mut conflicts:Vec<MergeConflict> = Vec::new();
+  let mut conflict = |gnx:&str, h:&str, kind:ConflictKind, details:String| {
+    conflicts.push(MergeConflict { gnx: gnx.to_string(), h: h.to_string(), kind, details });
+  };
+  // merged vnodes and their parents
+  let mut merged:HashMap<&str, VData> = HashMap::new();
+  let mut parents:HashMap<&str, Vec<&str>> = HashMap::new();
+  let mut all:Vec<&str> = so.order.clone();
+  all.extend(st.order.iter().filter(|x|!so.v.contains_key(*x)));
+  for gnx in all {
+    let (vb, vo, vt) = (sb.v.get(gnx), so.v.get(gnx), st.v.get(gnx));
+    let (pb, po, pt) = (sb.parents(gnx), so.parents(gnx), st.parents(gnx));
+    let changed = |v:Option<&&VData>| match (vb, v) {
+      (Some(b), Some(v)) => b.h != v.h || b.b != v.b,
+      (None, Some(_)) => true,
+      _ => false
+    };
+    let (v, ps) = match (vo, vt) {
+      (Some(a), None) | (None, Some(a)) if vb.is_some() => {
+        // deleted on one side
+        let (ps, cv) = if vo.is_some() { (&po, vo) } else { (&pt, vt) };
+        if !changed(cv) { continue }
+        conflict(gnx, &a.h, ConflictKind::DeletedChanged,
+                 format!("'{}' was deleted on one side and changed on the other", a.h));
+        ((*a).clone(), ps.clone())
+      },
+      (Some(a), None) => ((*a).clone(), po.clone()),
+      (None, Some(a)) => ((*a).clone(), pt.clone()),
+      (Some(a), Some(b)) => {
+        let mut v = (*a).clone();
+        let base = vb.copied().unwrap_or(a);
+        match merge3(&base.h, &a.h, &b.h) {
+          Some(h) => v.h = h,
+          None => conflict(gnx, &a.h, ConflictKind::Headline,
+                           format!("ours: {}\ntheirs: {}\n", a.h, b.h))
+        }
+        if a.b != b.b {
+          let (txt, c) = merge_lines(&base.b, &a.b, &b.b);
+          if c {
+            conflict(gnx, &v.h, ConflictKind::Body,
+                     format!("conflicting changes in the body of '{}'\n", v.h));
+          }
+          v.b = txt;
+        }
+        let ps = match merge3(&pb, &po, &pt) {
+          Some(ps) => ps,
+          None => {
+            conflict(gnx, &v.h, ConflictKind::Moved,
+                     format!("ours: {}\ntheirs: {}\n", po.join(", "), pt.join(", ")));
+            po.clone()
+          }
+        };
+        (v, ps)
+      },
+      (None, None) => continue
+    };
+    merged.insert(gnx, v);
+    parents.insert(gnx, ps);
+  }
+  // children of every parent in the merged outline
+  let mut kids:HashMap<&str, Vec<&str>> = HashMap::new();
+  for (gnx, ps) in parents.iter() {
+    for p in ps.iter().filter(|p|**p == ROOT_GNX || merged.contains_key(*p)) {
+      kids.entry(p).or_insert_with(Vec::new).push(gnx);
+    }
+  }
+  let empty:Vec<&str> = Vec::new();
+  let mut children:HashMap<&str, Vec<&str>> = HashMap::new();
+  for (p, ks) in kids {
+    let (cb, co, ct) = (sb.children.get(p), so.children.get(p).unwrap_or(&empty),
+                        st.children.get(p).unwrap_or(&empty));
+    let (prim, sec) = if cb == Some(co) { (ct, co) } else { (co, ct) };
+    children.insert(p, order_children(ks, prim, sec));
+  }
+  // build the outline
+  let mut nodes = vec![VData::new(ROOT_GNX)];
+  nodes[0].h.push_str("<hidden root vnode>");
+  let mut outline:Outline = vec![0u64];
+  let mut ignxs:HashMap<&str, u32> = HashMap::new();
+  fn add<'a>(gnx:&'a str, lev:u8, path:&mut Vec<&'a str>, o:&mut Outline, nodes:&mut Vec<VData>,
+             ignxs:&mut HashMap<&'a str, u32>, merged:&HashMap<&'a str, VData>,
+             children:&HashMap<&'a str, Vec<&'a str>>) {
+    if path.contains(&gnx) { return }
+    let ignx = *ignxs.entry(gnx).or_insert_with(|| {
+      let mut v = merged[gnx].clone();
+      v.ignx = nodes.len() as u32;
+      nodes.push(v);
+      nodes.len() as u32 - 1
+    });
+    if o.add_node(lev, ignx).unwrap_or(true) { return }
+    path.push(gnx);
+    for c in children.get(gnx).map(|x|x.as_slice()).unwrap_or(&[]) {
+      add(c, lev + 1, path, o, nodes, ignxs, merged, children);
+    }
+    path.pop();
+  }
+  let mut path = vec![ROOT_GNX];
+  for c in children.get(ROOT_GNX).map(|x|x.as_slice()).unwrap_or(&[]) {
+    add(c, 1, &mut path, &mut outline, &mut nodes, &mut ignxs, &merged, &children);
+  }
+  let mut seen:HashSet<&str> = HashSet::new();
+  let lost:Vec<&str> = so.order.iter().chain(st.order.iter())
+    .copied()
+    .filter(|x|merged.contains_key(x) && !ignxs.contains_key(x) && seen.insert(x))
+    .collect();
+  // nodes whose parents were all deleted on the other side, and their
+  // descendants which are placed together with them
+  let orphans:Vec<&str> = lost.iter().copied()
+    .filter(|x|parents[x].iter().all(|p|*p != ROOT_GNX && !merged.contains_key(p)))
+    .collect();
+  let mut placed:HashSet<&str> = HashSet::new();
+  let mut stack = orphans.clone();
+  while let Some(gnx) = stack.pop() {
+    if !placed.insert(gnx) { continue }
+    stack.extend(children.get(gnx).map(|x|x.as_slice()).unwrap_or(&[]));
+  }
+  for gnx in lost.iter() {
+    let h = &merged[gnx].h;
+    if orphans.contains(gnx) {
+      let heads:Vec<&str> = parents[gnx].iter()
+        .filter_map(|p|sb.v.get(p).or_else(||so.v.get(p)).or_else(||st.v.get(p)))
+        .map(|v|v.h.as_str())
+        .collect();
+      conflict(gnx, h, ConflictKind::DeletedChanged,
+               format!("'{}' was put under '{}', which was deleted on the other side\n",
+                       h, heads.join("', '")));
+    } else if !placed.contains(gnx) {
+      conflict(gnx, h, ConflictKind::Cycle, format!("'{}' has become its own ancestor\n", h));
+    }
+  }
+  if conflicts.is_empty() {
+    return MergeResult { outline, nodes, conflicts };
+  }
+  // conflict nodes
+  let top = g.next(&nodes);
+  let mut v = VData::new(&top);
+  v.h.push_str(CONFLICTS_HEADLINE);
+  v.ignx = nodes.len() as u32;
+  nodes.push(v);
+  outline.add_node(1, nodes.len() as u32 - 1).unwrap();
+  for c in conflicts.iter() {
+    let mut v = VData::new(&g.next(&nodes));
+    v.h = format!("{}: {}", c.kind.name(), c.h);
+    v.b = c.details.clone();
+    v.ignx = nodes.len() as u32;
+    nodes.push(v);
+    outline.add_node(2, nodes.len() as u32 - 1).unwrap();
+    if (c.kind == ConflictKind::Cycle || c.kind == ConflictKind::DeletedChanged)
+        && lost.contains(&c.gnx.as_str()) && !ignxs.contains_key(c.gnx.as_str()) {
+      let gnx = lost.iter().find(|x|**x == c.gnx).unwrap();
+      let mut path = vec![ROOT_GNX];
+      add(gnx, 3, &mut path, &mut outline, &mut nodes, &mut ignxs, &merged, &children);
+    }
+  }
+  MergeResult { outline, nodes, conflicts }
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::from_leo_content;
+  fn leo(vnodes:&str, tnodes:&str) -> (Outline, Vec<VData>) {
+    from_leo_content(&format!("<leo_file><vnodes>{}</vnodes><tnodes>{}</tnodes></leo_file>",
+                              vnodes, tnodes))
+  }
+  fn heads(r:&MergeResult) -> Vec<String> {
+    r.outline.iter().skip(1)
+      .map(|x|format!("{}{}", " ".repeat(x.level() as usize - 1), r.nodes[x.ignx() as usize].h))
+      .collect()
+  }
+  #[test]
+  fn test_merge_lines() {
+    assert_eq!(merge_lines("a\nb\nc\n", "A\nb\nc\n", "a\nb\nC\n"), ("A\nb\nC\n".to_string(), false));
+    let (txt, c) = merge_lines("a\nb\n", "x\nb\n", "y\nb\n");
+    assert!(c);
+    assert_eq!(txt, "<<<<<<< ours\nx\n=======\ny\n>>>>>>> theirs\nb\n");
+  }
+  #[test]
+  fn test_merge_outlines() {
+    let base = leo(r#"<v t="a"><vh>A</vh><v t="b"><vh>B</vh></v></v><v t="c"><vh>C</vh></v>
+<v t="d"><vh>D</vh></v>"#, r#"<t tx="b">1
+2
+3
+</t>"#);
+    let ours = leo(r#"<v t="a"><vh>A1</vh><v t="b"><vh>B</vh></v><v t="e"><vh>E</vh></v></v>
+<v t="c"><vh>C</vh></v><v t="d"><vh>D</vh></v>"#, r#"<t tx="b">one
+2
+3
+</t>"#);
+    let theirs = leo(r#"<v t="c"><vh>C</vh><v t="d"><vh>D</vh></v></v>
+<v t="a"><vh>A</vh><v t="b"><vh>B</vh></v></v><v t="f"><vh>F</vh></v>"#, r#"<t tx="b">1
+2
+three
+</t>"#);
+    let mut g = GnxGenerator::new("test");
+    let r = merge_outlines(&base.0, &base.1, &ours.0, &ours.1, &theirs.0, &theirs.1, &mut g);
+    assert_eq!(r.conflicts, vec![]);
+    // top level order is theirs, because ours hasn't changed it
+    assert_eq!(heads(&r), vec!["C", " D", "A1", " B", " E", "F"]);
+    assert_eq!(r.nodes[r.outline[4].ignx() as usize].b, "one\n2\nthree\n");
+
+    // moved to different parents and edited the same line
+    let theirs = leo(r#"<v t="a"><vh>A</vh></v><v t="c"><vh>C</vh><v t="b"><vh>B</vh></v></v>
+<v t="d"><vh>D</vh></v>"#, r#"<t tx="b">uno
+2
+3
+</t>"#);
+    let ours = leo(r#"<v t="a"><vh>A</vh></v><v t="c"><vh>C</vh></v>
+<v t="d"><vh>D</vh><v t="b"><vh>B</vh></v></v>"#, r#"<t tx="b">one
+2
+3
+</t>"#);
+    let r = merge_outlines(&base.0, &base.1, &ours.0, &ours.1, &theirs.0, &theirs.1, &mut g);
+    let kinds:Vec<ConflictKind> = r.conflicts.iter().map(|c|c.kind).collect();
+    assert_eq!(kinds, vec![ConflictKind::Body, ConflictKind::Moved]);
+    assert_eq!(heads(&r), vec!["A", "C", "D", " B", CONFLICTS_HEADLINE, " body: B", " moved: B"]);
+  }
+  #[test]
+  fn test_merge_lost_nodes() {
+    let base = leo(r#"<v t="a"><vh>A</vh></v><v t="b"><vh>B</vh></v><v t="c"><vh>C</vh></v>"#, "");
+    // b moved under a on one side and a under b on the other
+    let ours = leo(r#"<v t="a"><vh>A</vh><v t="b"><vh>B</vh></v></v><v t="c"><vh>C</vh></v>"#, "");
+    let theirs = leo(r#"<v t="b"><vh>B</vh><v t="a"><vh>A</vh></v></v><v t="c"><vh>C</vh></v>"#, "");
+    let mut g = GnxGenerator::new("test");
+    let r = merge_outlines(&base.0, &base.1, &ours.0, &ours.1, &theirs.0, &theirs.1, &mut g);
+    let kinds:Vec<(ConflictKind, &str)> = r.conflicts.iter().map(|c|(c.kind, c.gnx.as_str())).collect();
+    assert_eq!(kinds, vec![(ConflictKind::Cycle, "a"), (ConflictKind::Cycle, "b")]);
+    assert_eq!(heads(&r), vec!["C", CONFLICTS_HEADLINE, " cycle: A", "  A", "   B", " cycle: B"]);
+
+    // c deleted on one side and got a new child with its own child on the other
+    let ours = leo(r#"<v t="a"><vh>A</vh></v><v t="b"><vh>B</vh></v>"#, "");
+    let theirs = leo(r#"<v t="a"><vh>A</vh></v><v t="b"><vh>B</vh></v>
+<v t="c"><vh>C</vh><v t="d"><vh>D</vh><v t="e"><vh>E</vh></v></v></v>"#, "");
+    let r = merge_outlines(&base.0, &base.1, &ours.0, &ours.1, &theirs.0, &theirs.1, &mut g);
+    assert_eq!(r.conflicts.len(), 1);
+    assert_eq!(r.conflicts[0].kind, ConflictKind::DeletedChanged);
+    assert_eq!(r.conflicts[0].details, "'D' was put under 'C', which was deleted on the other side\n");
+    assert_eq!(heads(&r), vec!["A", "B", CONFLICTS_HEADLINE, " deleted-changed: D", "  D", "   E"]);
+  }
+}
@@ -7,6 +7,7 @@ use pyo3::prelude::*;
 use std::path::{ Path};
 use std::error::Error;
 use std::fmt;
+use std::cmp::Ordering;
 pub type LevGnx = u64;
 pub trait LevGnxOps {
   /// returns level of this node
@@ -380,6 +381,7 @@ pub fn gnx_index(nodes:&Vec<VData>) -> HashMap<&str, u32> {
   res
 }
 #[pyclass]
+#[derive(Debug, PartialEq, Clone)]
 pub struct VData {
   #[pyo3(get)]
   pub gnx: String,
@@ -422,58 +424,61 @@ impl VData {
   pub fn section_ref(&self) -> Option<&str> {
     extract_section_ref(self.h.as_str())
   }
+  /// appends ignx and flags, gnx, headline and body separated by NUL
+  /// characters. Backslashes, tabs, newlines, carriage returns and NUL
+  /// characters in the texts are escaped, so the result fits on one
+  /// line of a change record.
   pub fn write_to(&self, buf:&mut String) {
     let z = 0 as char;
     let u:u64 = (self.ignx as u64) | ((self.flags as u64) << 32);
     b64write(u, buf);
     buf.push(z);
-    buf.push_str(&self.gnx);
+    escape_field(&self.gnx, buf);
     buf.push(z);
-    buf.push_str(&self.h);
+    escape_field(&self.h, buf);
     buf.push(z);
-    for c in self.b.escape_debug() { buf.push(c) }
+    escape_field(&self.b, buf);
   }
   pub fn from_str(s:&str) -> VData {
     let mut it = s.split(0 as char);
     let u = b64int(it.next().unwrap());
     let ignx = (u & 0xFFFF_FFFF) as u32;
     let flags = ((u >> 32) & 0xFFFF) as u16;
-    let gnx = it.next().unwrap();
-    let h = it.next().unwrap();
-    let mut a = [0 as char];
-    let b:String = it
-      .next()
-      .unwrap()
-      .chars()
-      .map(|x|{
-        if a[0] != '\\' {
-          a[0]=x;
-          if x == '\\' {
-            0 as char
-          } else {
-            x
-          }
-        } else {
-          a[0] = 1 as char;
-          match x {
-            'r' => '\r',
-            'n' => '\n',
-            't' => '\t',
-            y => y
-          }
-        }
-      })
-      .filter(|x| *x != (0 as char))
-      .collect();
-
-    VData {
-      gnx: gnx.to_string(),
-      h: h.to_string(),
-      b: b,
-      flags: flags,
-      ignx: ignx,
+    let gnx = unescape_field(it.next().unwrap());
+    let h = unescape_field(it.next().unwrap());
+    let b = unescape_field(it.next().unwrap());
+    VData { gnx, h, b, flags, ignx }
+  }
+}
+fn escape_field(s:&str, buf:&mut String) {
+  for c in s.chars() {
+    match c {
+      '\\' => buf.push_str("\\\\"),
+      '\t' => buf.push_str("\\t"),
+      '\n' => buf.push_str("\\n"),
+      '\r' => buf.push_str("\\r"),
+      '\0' => buf.push_str("\\0"),
+      _ => buf.push(c)
+    }
+  }
+}
+// reverts escape_field. Other escaped characters are taken as they
+// are, which also decodes the quotes escaped by older versions.
+fn unescape_field(s:&str) -> String {
+  let mut res = String::with_capacity(s.len());
+  let mut chars = s.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' { res.push(c); continue }
+    match chars.next() {
+      Some('r') => res.push('\r'),
+      Some('n') => res.push('\n'),
+      Some('t') => res.push('\t'),
+      Some('0') => res.push('\0'),
+      Some(y) => res.push(y),
+      None => ()
     }
   }
+  res
 }
 #[test]
 fn test_vdata_from_str() {
@@ -492,6 +497,17 @@ fn test_vdata_from_str() {
   assert_eq!(v.b, v2.b, "b not ok");
   assert_eq!(v.ignx, v2.ignx, "ignx not ok");
   assert_eq!(v.flags, v2.flags, "flags not ok");
+  let v = VData {
+    gnx: "a\tb\\c".to_string(),
+    ignx: 2,
+    h: "tab\there\nnewline \\t".to_string(),
+    b: "\u{301}quote ' \" nul \0 \r\n".to_string(),
+    flags: 0,
+  };
+  let mut buf = String::new();
+  v.write_to(&mut buf);
+  assert!(!buf.contains('\t') && !buf.contains('\n'));
+  assert_eq!(VData::from_str(&buf), v);
 }
 pub fn find_any_file_nodes(
     folder:&Path,
@@ -817,6 +833,12 @@ pub fn valid_operations(o:&Outline, i:usize) -> String {
     if valid_down {
       buf.push_str("down,");
     }
+    if can_promote(o, i) {
+      buf.push_str("promote,");
+    }
+    if can_demote(o, i) {
+      buf.push_str("demote,");
+    }
   }
   buf.pop();
   buf
@@ -977,14 +999,323 @@ pub fn delete_node(o:&mut Outline, i:usize) -> Option<String> {
   let ci = o.child_index(i);
   break_link(o, pgnx, ci)
 }
+/// moves the node at index i so that it becomes the child of the
+/// vnode npignx at index child_index (among the children it will have
+/// after the move). Every clone of the old and the new parent is updated.
+/// Returns label of the moved node under the first occurrence of the new
+/// parent and undo_info, or None if the new parent is in the subtree of
+/// the moved node or child_index is out of range
+pub fn move_node_to(o:&mut Outline, i:usize, npignx:u32, child_index:usize) -> Option<(u32, String)> {
+  if i == 0 || i >= o.len() { return None }
+  let sz = o.subtree_size(i);
+  if o[i..i+sz].iter().any(|x|x.ignx() == npignx) { return None }
+  let npi = o.find(npignx)?;
+  let pi = o.parent_index(i);
+  let pignx = o[pi].ignx();
+  let ignx = o[i].ignx();
+  let ci = o.child_index(i);
+  let n = o.children(npi).len() - if pignx == npignx { 1 } else { 0 };
+  if child_index > n { return None }
+  let (a, b) = if pignx != npignx || child_index < ci {
+    // inserting before the old position shifts it by one
+    (child_index, if pignx == npignx { ci + 1 } else { ci })
+  } else {
+    (child_index + 1, ci)
+  };
+  let s1 = create_link(o, npignx, a, ignx)?;
+  let s2 = break_link(o, pignx, b)?;
+  let npi = o.find(npignx)?;
+  let lev = o[npi].level() + 1;
+  let label = o[npi+1..].iter()
+    .take_while(|x|x.level() >= lev)
+    .filter(|x|x.level() == lev)
+    .nth(child_index)?
+    .label();
+  Some((label, format!("{}\n{}", s1, s2)))
+}
+#[test]
+fn test_move_node_to() {
+  let (mut o, nodes) = crate::parsing::from_leo_content(r#"<leo_file><vnodes>
+<v t="p"><vh>P</vh><v t="a"><vh>A</vh></v><v t="b"><vh>B</vh></v><v t="c"><vh>C</vh></v></v>
+<v t="q"><vh>Q</vh><v t="p"/></v>
+</vnodes><tnodes></tnodes></leo_file>"#);
+  let o1 = o.clone();
+  let order = |o:&Outline| -> String {
+    o.iter().skip(1).map(|x|nodes[x.ignx() as usize].h.as_str()).collect()
+  };
+  let (p, s) = move_node_to(&mut o, 2, 1, 2).unwrap();
+  assert_eq!(order(&o), "PBCAQPBCA");
+  assert_eq!(o.label_index(p), Some(4));
+  assert_eq!(check_clones(&o, &nodes), None);
+  for x in s.lines().rev() {
+    if x.starts_with("ip:") { undo_insert_parts(&mut o, x) } else { undo_delete_blocks(&mut o, x) }
+  }
+  assert_eq!(o, o1);
+  move_node_to(&mut o, 4, 1, 0).unwrap();
+  assert_eq!(order(&o), "PCABQPCAB");
+  // c goes under q, after p
+  let q = o[5].ignx();
+  move_node_to(&mut o, 2, q, 1).unwrap();
+  assert_eq!(order(&o), "PABQPABC");
+  // p can't be moved inside its own subtree or beyond the end
+  let a = o[2].ignx();
+  assert!(move_node_to(&mut o, 1, a, 0).is_none());
+  assert!(move_node_to(&mut o, 2, 0, 5).is_none());
+}
+/// makes all children of the node at index i its following siblings.
+/// Returns undo_info or None if the node has no children or if
+/// the operation would make a node its own descendant
+pub fn promote(o:&mut Outline, i:usize) -> Option<String> {
+  let ch = o.children(i);
+  if !can_promote(o, i) { return None }
+  let pi = o.parent_index(i);
+  let pignx = o[pi].ignx();
+  let ignx = o[i].ignx();
+  let ci = o.child_index(i);
+  let mut res:Vec<String> = Vec::with_capacity(2 * ch.len());
+  for c in ch.iter().rev() {
+    res.push(create_link(o, pignx, ci + 1, *c)?);
+  }
+  for _ in ch.iter() {
+    res.push(break_link(o, ignx, 0)?);
+  }
+  Some(res.join("\n"))
+}
+fn can_promote(o:&Outline, i:usize) -> bool {
+  let sz = o.subtree_size(i);
+  let pignx = o[o.parent_index(i)].ignx();
+  sz > 1 && o[i+1..i+sz].iter().all(|x|x.ignx() != pignx)
+}
+/// makes all following siblings of the node at index i its children.
+/// Returns undo_info or None if the node has no following siblings or
+/// if the operation would make a node its own descendant
+pub fn demote(o:&mut Outline, i:usize) -> Option<String> {
+  if !can_demote(o, i) { return None }
+  let pi = o.parent_index(i);
+  let pignx = o[pi].ignx();
+  let ignx = o[i].ignx();
+  let ci = o.child_index(i);
+  let n = o.children(i).len();
+  let sibs:Vec<u32> = o.children(pi).into_iter().skip(ci + 1).collect();
+  let mut res:Vec<String> = Vec::with_capacity(2 * sibs.len());
+  for (k, c) in sibs.iter().enumerate() {
+    res.push(create_link(o, ignx, n + k, *c)?);
+  }
+  for _ in sibs.iter() {
+    res.push(break_link(o, pignx, ci + 1)?);
+  }
+  Some(res.join("\n"))
+}
+fn can_demote(o:&Outline, i:usize) -> bool {
+  let lev = o[i].level();
+  let ignx = o[i].ignx();
+  let a = i + o.subtree_size(i);
+  let b = a + o[a..].iter().take_while(|x|x.level() >= lev).count();
+  a < b && o[a..b].iter().all(|x|x.ignx() != ignx)
+}
+#[test]
+fn test_promote_demote() {
+  let (mut o, nodes) = crate::parsing::from_leo_content(r#"<leo_file><vnodes>
+<v t="p"><vh>P</vh><v t="a"><vh>A</vh><v t="b"><vh>B</vh></v><v t="c"><vh>C</vh></v></v><v t="d"><vh>D</vh></v></v>
+<v t="q"><vh>Q</vh><v t="p"/></v>
+</vnodes><tnodes></tnodes></leo_file>"#);
+  let o1 = o.clone();
+  let hs = |o:&Outline| -> Vec<(u8, String)> {
+    o.iter().skip(1).map(|x|(x.level(), nodes[x.ignx() as usize].h.clone())).collect()
+  };
+  let s = promote(&mut o, 2).unwrap();
+  let lv:Vec<u8> = hs(&o).iter().map(|x|x.0).collect();
+  assert_eq!(lv, [1, 2, 2, 2, 2, 1, 2, 3, 3, 3, 3]);
+  assert_eq!(hs(&o)[2].1, "B");
+  assert_eq!(hs(&o)[4].1, "D");
+  assert_eq!(check_clones(&o, &nodes), None);
+  assert!(promote(&mut o, 2).is_none());
+  let s2 = demote(&mut o, 2).unwrap();
+  assert_eq!(o[1..].iter().map(|x|x.ignx()).collect::<Vec<u32>>(),
+             o1[1..].iter().map(|x|x.ignx()).collect::<Vec<u32>>());
+  assert_eq!(check_clones(&o, &nodes), None);
+  for x in s2.lines().rev().chain(s.lines().rev()) {
+    if x.starts_with("ip:") { undo_insert_parts(&mut o, x) } else { undo_delete_blocks(&mut o, x) }
+  }
+  assert_eq!(o, o1);
+  // q contains p, so p can't become its child
+  assert!(demote(&mut o, 1).is_none());
+  assert!(!valid_operations(&o, 1).contains("demote"));
+  assert!(promote(&mut o, 1).is_some());
+  assert_eq!(o.len(), 8);
+  assert_eq!(check_clones(&o, &nodes), None);
+}
+#[test]
+fn test_insert_node_at() {
+  let (mut o, mut nodes) = crate::parsing::from_leo_content(r#"<leo_file><vnodes>
+<v t="p"><vh>P</vh><v t="a"><vh>A</vh></v></v>
+<v t="q"><vh>Q</vh><v t="p"/></v>
+</vnodes><tnodes></tnodes></leo_file>"#);
+  let order = |o:&Outline, nodes:&Vec<VData>| -> String {
+    o.iter().skip(1).map(|x|nodes[x.ignx() as usize].gnx.as_str()).collect()
+  };
+  let (l, _) = insert_node_at(&mut o, &mut nodes, 2, "b", InsertPlace::Before);
+  assert_eq!(o.label_index(l), Some(2));
+  insert_node_at(&mut o, &mut nodes, 3, "c", InsertPlace::After);
+  insert_node_at(&mut o, &mut nodes, 1, "d", InsertPlace::FirstChild);
+  let (l, s) = insert_node_at(&mut o, &mut nodes, 1, "e", InsertPlace::LastChild);
+  assert_eq!(order(&o, &nodes), "pdbaceqpdbace");
+  assert_eq!(o.label_index(l), Some(6));
+  assert_eq!(check_clones(&o, &nodes), None);
+  undo_insert_parts(&mut o, s.lines().nth(1).unwrap());
+  assert_eq!(order(&o, &nodes), "pdbacqpdbac");
+  // inserting after the last node used to panic
+  let n = o.len() - 1;
+  insert_new_node(&mut o, &mut nodes, n, "f");
+  assert_eq!(order(&o, &nodes), "pdbacfqpdbacf");
+}
+/// inserts a new node with the given gnx and headline h as the last top
+/// level node, and links clones of the vnodes ignxs as its children.
+/// Returns label of the new node and undo_info, or None if ignxs is empty
+pub fn clone_under_new_node(o:&mut Outline, nodes:&mut Vec<VData>, gnx:&str, h:&str, ignxs:&[u32])
+    -> Option<(u32, String)> {
+  if ignxs.is_empty() { return None }
+  let last = (1..o.len()).rev().find(|i|o[*i].level() == 1)?;
+  let (label, s) = insert_node_at(o, nodes, last, gnx, InsertPlace::After);
+  let mut res = vec![s];
+  let ignx = o[o.label_index(label)?].ignx();
+  let mut v = nodes[ignx as usize].clone();
+  v.h.push_str(h);
+  res.push(update_node(nodes, &v)?);
+  for (k, c) in ignxs.iter().enumerate() {
+    res.push(create_link(o, ignx, k, *c)?);
+  }
+  Some((label, res.join("\n")))
+}
+/// keys by which children can be sorted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+  /// headline as it is
+  Headline,
+  /// headline ignoring case
+  HeadlineNoCase,
+  /// headline ignoring case, with numbers compared by their value
+  Natural
+}
+impl SortKey {
+  pub fn from_str(s:&str) -> Option<SortKey> {
+    match s {
+      "headline" => Some(SortKey::Headline),
+      "nocase" => Some(SortKey::HeadlineNoCase),
+      "natural" => Some(SortKey::Natural),
+      _ => None
+    }
+  }
+  pub fn cmp(&self, a:&str, b:&str) -> Ordering {
+    match self {
+      SortKey::Headline => a.cmp(b),
+      SortKey::HeadlineNoCase => a.to_lowercase().cmp(&b.to_lowercase()),
+      SortKey::Natural => natural_cmp(a, b)
+    }
+  }
+}
+/// compares strings ignoring case, runs of digits are compared
+/// as numbers so that "a2" < "a10"
+pub fn natural_cmp(a:&str, b:&str) -> Ordering {
+  let chunks = |s:&str| -> Vec<(bool, String)> {
+    let mut res:Vec<(bool, String)> = Vec::new();
+    for c in s.chars() {
+      let d = c.is_ascii_digit();
+      match res.last_mut() {
+        Some(x) if x.0 == d => x.1.push(c),
+        _ => res.push((d, c.to_string()))
+      }
+    }
+    res
+  };
+  let (ca, cb) = (chunks(a), chunks(b));
+  for (x, y) in ca.iter().zip(cb.iter()) {
+    let r = if x.0 && y.0 {
+      let (u, v) = (x.1.trim_start_matches('0'), y.1.trim_start_matches('0'));
+      u.len().cmp(&v.len()).then_with(||u.cmp(v))
+    } else {
+      x.1.to_lowercase().cmp(&y.1.to_lowercase())
+    };
+    if r != Ordering::Equal { return r }
+  }
+  ca.len().cmp(&cb.len()).then_with(||a.cmp(b))
+}
+/// puts children of the node at index i in the given order, in every
+/// clone of this node. order contains old child indices.
+/// Returns undo_info or None if the order of children is unchanged
+fn reorder_children(o:&mut Outline, i:usize, order:&[usize]) -> Option<String> {
+  if order.iter().enumerate().all(|(a, b)|a == *b) { return None }
+  let plev = o[i].level();
+  let sz = o.subtree_size(i) - 1;
+  // children blocks as (offset from i, size)
+  let blocks:Vec<(usize, usize)> = (i+1..i+1+sz)
+    .filter(|j|o[*j].level() == plev + 1)
+    .map(|j|(j - i, o.subtree_size(j)))
+    .collect();
+  let pignx = o[i].ignx();
+  let marks:Vec<usize> = o
+    .iter()
+    .enumerate()
+    .filter(|x|x.1.ignx() == pignx)
+    .map(|x|x.0 + 1)
+    .collect();
+  let mut data:Outline = Vec::with_capacity(marks.len() * sz);
+  for m in marks.iter() {
+    for k in order {
+      let (d, n) = blocks[*k];
+      data.extend_from_slice(&o[m-1+d..m-1+d+n]);
+    }
+  }
+  let mut buf = String::new();
+  encode_set_nodes(o, &marks, &data, &mut buf);
+  set_nodes(o, &marks, &data);
+  Some(buf)
+}
+/// sorts children of the node at index i by their headlines.
+/// Returns undo_info or None if the children are already sorted
+pub fn sort_children(o:&mut Outline, nodes:&Vec<VData>, i:usize, key:SortKey) -> Option<String> {
+  let ch = o.children(i);
+  let mut order:Vec<usize> = (0..ch.len()).collect();
+  order.sort_by(|a, b| key.cmp(&nodes[ch[*a] as usize].h, &nodes[ch[*b] as usize].h));
+  reorder_children(o, i, &order)
+}
+/// reverses the order of children of the node at index i.
+/// Returns undo_info or None if the node has less than two children
+pub fn reverse_children(o:&mut Outline, i:usize) -> Option<String> {
+  let n = o.children(i).len();
+  let order:Vec<usize> = (0..n).rev().collect();
+  reorder_children(o, i, &order)
+}
+#[test]
+fn test_sort_children() {
+  let (mut o, nodes) = crate::parsing::from_leo_content(r#"<leo_file><vnodes>
+<v t="p"><vh>P</vh><v t="b"><vh>x10</vh><v t="c"><vh>C</vh></v></v><v t="a"><vh>X2</vh></v></v>
+<v t="q"><vh>Q</vh><v t="p"/></v>
+</vnodes><tnodes></tnodes></leo_file>"#);
+  let o1 = o.clone();
+  let hs = |o:&Outline| -> Vec<String> {
+    o.iter().skip(1).map(|x|nodes[x.ignx() as usize].h.clone()).collect()
+  };
+  assert!(sort_children(&mut o, &nodes, 1, SortKey::HeadlineNoCase).is_none());
+  let s = sort_children(&mut o, &nodes, 1, SortKey::Natural).unwrap();
+  assert_eq!(hs(&o), ["P", "X2", "x10", "C", "Q", "P", "X2", "x10", "C"]);
+  assert_eq!(check_clones(&o, &nodes), None);
+  undo_set_nodes(&mut o, &s);
+  assert_eq!(o, o1);
+  reverse_children(&mut o, 1).unwrap();
+  assert_eq!(hs(&o), ["P", "X2", "x10", "C", "Q", "P", "X2", "x10", "C"]);
+  assert!(reverse_children(&mut o, 2).is_none());
+  assert_eq!(natural_cmp("a2b", "A10a"), Ordering::Less);
+}
 pub fn move_node_right(o:&mut Outline, i:usize) -> Option<String> {
   if i < 2 {return None}
   let ilev = o[i].level();
   if  ilev == o[i-1].level() + 1 {return None}
-  let j = o[..i-1]
+  let j = o[..i]
     .iter()
     .enumerate()
     .rev()
+    .take_while(|x|x.1.level() >= ilev)
     .filter(|x|x.1.level() == ilev)
     .map(|x|x.0)
     .nth(0)
@@ -1069,6 +1400,35 @@ pub fn move_node_right(o:&mut Outline, i:usize) -> Option<String> {
   insert_parts(o, &nmarks, &deldata);
   return Some(buf);
 }
+#[test]
+fn test_move_node_right() {
+  let (mut o, nodes) = crate::parsing::from_leo_content(r#"<leo_file><vnodes>
+<v t="a"><vh>A</vh></v>
+<v t="b"><vh>B</vh><v t="b1"><vh>B1</vh></v></v>
+<v t="c"><vh>C</vh></v><v t="d"><vh>D</vh></v>
+</vnodes><tnodes></tnodes></leo_file>"#);
+  let hs = |o:&Outline| -> Vec<(u8, String)> {
+    o.iter().skip(1).map(|x|(x.level(), nodes[x.ignx() as usize].h.clone())).collect()
+  };
+  let lv = |o:&Outline| -> Vec<u8> { hs(o).iter().map(|x|x.0).collect() };
+  let o1 = o.clone();
+  // previous sibling is the first top level node right before
+  assert!(move_node_right(&mut o, 2).is_some());
+  assert_eq!(lv(&o), [1, 2, 3, 1, 1]);
+  assert_eq!(o.parent_index(2), 1);
+  o = o1;
+  // previous sibling is the node right before
+  assert!(move_node_right(&mut o, 5).is_some());
+  assert_eq!(lv(&o), [1, 1, 2, 1, 2]);
+  assert_eq!(hs(&o)[o.parent_index(5) - 1].1, "C");
+  // previous sibling has a child in between
+  assert!(move_node_right(&mut o, 4).is_some());
+  assert_eq!(lv(&o), [1, 1, 2, 2, 3]);
+  assert_eq!(o.parent_index(4), 2);
+  // the first child can't be moved right
+  assert!(move_node_right(&mut o, 3).is_none());
+  assert_eq!(check_clones(&o, &nodes), None);
+}
 pub fn move_node_left(o:&mut Outline, i:usize) -> Option<String> {
   if i >= o.len() { return None }
   if o[i].level() < 2 { return None }
@@ -1439,14 +1799,34 @@ pub fn move_node_down(o:&mut Outline, i:usize) -> Option<String> {
   }
 }
 pub fn insert_new_node(o:&mut Outline, nodes:&mut Vec<VData>, i:usize, gnx:&str) -> (u32, String) {
-  let ignx = nodes.len() as u32;
-  let (pi, j) =
-    if  o[i].level() + 1 == o[i+1].level()
+  let place =
+    if  i + 1 < o.len()
+     && o[i].level() + 1 == o[i+1].level()
      && o[i].is_expanded() {
-      ( i, i+1)
+      InsertPlace::FirstChild
     } else {
-      ( o.parent_index(i), i + o.subtree_size(i) )
+      InsertPlace::After
     };
+  insert_node_at(o, nodes, i, gnx, place)
+}
+/// where a new node is inserted relative to the node at given index
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InsertPlace {
+  Before,
+  After,
+  FirstChild,
+  LastChild
+}
+/// inserts new node with the given gnx at the given place relative to the
+/// node at index i. Returns label of the new node and undo_info.
+pub fn insert_node_at(o:&mut Outline, nodes:&mut Vec<VData>, i:usize, gnx:&str, place:InsertPlace) -> (u32, String) {
+  let ignx = nodes.len() as u32;
+  let (pi, j) = match place {
+    InsertPlace::Before => ( o.parent_index(i), i ),
+    InsertPlace::After => ( o.parent_index(i), i + o.subtree_size(i) ),
+    InsertPlace::FirstChild => ( i, i+1 ),
+    InsertPlace::LastChild => ( i, i + o.subtree_size(i) )
+  };
   let mut u = format!("addv:{}\n", gnx);
   nodes.push(VData::new(gnx));
   nodes[ignx as usize].ignx = ignx;
@@ -1480,10 +1860,87 @@ pub fn insert_new_node(o:&mut Outline, nodes:&mut Vec<VData>, i:usize, gnx:&str)
   insert_parts(o, &marks, &data);
   (rlabel, u)
 }
+/// inserts block of nodes tch as children of the node at index pi,
+/// starting at index j, and in all clones of that node. Top level nodes
+/// in tch must have level 0.
+/// Returns label of the first inserted node at index j and undo_info.
+pub fn insert_block(o:&mut Outline, pi:usize, j:usize, tch:&Outline) -> (u32, String) {
+  let pignx = o[pi].ignx();
+  let delta = j - pi;
+  let marks:Vec<usize> = o
+    .iter()
+    .enumerate()
+    .filter(|x| x.1.ignx() == pignx)
+    .map(|x|x.0 + delta)
+    .collect();
+  let mut data:Vec<u64> = Vec::with_capacity(marks.len() * tch.len());
+  let mut label = o[0].label();
+  let mut rlabel:u32 = 0;
+  for m in marks.iter() {
+    let zlev = o[*m-delta].level() + 1;
+    if *m == j {rlabel = label + 1}
+    for x in tch.iter() {
+      let mut y = *x;
+      y.shift(zlev as i8);
+      label += 1;
+      y.set_label(label);
+      data.push(y);
+    }
+  }
+  let mut u = String::new();
+  encode_insert_parts(o, &marks, &data, &mut u);
+  o[0].set_label(label);
+  insert_parts(o, &marks, &data);
+  (rlabel, u)
+}
 pub fn redo_insert_new_node(nodes:&mut Vec<VData>, u:&str) {
   let v = VData::new(&u[5..]);
   nodes.push(v);
 }
+/// sets h, b and flags of the vnode with the same ignx and gnx as v.
+/// Returns undo_info or None if nodes don't contain such vnode
+pub fn update_node(nodes:&mut Vec<VData>, v:&VData) -> Option<String> {
+  let i = v.ignx as usize;
+  if i >= nodes.len() { return None }
+  let v0 = &mut nodes[i];
+  if v0.gnx != v.gnx { return None }
+  let mut buf = "vupd:".to_string();
+  v0.write_to(&mut buf);
+  buf.push('\t');
+  v.write_to(&mut buf);
+  v0.h.replace_range(.., &v.h);
+  v0.b.replace_range(.., &v.b);
+  v0.flags = v.flags;
+  Some(buf)
+}
+/// returns update records (joined by newlines) for all vnodes whose
+/// content differs from their previous content given in old
+pub fn vnode_updates(old:&[VData], nodes:&Vec<VData>) -> String {
+  let mut buf = String::new();
+  for v0 in old.iter() {
+    let v = &nodes[v0.ignx as usize];
+    if v == v0 { continue }
+    if buf.len() > 0 { buf.push('\n') }
+    buf.push_str("vupd:");
+    v0.write_to(&mut buf);
+    buf.push('\t');
+    v.write_to(&mut buf);
+  }
+  buf
+}
+#[test]
+fn test_vnode_updates() {
+  let (_, mut nodes) = crate::parsing::from_leo_content(r#"<leo_file><vnodes>
+<v t="a"><vh>A</vh></v><v t="b"><vh>B</vh></v>
+</vnodes><tnodes></tnodes></leo_file>"#);
+  let old = nodes.clone();
+  assert_eq!(vnode_updates(&old, &nodes), "");
+  nodes[2].b.push_str("changed");
+  let s = vnode_updates(&old, &nodes);
+  assert_eq!(s.lines().count(), 1);
+  undo_update_node(&mut nodes, &s);
+  assert_eq!(nodes, old);
+}
 pub fn undo_update_node(nodes:&mut Vec<VData>, x:&str) {
   let v = VData::from_str(partition(&x[5..], "\t").0);
   let i = v.ignx as usize;
@@ -1654,7 +2111,7 @@ fn shift_one_block(o:&mut Outline, i:usize, sz:usize, d:i8) {
   }
 }
 
-fn shift_blocks(o:&mut Outline, marks:&Vec<usize>, sz:usize, d:i8) {
+pub fn shift_blocks(o:&mut Outline, marks:&Vec<usize>, sz:usize, d:i8) {
   for i in marks.iter() {
     shift_one_block(o, *i, sz, d);
   }
@@ -1740,7 +2197,7 @@ fn encode_set_nodes(o:&Outline, marks:&Vec<usize>, data:&Outline, buf:&mut Strin
   buf.pop();
   buf.push(')');
 }
-fn set_nodes(o:&mut Outline, marks:&Vec<usize>, data:&Outline) {
+pub fn set_nodes(o:&mut Outline, marks:&Vec<usize>, data:&Outline) {
   let sz = data.len() / marks.len();
   for (i, m) in marks.iter().enumerate() {
     for (j, x) in data.iter().skip(i * sz).enumerate().take(sz) {
@@ -1803,7 +2260,7 @@ pub fn redo_set_nodes(o:&mut Outline, enc:&str) {
 
 
 #[derive(Debug)]
-pub struct TreeError(String);
+pub struct TreeError(pub String);
 impl Error for TreeError{}
 impl fmt::Display for TreeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
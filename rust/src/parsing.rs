@@ -2,6 +2,8 @@ use crate::model::{VData, Outline, OutlineOps, LevGnx, LevGnxOps,
              combine_trees, find_derived_files,
              find_clean_files, find_auto_files, find_edit_files};
 use crate::atclean::update_atclean_tree;
+use crate::marks::MARKED;
+use crate::writing::LeoExtras;
 use quick_xml::Reader as XmlReader;
 use quick_xml::events::Event;
 use quick_xml::events::attributes::Attributes;
@@ -28,6 +30,18 @@ mod tests {
     let s = b"#@+node:ekr.20050208101229: * << imports  (leoGlobals)";
     assert_eq!(super::handle_level_stars(s, 28), (1, 30))
   }
+  #[test]
+  fn test_malformed_leo_content() {
+    let xml = r#"<leo_file><vnodes><v t="a"><vh>A</vh></v>
+<v t="b"><vh>B</vh></x></vnodes><tnodes></tnodes></leo_file>"#;
+    assert!(super::try_from_leo_content(xml).is_err());
+    let (o, _) = super::try_from_leo_content(&xml.replace("</x>", "</v>")).unwrap();
+    assert_eq!(o.len(), 3);
+    let xml = xml.replace("</x>", "</v>").replace(r#"<v t="b">"#, "<v>").replace(">A<", ">A &bogus;<");
+    assert!(super::try_from_leo_content(&xml).is_err());
+    // lenient parsing skips what it can't read
+    assert_eq!(super::from_leo_content(&xml).0.len(), 2);
+  }
 }
 struct LdfParseState<'a> {
   ind:usize,
@@ -494,7 +508,7 @@ pub fn from_derived_file(fname:&Path) -> Result<(Outline, Vec<VData>), io::Error
   Ok(from_derived_file_content(s.as_str()))
 }
 
-fn read_file_as_in_linux(fname:&Path) -> Result<String, io::Error> {
+pub(crate) fn read_file_as_in_linux(fname:&Path) -> Result<String, io::Error> {
   let f = File::open(&fname)?;
   let mdata = f.metadata()?;
   let sz = mdata.len() as usize;
@@ -552,7 +566,7 @@ fn parser_config() -> ParserConfig {
 */
 pub fn from_leo_file(fname:&Path) -> Result<(Outline, Vec<VData>), io::Error> {
   let s = read_file_as_in_linux(fname)?;
-  Ok(from_leo_content(s.as_str()))
+  try_from_leo_content(s.as_str()).map_err(invalid_leo)
 }
 
 /*
@@ -570,7 +584,18 @@ pub fn from_zip_archive(archive:&Path, fname:&str) -> Result<(Outline, Vec<VData
   Err(io::Error::new(io::ErrorKind::NotFound, msg))
 }
 */
+fn invalid_leo(msg:String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
 pub fn from_leo_content(buf:&str) -> (Outline, Vec<VData>) {
+  parse_leo_content(buf, false).unwrap_or_default()
+}
+/// same as from_leo_content but fails on malformed xml instead of
+/// returning whatever could be read
+pub fn try_from_leo_content(buf:&str) -> Result<(Outline, Vec<VData>), String> {
+  parse_leo_content(buf, true)
+}
+fn parse_leo_content(buf:&str, strict:bool) -> Result<(Outline, Vec<VData>), String> {
   let mut reader = XmlReader::from_str(buf);
   let mut nodes:Vec<VData> = Vec::new();
   nodes.push(VData::new("hidden-root-vnode-gnx"));
@@ -583,46 +608,65 @@ pub fn from_leo_content(buf:&str) -> (Outline, Vec<VData>) {
   loop {
     let mut xmlbuf = Vec::new();
     let getattr = |k:&[u8], attrs:Attributes, rr| {
-      for x in attrs {
-        let a = x.unwrap();
+      for a in attrs.filter_map(|x|x.ok()) {
         if a.key == k {
-          return a.unescape_and_decode_value(rr).unwrap();
+          return a.unescape_and_decode_value(rr).map_err(|e|e.to_string());
         }
       }
-      panic!("missing attribute:{:?}", k);
+      Err(format!("missing attribute {}", String::from_utf8_lossy(k)))
+    };
+    let is_marked = |attrs:Attributes| {
+      attrs.filter_map(|x|x.ok())
+           .any(|a|a.key == b"a" && a.value.contains(&b'M'))
     };
-    match reader.read_event(&mut xmlbuf) {
+    // malformed content is reported in strict mode and skipped otherwise
+    let res:Result<(), String> = match reader.read_event(&mut xmlbuf) {
       Ok(Event::Start(ref e)) => {
         let n = e.local_name();
+        txt.clear();
         if n == b"v" {
-          last_gnx.clear();
-          last_gnx.push_str(&getattr(b"t", e.attributes(), &reader));
-          let mut v = VData::new(&last_gnx);
-          let ignx = gnx2i.entry(v.gnx.clone()).or_insert(gnxcount);
-          v.ignx = *ignx as u32;
-          lev += 1u8;
-          outline.add_node(lev, *ignx as u32).unwrap();
-          nodes.push(v);
-          gnxcount += 1;
+          getattr(b"t", e.attributes(), &reader).and_then(|gnx|{
+            last_gnx = gnx;
+            let mut v = VData::new(&last_gnx);
+            let ignx = gnx2i.get(&v.gnx).copied().unwrap_or(gnxcount);
+            v.ignx = ignx as u32;
+            if is_marked(e.attributes()) {
+              v.flags |= MARKED;
+            }
+            lev = lev.checked_add(1).ok_or("outline is too deep")?;
+            outline.add_node(lev, ignx as u32).map_err(|e|e.to_string())?;
+            gnx2i.insert(v.gnx.clone(), ignx);
+            nodes.push(v);
+            gnxcount += 1;
+            Ok(())
+          })
         } else if n == b"vnodes" {
-          lev=0;
+          lev = 0;
+          Ok(())
         } else if n == b"t" {
-          last_gnx.clear();
-          last_gnx.push_str(&getattr(b"tx", e.attributes(), &reader));
+          getattr(b"tx", e.attributes(), &reader).map(|gnx|last_gnx = gnx)
+        } else {
+          Ok(())
         }
-        txt.clear();
       },
       Ok(Event::Empty(ref e)) => {
         let n = e.local_name();
         if n == b"v" {
-          last_gnx.clear();
-          last_gnx.push_str(&getattr(b"t", e.attributes(), &reader));
-          if let Some(ignx) = gnx2i.get(last_gnx.as_str()) {
-            outline.add_node(lev+1, *ignx as u32).unwrap();
-          }
+          getattr(b"t", e.attributes(), &reader).and_then(|gnx|{
+            last_gnx = gnx;
+            match gnx2i.get(last_gnx.as_str()) {
+              Some(ignx) => outline.add_node(lev.saturating_add(1), *ignx as u32)
+                .map(|_|()).map_err(|e|e.to_string()),
+              None => Ok(())
+            }
+          })
+        } else {
+          Ok(())
         }
       },
-      Ok(Event::Text(e)) => txt.push_str(&e.unescape_and_decode(&reader).unwrap()),
+      Ok(Event::Text(e)) => e.unescape_and_decode(&reader)
+        .map(|t|txt.push_str(&t))
+        .map_err(|e|e.to_string()),
       Ok(Event::End(ref e)) => {
         let n = e.local_name();
         if n == b"vh" {
@@ -630,18 +674,25 @@ pub fn from_leo_content(buf:&str) -> (Outline, Vec<VData>) {
             nodes[*i].h.push_str(&txt)
           }
         } else if n == b"v" {
-          lev -= 1;
+          lev = lev.saturating_sub(1);
         } else if n == b"t" {
           if let Some(i) = gnx2i.get(&last_gnx) {
             nodes[*i].b.push_str(&txt);
           }
         }
+        Ok(())
       },
       Ok(Event::Eof) => break,
-      _ => ()
+      Err(e) => Err(e.to_string()),
+      _ => Ok(())
+    };
+    if let Err(e) = res {
+      if strict {
+        return Err(format!("{} at position {}", e, reader.buffer_position()));
+      }
     }
   }
-  (outline, nodes)
+  Ok((outline, nodes))
 }
 pub fn from_auto_content(v:&VData, cont:&str) -> (Outline, Vec<VData>) {
   let mut v2 = v.clone();
@@ -654,10 +705,18 @@ pub fn from_auto_content(v:&VData, cont:&str) -> (Outline, Vec<VData>) {
   (outline, nodes)
 }
 pub fn load_with_external_files(fname:&str) -> Result<(Outline, Vec<VData>), io::Error> {
+  load_document_with_external_files(fname).map(|(o, nodes, _)|(o, nodes))
+}
+/// same as load_with_external_files, but also returns the parts of the
+/// document that are not kept in the outline, so that they can be
+/// written back when the document is saved
+pub fn load_document_with_external_files(fname:&str)
+    -> Result<(Outline, Vec<VData>, LeoExtras), io::Error> {
   let pbuf = fs::canonicalize(fname)?;
   let xmlcont = fs::read_to_string(pbuf.as_path())?;
+  let extras = LeoExtras::from_content(&xmlcont).unwrap_or_default();
   let mut trees = Vec::new();
-  let (outline, mut vnodes) = from_leo_content(xmlcont.as_str());
+  let (outline, mut vnodes) = try_from_leo_content(xmlcont.as_str()).map_err(invalid_leo)?;
   let folder = pbuf.parent().unwrap();
   let mut missing_files = Vec::new();
   for (f,_) in find_derived_files(folder, &outline, &vnodes) {
@@ -702,5 +761,6 @@ pub fn load_with_external_files(fname:&str) -> Result<(Outline, Vec<VData>), io:
       print!("missing file:{}", &x);
     }
   }
-  Ok(combine_trees(&trees))
+  let (outline, nodes) = combine_trees(&trees);
+  Ok((outline, nodes, extras))
 }
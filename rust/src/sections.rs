@@ -0,0 +1,122 @@
+use crate::model::{VData, Outline, OutlineOps, LevGnxOps, Tree};
+use crate::utils::extract_section_ref;
+use crate::atclean::atclean_to_string;
+use std::collections::HashSet;
+
+/// Section reference found in a body
+#[derive(Debug, PartialEq, Clone)]
+pub struct SectionRef {
+  /// outline index of the node containing the reference
+  pub index: usize,
+  /// line number in the body, starting from 0
+  pub line: usize,
+  /// section name including `<<` and `>>`
+  pub name: String,
+  /// outline index of the node defining the section
+  pub def: Option<usize>
+}
+/// Result of the section analysis of a subtree
+#[derive(Debug, Default, PartialEq)]
+pub struct SectionReport {
+  /// all references in outline order
+  pub refs: Vec<SectionRef>,
+  /// indices of the definitions that are never referenced
+  pub unused: Vec<usize>,
+  /// names with indices of all their definitions, for sections defined
+  /// more than once in the subtree where the reference is resolved
+  pub duplicates: Vec<(String, Vec<usize>)>
+}
+impl SectionReport {
+  /// references whose sections are not defined
+  pub fn undefined(&self) -> impl Iterator<Item=&SectionRef> {
+    self.refs.iter().filter(|r|r.def.is_none())
+  }
+  pub fn is_ok(&self) -> bool {
+    self.undefined().next().is_none() && self.unused.is_empty() && self.duplicates.is_empty()
+  }
+  /// returns human readable description of every problem found
+  pub fn problems(&self, o:&Outline, nodes:&Vec<VData>) -> Vec<String> {
+    let h = |i:usize| nodes[o[i].ignx() as usize].h.as_str();
+    let mut res = Vec::new();
+    for r in self.undefined() {
+      res.push(format!("undefined section {} referenced in '{}' line {}",
+                       r.name, h(r.index), r.line + 1));
+    }
+    for i in self.unused.iter() {
+      res.push(format!("unused section definition '{}'", h(*i)));
+    }
+    for (name, defs) in self.duplicates.iter() {
+      res.push(format!("section {} is defined {} times", name, defs.len()));
+    }
+    res
+  }
+}
+/// finds all section references in the subtree of the node at index ni,
+/// and checks where they are defined. Like find_section, a reference is
+/// resolved in the subtree of the node containing it.
+pub fn analyze_sections(o:&Outline, nodes:&Vec<VData>, ni:usize) -> SectionReport {
+  let mut res = SectionReport::default();
+  let end = ni + o.subtree_size(ni);
+  let t = Tree::new(o, nodes);
+  let mut used:HashSet<usize> = HashSet::new();
+  for i in ni..end {
+    let v = &nodes[o[i].ignx() as usize];
+    for (line, x) in v.b.lines().enumerate() {
+      let name = match extract_section_ref(x) {
+        Some(name) => name,
+        None => continue
+      };
+      let def = t.find_section(name, i);
+      if let Some(j) = def {
+        used.insert(j);
+        let defs:Vec<usize> = (j..i + o.subtree_size(i))
+          .filter(|k|nodes[o[*k].ignx() as usize].section_ref() == Some(name))
+          .collect();
+        if defs.len() > 1 && !res.duplicates.iter().any(|d|d.0 == name && d.1 == defs) {
+          used.extend(defs.iter());
+          res.duplicates.push((name.to_string(), defs));
+        }
+      }
+      res.refs.push(SectionRef { index: i, line, name: name.to_string(), def });
+    }
+  }
+  res.unused = (ni + 1..end)
+    .filter(|i|!used.contains(i) && nodes[o[*i].ignx() as usize].section_ref().is_some())
+    .collect();
+  res
+}
+/// same as atclean_to_string, but also returns problems with sections
+pub fn atclean_to_string_checked(o:&Outline, nodes:&Vec<VData>, ni:usize) -> (String, Vec<String>) {
+  let report = analyze_sections(o, nodes, ni);
+  (atclean_to_string(o, nodes, ni), report.problems(o, nodes))
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::from_leo_content;
+  #[test]
+  fn test_analyze_sections() {
+    let (o, nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="r"><vh>@clean a.py</vh>
+<v t="a"><vh>&lt;&lt; a &gt;&gt;</vh></v>
+<v t="a2"><vh>&lt;&lt; a &gt;&gt;</vh></v>
+<v t="c"><vh>&lt;&lt; c &gt;&gt;</vh></v>
+</v>
+</vnodes><tnodes><t tx="r">first
+&lt;&lt; a &gt;&gt;
+  &lt;&lt; b &gt;&gt;
+last
+</t><t tx="a">A
+</t></tnodes></leo_file>"#);
+    let r = analyze_sections(&o, &nodes, 1);
+    assert_eq!(r.refs.len(), 2);
+    assert_eq!(r.refs[0].def, Some(2));
+    assert_eq!(r.undefined().map(|x|x.line).collect::<Vec<_>>(), vec![2]);
+    assert_eq!(r.unused, vec![4]);
+    assert_eq!(r.duplicates, vec![("<< a >>".to_string(), vec![2, 3])]);
+    let (txt, problems) = atclean_to_string_checked(&o, &nodes, 1);
+    // undefined reference doesn't truncate the output
+    assert_eq!(txt, "first\nA\n  << b >>\nlast\n");
+    assert_eq!(problems.len(), 3);
+  }
+}
@@ -0,0 +1,245 @@
+use crate::model::{VData, Outline, OutlineOps, LevGnxOps};
+use crate::hoist::Hoist;
+use std::{fs, io, path::{Path, PathBuf}};
+use std::collections::HashMap;
+
+/// Position stored independently of labels: (child index, gnx) of every
+/// node on the path from the top level down to the node.
+pub type ArchivedPosition = Vec<(usize, String)>;
+
+/// Session state of an outline view: expanded nodes, the current node,
+/// the scroll position given by the node shown at the top of the view,
+/// and the hoist stack.
+///
+/// It is written as text, one record per line. Every record starts with
+/// its kind (`expanded`, `current`, `top` or `hoist`), hoist records
+/// continue with 1 or 0 telling whether the hoisted node is shown, and
+/// all of them end with the archived position as child index and gnx
+/// pairs. Fields are separated by tabs. Backslash, tab and newline in
+/// gnx are written as `\\`, `\t` and `\n`.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Session {
+  pub expanded: Vec<ArchivedPosition>,
+  pub current: Option<ArchivedPosition>,
+  pub top: Option<ArchivedPosition>,
+  pub hoist: Vec<(ArchivedPosition, bool)>
+}
+pub const SESSION_HEADER:&str = "#mini_leo-session:1";
+
+/// returns archived position of the node at index i
+pub fn archive_position(o:&Outline, nodes:&Vec<VData>, i:usize) -> ArchivedPosition {
+  let mut res = Vec::new();
+  let mut j = i;
+  while j > 0 {
+    res.push((o.child_index(j), nodes[o[j].ignx() as usize].gnx.clone()));
+    j = o.parent_index(j);
+  }
+  res.reverse();
+  res
+}
+/// returns index of the node at the archived position. On every level
+/// the child with the stored index is taken if it has the stored gnx,
+/// otherwise the first child with this gnx. Returns None if some node
+/// on the path can't be found.
+pub fn resolve_position(o:&Outline, nodes:&Vec<VData>, ap:&ArchivedPosition) -> Option<usize> {
+  resolve(o, nodes, &children_map(o), ap)
+}
+// indices of children of every node that has them
+fn children_map(o:&Outline) -> HashMap<usize, Vec<usize>> {
+  let mut res:HashMap<usize, Vec<usize>> = HashMap::new();
+  let mut stack = vec![0usize];
+  for (i, x) in o.iter().enumerate().skip(1) {
+    while stack.len() > 1 && o[stack[stack.len() - 1]].level() >= x.level() {
+      stack.pop();
+    }
+    res.entry(stack[stack.len() - 1]).or_insert_with(Vec::new).push(i);
+    stack.push(i);
+  }
+  res
+}
+fn resolve(o:&Outline, nodes:&Vec<VData>, chmap:&HashMap<usize, Vec<usize>>, ap:&ArchivedPosition) -> Option<usize> {
+  let mut p = 0usize;
+  for (ci, gnx) in ap.iter() {
+    let chs = chmap.get(&p)?;
+    let has = |j:&usize| nodes[o[*j].ignx() as usize].gnx == *gnx;
+    p = match chs.get(*ci).filter(|j|has(j)) {
+      Some(j) => *j,
+      None => *chs.iter().find(|j|has(j))?
+    };
+  }
+  if p == 0 { None } else { Some(p) }
+}
+fn write_position(ap:&ArchivedPosition, buf:&mut String) {
+  for (ci, gnx) in ap.iter() {
+    buf.push('\t');
+    buf.push_str(&ci.to_string());
+    buf.push('\t');
+    for c in gnx.chars() {
+      match c {
+        '\\' => buf.push_str("\\\\"),
+        '\t' => buf.push_str("\\t"),
+        '\n' => buf.push_str("\\n"),
+        _ => buf.push(c)
+      }
+    }
+  }
+  buf.push('\n');
+}
+fn unescape(s:&str) -> Option<String> {
+  let mut res = String::with_capacity(s.len());
+  let mut chars = s.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' { res.push(c); continue }
+    match chars.next()? {
+      '\\' => res.push('\\'),
+      't' => res.push('\t'),
+      'n' => res.push('\n'),
+      _ => return None
+    }
+  }
+  Some(res)
+}
+fn read_position(fields:&[&str]) -> Option<ArchivedPosition> {
+  if fields.is_empty() || fields.len() % 2 != 0 { return None }
+  fields.chunks(2)
+    .map(|x|Some((x[0].parse().ok()?, unescape(x[1])?)))
+    .collect()
+}
+impl Session {
+  /// collects session state of the outline. Current node and the node
+  /// at the top of the view are given by their indices, which may be 0
+  /// if there is no such node.
+  pub fn capture(o:&Outline, nodes:&Vec<VData>, hoist:&Hoist, current:usize, top:usize) -> Self {
+    let ap = |i:usize|archive_position(o, nodes, i);
+    Session {
+      expanded: (1..o.len()).filter(|i|o[*i].is_expanded()).map(ap).collect(),
+      current: Some(current).filter(|i|*i > 0 && *i < o.len()).map(ap),
+      top: Some(top).filter(|i|*i > 0 && *i < o.len()).map(ap),
+      hoist: hoist.entries().iter()
+        .filter_map(|(label, show)|o.label_index(*label).map(|i|(ap(i), *show)))
+        .collect()
+    }
+  }
+  /// restores the session state in the outline: only nodes at the stored
+  /// positions remain expanded and the hoist stack is replaced. Positions
+  /// that no longer exist are ignored.
+  ///
+  /// Returns indices of the current node and of the node at the top of
+  /// the view, if they can be found
+  pub fn apply(&self, o:&mut Outline, nodes:&Vec<VData>, hoist:&mut Hoist) -> (Option<usize>, Option<usize>) {
+    let chmap = children_map(o);
+    let find = |ap:&ArchivedPosition|resolve(o, nodes, &chmap, ap);
+    let expanded:Vec<usize> = self.expanded.iter().filter_map(find).collect();
+    let entries = self.hoist.iter()
+      .filter_map(|(ap, show)|find(ap).map(|i|(o[i].label(), *show)))
+      .collect();
+    let res = (self.current.as_ref().and_then(find), self.top.as_ref().and_then(find));
+    for x in o.iter_mut().skip(1) { x.collapse() }
+    for i in expanded { o[i].expand() }
+    hoist.set_entries(entries);
+    res
+  }
+  pub fn to_text(&self) -> String {
+    let mut buf = String::from(SESSION_HEADER);
+    buf.push('\n');
+    if let Some(ap) = self.current.as_ref() {
+      buf.push_str("current");
+      write_position(ap, &mut buf);
+    }
+    if let Some(ap) = self.top.as_ref() {
+      buf.push_str("top");
+      write_position(ap, &mut buf);
+    }
+    for ap in self.expanded.iter() {
+      buf.push_str("expanded");
+      write_position(ap, &mut buf);
+    }
+    for (ap, show) in self.hoist.iter() {
+      buf.push_str(if *show { "hoist\t1" } else { "hoist\t0" });
+      write_position(ap, &mut buf);
+    }
+    buf
+  }
+  pub fn from_text(txt:&str) -> io::Result<Self> {
+    let mut lines = txt.lines();
+    if lines.next() != Some(SESSION_HEADER) {
+      return Err(invalid("not a mini_leo session file".to_string()));
+    }
+    let mut res = Session::default();
+    for (n, line) in lines.enumerate().filter(|x|!x.1.is_empty()) {
+      let fields:Vec<&str> = line.split('\t').collect();
+      let ok = match fields[0] {
+        "current" => read_position(&fields[1..]).map(|ap|res.current = Some(ap)),
+        "top" => read_position(&fields[1..]).map(|ap|res.top = Some(ap)),
+        "expanded" => read_position(&fields[1..]).map(|ap|res.expanded.push(ap)),
+        "hoist" if fields.len() > 1 && (fields[1] == "1" || fields[1] == "0") =>
+          read_position(&fields[2..]).map(|ap|res.hoist.push((ap, fields[1] == "1"))),
+        _ => None
+      };
+      if ok.is_none() {
+        return Err(invalid(format!("invalid session record at line {}", n + 2)));
+      }
+    }
+    Ok(res)
+  }
+  pub fn save(&self, fname:&Path) -> io::Result<()> {
+    fs::write(fname, self.to_text())
+  }
+  pub fn load(fname:&Path) -> io::Result<Self> {
+    Session::from_text(&fs::read_to_string(fname)?)
+  }
+}
+fn invalid(msg:String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+/// returns path of the session file for the given Leo document
+pub fn session_file(leo_fname:&str) -> PathBuf {
+  PathBuf::from(format!("{}.session", leo_fname))
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::from_leo_content;
+  use crate::model::{insert_new_node, insert_node_at, InsertPlace};
+  #[test]
+  fn test_session_roundtrip() {
+    let (mut o, mut nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="a"><vh>A</vh><v t="b"><vh>B</vh><v t="c"><vh>C</vh></v></v></v>
+<v t="d"><vh>D</vh><v t="b"/></v>
+</vnodes><tnodes></tnodes></leo_file>"#);
+    o[1].expand();
+    o[5].expand();
+    let mut h = Hoist::new();
+    h.push(o[4].label());
+    let s = Session::capture(&o, &nodes, &h, 6, 4);
+    assert_eq!(s.current, Some(vec![(1, "d".to_string()), (0, "b".to_string()), (0, "c".to_string())]));
+    let s2 = Session::from_text(&s.to_text()).unwrap();
+    assert_eq!(s2, s);
+    // positions still resolve after inserting nodes before them
+    insert_new_node(&mut o, &mut nodes, 1, "x");
+    insert_node_at(&mut o, &mut nodes, 1, "y", InsertPlace::Before);
+    for x in o.iter_mut() { x.expand() }
+    let mut h2 = Hoist::new();
+    let (cur, top) = s2.apply(&mut o, &nodes, &mut h2);
+    let cur = cur.unwrap();
+    let gnx = |i:usize|nodes[o[i].ignx() as usize].gnx.as_str();
+    assert_eq!(gnx(cur), "c");
+    assert_eq!(top.map(gnx), Some("d"));
+    assert_eq!(gnx(o.parent_index(o.parent_index(cur))), "d");
+    let exp:Vec<&str> = (1..o.len()).filter(|i|o[*i].is_expanded()).map(gnx).collect();
+    assert_eq!(exp, vec!["a", "b"]);
+    assert_eq!(h2.hoisted(&o).and_then(|l|o.label_index(l)).map(gnx), Some("d"));
+    assert!(Session::from_text("#mini_leo-session:1\nexpanded\tx\ta\n").is_err());
+    assert!(Session::from_text("#mini_leo-session:1\nexpanded\t0\ta\\x\n").is_err());
+  }
+  #[test]
+  fn test_session_special_gnx() {
+    let s = Session {
+      current: Some(vec![(0, "a\tb".to_string()), (2, "c\\n\nd".to_string())]),
+      ..Session::default()
+    };
+    let txt = s.to_text();
+    assert_eq!(txt.lines().count(), 2);
+    assert_eq!(Session::from_text(&txt).unwrap(), s);
+  }
+}
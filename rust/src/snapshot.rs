@@ -0,0 +1,173 @@
+use crate::model::{VData, Outline, LevGnxOps, find_derived_files, find_clean_files};
+use crate::changes::{ChangeError, ByteReader, write_varint, write_str, write_nodes, write_vnode};
+use crate::utils::{FNV_OFFSET, fnv1a};
+use std::{fs, io, path::Path, time::UNIX_EPOCH};
+
+/// Binary snapshot of an outline with its vnodes.
+///
+/// File starts with `MLS` followed by the version byte, then come the
+/// folder of the Leo document, the outline as raw little endian u64
+/// values, the vnodes, and stamps of external files the outline was
+/// loaded from. Integers are LEB128 varints and strings are varint
+/// length followed by utf-8 bytes, as in binary change records. The
+/// last 8 bytes are FNV-1a checksum of everything before them.
+#[derive(Debug, PartialEq)]
+pub struct Snapshot {
+  pub folder: Option<String>,
+  pub outline: Outline,
+  pub nodes: Vec<VData>,
+  pub files: Vec<FileStamp>
+}
+/// Modification time (in nanoseconds since the epoch) and content hash
+/// of an external file
+#[derive(Debug, PartialEq, Clone)]
+pub struct FileStamp {
+  pub path: String,
+  pub mtime: u64,
+  pub hash: u64
+}
+pub const SNAPSHOT_MAGIC:&[u8] = b"MLS";
+pub const SNAPSHOT_VERSION:u8 = 1;
+
+fn mtime(path:&str) -> io::Result<u64> {
+  let t = fs::metadata(path)?.modified()?;
+  Ok(t.duration_since(UNIX_EPOCH).map(|d|d.as_nanos() as u64).unwrap_or(0))
+}
+impl FileStamp {
+  pub fn of(path:&str) -> io::Result<Self> {
+    Ok(FileStamp { path: path.to_string(), mtime: mtime(path)?, hash: fnv1a(FNV_OFFSET, &fs::read(path)?) })
+  }
+  /// returns true if the file hasn't changed. Content is hashed only
+  /// if the modification time has changed.
+  pub fn is_fresh(&self) -> bool {
+    match mtime(&self.path) {
+      Ok(t) if t == self.mtime => true,
+      Ok(_) => fs::read(&self.path).map(|b|fnv1a(FNV_OFFSET, &b) == self.hash).unwrap_or(false),
+      Err(_) => false
+    }
+  }
+}
+/// returns stamps of all existing `at-file` and `at-clean` files of
+/// the outline
+pub fn external_file_stamps(folder:&Path, o:&Outline, nodes:&Vec<VData>) -> Vec<FileStamp> {
+  find_derived_files(folder, o, nodes)
+    .into_iter()
+    .chain(find_clean_files(folder, o, nodes))
+    .filter_map(|(f, _)|FileStamp::of(&f).ok())
+    .collect()
+}
+fn invalid(msg:&str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+impl Snapshot {
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut buf = Vec::from(SNAPSHOT_MAGIC);
+    buf.push(SNAPSHOT_VERSION);
+    write_str(self.folder.as_deref().unwrap_or(""), &mut buf);
+    write_nodes(&self.outline, &mut buf);
+    write_varint(self.nodes.len() as u64, &mut buf);
+    for v in self.nodes.iter() { write_vnode(v, &mut buf) }
+    write_varint(self.files.len() as u64, &mut buf);
+    for f in self.files.iter() {
+      write_str(&f.path, &mut buf);
+      write_varint(f.mtime, &mut buf);
+      buf.extend_from_slice(&f.hash.to_le_bytes());
+    }
+    let h = fnv1a(FNV_OFFSET, &buf);
+    buf.extend_from_slice(&h.to_le_bytes());
+    buf
+  }
+  pub fn from_bytes(data:&[u8]) -> io::Result<Self> {
+    let n = SNAPSHOT_MAGIC.len();
+    if data.len() < n + 9 || &data[..n] != SNAPSHOT_MAGIC {
+      return Err(invalid("not a mini_leo snapshot"));
+    }
+    if data[n] != SNAPSHOT_VERSION {
+      return Err(invalid("unsupported snapshot version"));
+    }
+    let (body, sum) = data.split_at(data.len() - 8);
+    let mut a = [0u8; 8];
+    a.copy_from_slice(sum);
+    if fnv1a(FNV_OFFSET, body) != u64::from_le_bytes(a) {
+      return Err(invalid("snapshot checksum mismatch"));
+    }
+    let read = |r:&mut ByteReader| -> Result<Snapshot, ChangeError> {
+      let folder = Some(r.string()?).filter(|x|!x.is_empty());
+      let outline = r.nodes()?;
+      let nodes = (0..r.varint()?).map(|_|r.vnode()).collect::<Result<Vec<_>, _>>()?;
+      let mut files = Vec::new();
+      for _ in 0..r.varint()? {
+        let path = r.string()?;
+        let mtime = r.varint()?;
+        let mut a = [0u8; 8];
+        for x in a.iter_mut() { *x = r.byte()? }
+        files.push(FileStamp { path, mtime, hash: u64::from_le_bytes(a) });
+      }
+      Ok(Snapshot { folder, outline, nodes, files })
+    };
+    let mut r = ByteReader::new(&body[n + 1..]);
+    let res = read(&mut r).map_err(|e|invalid(&e.msg))?;
+    if !r.at_end() { return Err(invalid("unexpected data at the end of snapshot")) }
+    if res.outline.is_empty() || res.outline.iter().any(|x|x.ignx() as usize >= res.nodes.len()) {
+      return Err(invalid("outline refers to missing vnodes"));
+    }
+    Ok(res)
+  }
+  pub fn save(&self, fname:&Path) -> io::Result<()> {
+    fs::write(fname, self.to_bytes())
+  }
+  pub fn load(fname:&Path) -> io::Result<Self> {
+    Snapshot::from_bytes(&fs::read(fname)?)
+  }
+  /// returns paths of external files which have changed or disappeared
+  /// since the snapshot was made
+  pub fn stale_files(&self) -> Vec<String> {
+    self.files.iter().filter(|f|!f.is_fresh()).map(|f|f.path.clone()).collect()
+  }
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::from_leo_content;
+  #[test]
+  fn test_snapshot_roundtrip() {
+    let (outline, mut nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="a" a="M"><vh>A</vh><v t="b"><vh>B</vh></v></v><v t="b"/>
+</vnodes><tnodes><t tx="b">čaj
+</t></tnodes></leo_file>"#);
+    nodes[1].flags = 3;
+    let dir = std::env::temp_dir();
+    let ext = dir.join("mini_leo_snapshot_test.py");
+    fs::write(&ext, "x = 1\n").unwrap();
+    let files = vec![FileStamp::of(ext.to_str().unwrap()).unwrap()];
+    let snap = Snapshot { folder: Some("/tmp".to_string()), outline, nodes, files };
+    let f = dir.join("mini_leo_test.snap");
+    snap.save(&f).unwrap();
+    let snap2 = Snapshot::load(&f).unwrap();
+    assert_eq!(snap2, snap);
+    assert!(snap2.stale_files().is_empty());
+    fs::write(&ext, "x = 2\n").unwrap();
+    assert_eq!(snap2.stale_files().len(), 1);
+    let mut data = fs::read(&f).unwrap();
+    data[10] ^= 1;
+    assert_eq!(Snapshot::from_bytes(&data).unwrap_err().to_string(), "snapshot checksum mismatch");
+    let _ = fs::remove_file(&f);
+    let _ = fs::remove_file(&ext);
+  }
+  #[test]
+  fn test_external_file_stamps() {
+    let dir = std::env::temp_dir().join("mini_leo_stamps_test");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("x.py"), "x = 1\n").unwrap();
+    fs::write(dir.join("y.txt"), "y\n").unwrap();
+    let (o, nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="a"><vh>@file x.py</vh></v><v t="b"><vh>@clean y.txt</vh></v><v t="c"><vh>@file z.py</vh></v>
+</vnodes><tnodes></tnodes></leo_file>"#);
+    let stamps = external_file_stamps(&dir, &o, &nodes);
+    let names:Vec<&str> = stamps.iter()
+      .filter_map(|f|Path::new(&f.path).file_name()?.to_str())
+      .collect();
+    assert_eq!(names, vec!["x.py", "y.txt"]);
+    let _ = fs::remove_dir_all(&dir);
+  }
+}
@@ -0,0 +1,104 @@
+use crate::model::{VData, Outline};
+use crate::changes::{Change, ChangeError, decode_changes};
+
+/// Groups several outline operations into one undoable unit.
+///
+/// Transaction keeps a copy of the outline and the number of vnodes
+/// as they were when transaction began, and collects change records
+/// of all operations performed while it is open. On commit all records
+/// are joined into one change string that undo/redo can apply at once.
+/// Previous content of every vnode updated by a recorded change is kept
+/// too, so that on rollback those vnodes can be restored before the
+/// outline and the node vector are reset to their exact previous state.
+pub struct Transaction {
+  outline: Outline,
+  nodes_len: usize,
+  changes: Vec<String>,
+  updated: Vec<VData>
+}
+impl Transaction {
+  pub fn begin(o:&Outline, nodes:&Vec<VData>) -> Self {
+    Transaction {
+      outline: o.clone(),
+      nodes_len: nodes.len(),
+      changes: Vec::new(),
+      updated: Vec::new()
+    }
+  }
+  /// adds the change string of one operation to this transaction.
+  ///
+  /// Returns an error and records nothing if the change string is not
+  /// valid, because the transaction couldn't be rolled back over it.
+  pub fn record(&mut self, change:&str) -> Result<(), ChangeError> {
+    if change.len() > 0 {
+      for c in decode_changes(change)?.into_iter() {
+        if let Change::UpdateVNode { old, .. } = c {
+          self.updated.push(old);
+        }
+      }
+      self.changes.push(change.to_string());
+    }
+    Ok(())
+  }
+  /// returns true if no change has been recorded yet
+  pub fn is_empty(&self) -> bool { self.changes.is_empty() }
+
+  /// returns one change string combining all recorded changes
+  pub fn commit(self) -> String {
+    self.changes.join("\n")
+  }
+  /// restores the outline and nodes as they were when this
+  /// transaction began
+  pub fn rollback(self, o:&mut Outline, nodes:&mut Vec<VData>) {
+    // outline is restored from the copy, so only vnode updates
+    // need to be undone here
+    for old in self.updated.into_iter().rev() {
+      if (old.ignx as usize) < nodes.len() {
+        let i = old.ignx as usize;
+        nodes[i] = old;
+      }
+    }
+    nodes.truncate(self.nodes_len);
+    *o = self.outline;
+  }
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::from_leo_content;
+  use crate::model::{insert_new_node, move_node_right, OutlineOps};
+  #[test]
+  fn test_commit_and_rollback() {
+    let (mut o, mut nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="a"><vh>A</vh></v><v t="b"><vh>B</vh></v>
+</vnodes><tnodes></tnodes></leo_file>"#);
+    let o1 = o.clone();
+    let mut tr = Transaction::begin(&o, &nodes);
+    let (p, s) = insert_new_node(&mut o, &mut nodes, 1, "c");
+    tr.record(&s).unwrap();
+    let i = o.label_index(p).unwrap();
+    tr.record(&move_node_right(&mut o, i).unwrap()).unwrap();
+    let mut buf = "vupd:".to_string();
+    nodes[1].write_to(&mut buf);
+    nodes[1].h.push_str(" changed");
+    buf.push('\t');
+    nodes[1].write_to(&mut buf);
+    tr.record(&buf).unwrap();
+    tr.rollback(&mut o, &mut nodes);
+    assert_eq!(o, o1);
+    assert_eq!(nodes[1].h, "A");
+    assert_eq!(nodes.len(), 3);
+
+    let mut tr = Transaction::begin(&o, &nodes);
+    let (_, s) = insert_new_node(&mut o, &mut nodes, 1, "c");
+    tr.record(&s).unwrap();
+    assert_eq!(tr.commit(), s);
+  }
+  #[test]
+  fn test_record_invalid() {
+    let (o, nodes) = from_leo_content("<leo_file><vnodes></vnodes><tnodes></tnodes></leo_file>");
+    let mut tr = Transaction::begin(&o, &nodes);
+    assert!(tr.record("vupd:broken").is_err());
+    assert!(tr.is_empty());
+  }
+}
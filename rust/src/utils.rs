@@ -38,6 +38,11 @@ pub fn b64int(a:&str) -> u64 {
   }
   res
 }
+#[allow(dead_code)]
+/// returns true if the given str is non-empty base 64 number
+pub fn is_b64(a:&str) -> bool {
+  a.len() > 0 && a.bytes().all(|i| i < 128 && B64VALUES[i as usize] != 255)
+}
 const B64DIGITS:[char;64] = [
   '0', '1', '2', '3', '4', '5', '6', '7',
   '8', '9', 'A', 'B', 'C', 'D', 'E', 'F',
@@ -88,7 +93,7 @@ pub fn partition<'a>(input:&'a str, sep:&str) -> (&'a str, &'a str, &'a str) {
       (&input[..i], &input[i..j], &input[j..])
     },
     None => {
-      let i = input.len() - 1;
+      let i = input.len();
       (&input, &input[i..i], &input[i..i])
     }
   }
@@ -180,6 +185,13 @@ pub fn delete_blocks(inp:&mut Vec<u64>, marks:&Vec<usize>, sz:usize) {
     }
     inp.truncate(count-delta);
 }
+#[allow(dead_code)]
+pub const FNV_OFFSET:u64 = 0xcbf29ce484222325;
+/// continues FNV-1a hash h with the given bytes
+#[allow(dead_code)]
+pub fn fnv1a(h:u64, bytes:&[u8]) -> u64 {
+  bytes.iter().fold(h, |h, b|(h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
 #[cfg(test)]
 mod tests {
   #[test]
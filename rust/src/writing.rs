@@ -0,0 +1,257 @@
+use crate::model::{VData, Outline, OutlineOps, LevGnxOps};
+use crate::marks::is_marked;
+use quick_xml::Reader as XmlReader;
+use quick_xml::events::Event;
+use quick_xml::events::attributes::Attributes;
+use std::collections::{HashMap, HashSet};
+
+/// Parts of a Leo document that are not kept in the outline: text
+/// before the vnodes element (xml declaration, leo_header, globals,
+/// preferences, find_panel_settings...), text between the vnodes and
+/// tnodes elements, text after the tnodes element and attributes of
+/// v and t elements which mini_leo doesn't use (for example
+/// unknownAttributes), written as they were in the document and
+/// stored by gnx.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LeoExtras {
+  pub head: String,
+  pub middle: String,
+  pub tail: String,
+  pub vattrs: HashMap<String, String>,
+  pub tattrs: HashMap<String, String>
+}
+impl Default for LeoExtras {
+  fn default() -> Self {
+    LeoExtras {
+      head: String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+        "<leo_file xmlns:leo=\"http://leoeditor.com/namespaces/leo-python-editor/1.1\" >\n",
+        "<leo_header file_format=\"2\"/>\n")),
+      middle: String::from("\n"),
+      tail: String::from("\n</leo_file>\n"),
+      vattrs: HashMap::new(),
+      tattrs: HashMap::new()
+    }
+  }
+}
+/// returns start and end of the first element with the given name
+/// which has no attributes
+fn element_span(xml:&str, name:&str, from:usize) -> Option<(usize, usize)> {
+  let open = format!("<{}>", name);
+  let empty = format!("<{}/>", name);
+  match xml[from..].find(&open) {
+    Some(a) => {
+      let close = format!("</{}>", name);
+      let b = xml[from + a..].find(&close)?;
+      Some((from + a, from + a + b + close.len()))
+    },
+    None => xml[from..].find(&empty).map(|a|(from + a, from + a + empty.len()))
+  }
+}
+/// returns attributes other than the given ones, as they are written
+fn other_attributes(attrs:Attributes, known:&[&[u8]]) -> String {
+  let mut res = String::new();
+  for a in attrs.filter_map(|x|x.ok()).filter(|a|!known.contains(&a.key)) {
+    res.push(' ');
+    res.push_str(&String::from_utf8_lossy(a.key));
+    res.push_str("=\"");
+    res.push_str(&String::from_utf8_lossy(&a.value));
+    res.push('"');
+  }
+  res
+}
+impl LeoExtras {
+  /// collects the parts of Leo document xml which are not kept in the
+  /// outline. Returns None if xml has no vnodes or tnodes element.
+  pub fn from_content(xml:&str) -> Option<Self> {
+    let (va, vb) = element_span(xml, "vnodes", 0)?;
+    let (ta, tb) = element_span(xml, "tnodes", vb)?;
+    let mut res = LeoExtras {
+      head: xml[..va].to_string(),
+      middle: xml[vb..ta].to_string(),
+      tail: xml[tb..].to_string(),
+      vattrs: HashMap::new(),
+      tattrs: HashMap::new()
+    };
+    let mut reader = XmlReader::from_str(&xml[va..tb]);
+    let mut xmlbuf = Vec::new();
+    loop {
+      match reader.read_event(&mut xmlbuf) {
+        Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e)) => {
+          let (key, known, map):(&[u8], &[&[u8]], _) = match e.local_name() {
+            b"v" => (b"t", &[b"t", b"a"], &mut res.vattrs),
+            b"t" => (b"tx", &[b"tx"], &mut res.tattrs),
+            _ => { xmlbuf.clear(); continue }
+          };
+          let other = other_attributes(e.attributes(), known);
+          let gnx = e.attributes().filter_map(|x|x.ok()).find(|a|a.key == key)
+            .and_then(|a|a.unescape_and_decode_value(&reader).ok());
+          if let (false, Some(gnx)) = (other.is_empty(), gnx) {
+            map.entry(gnx).or_insert(other);
+          }
+        },
+        Ok(Event::Eof) | Err(_) => break,
+        _ => ()
+      }
+      xmlbuf.clear();
+    }
+    Some(res)
+  }
+}
+/// returns Leo document (xml) containing the subtree of the node at
+/// index ni. If ni is 0, the document contains the whole outline.
+///
+/// The first occurrence of a cloned node is written with its headline
+/// and children, all other occurrences are written as `<v t="gnx"/>`,
+/// the same way Leo writes its clipboard and .leo files.
+pub fn to_leo_content(o:&Outline, nodes:&Vec<VData>, ni:usize) -> String {
+  write_leo(o, nodes, ni, &LeoExtras::default(), false)
+}
+/// returns Leo document (xml) containing the whole outline, the way it
+/// is saved in a .leo file. Subtrees of @file, @auto and @edit nodes
+/// are left out, because they are read from their external files when
+/// the document is loaded. Parts of the document given in extras are
+/// written unchanged.
+pub fn to_leo_document(o:&Outline, nodes:&Vec<VData>, extras:&LeoExtras) -> String {
+  write_leo(o, nodes, 0, extras, true)
+}
+/// returns true if the content of node v is kept in an external file
+fn is_external(v:&VData) -> bool {
+  ["@file ", "@auto ", "@edit "].iter().any(|k|v.h.starts_with(k))
+}
+fn write_leo(o:&Outline, nodes:&Vec<VData>, ni:usize, extras:&LeoExtras, skip_external:bool) -> String {
+  let mut buf = extras.head.clone();
+  buf.push_str("<vnodes>\n");
+  let (a, b) = if ni == 0 { (1, o.len()) } else { (ni, ni + o.subtree_size(ni)) };
+  let zlev = if ni == 0 { 0 } else { o[ni].level() - 1 };
+  let mut written:HashSet<u32> = HashSet::new();
+  let mut order:Vec<u32> = Vec::new();
+  let mut open:Vec<u8> = Vec::new();
+  let mut skip_level = 255u8;
+  for x in &o[a..b] {
+    let lev = x.level() - zlev;
+    if lev > skip_level { continue }
+    skip_level = 255u8;
+    while open.last().map(|l| *l >= lev).unwrap_or(false) {
+      open.pop();
+      buf.push_str("</v>\n");
+    }
+    let v = &nodes[x.ignx() as usize];
+    buf.push_str("<v t=\"");
+    xml_escape(&v.gnx, &mut buf);
+    buf.push('"');
+    match (x.is_expanded(), is_marked(v)) {
+      (true, true) => buf.push_str(" a=\"EM\""),
+      (true, false) => buf.push_str(" a=\"E\""),
+      (false, true) => buf.push_str(" a=\"M\""),
+      _ => ()
+    }
+    if written.contains(&x.ignx()) {
+      buf.push_str("/>\n");
+      skip_level = lev;
+      continue;
+    }
+    written.insert(x.ignx());
+    if let Some(s) = extras.vattrs.get(&v.gnx) { buf.push_str(s) }
+    buf.push_str("><vh>");
+    xml_escape(&v.h, &mut buf);
+    buf.push_str("</vh>\n");
+    if skip_external && is_external(v) {
+      buf.push_str("</v>\n");
+      skip_level = lev;
+      continue;
+    }
+    order.push(x.ignx());
+    open.push(lev);
+  }
+  for _ in open {
+    buf.push_str("</v>\n");
+  }
+  buf.push_str("</vnodes>");
+  buf.push_str(&extras.middle);
+  buf.push_str("<tnodes>\n");
+  for i in order {
+    let v = &nodes[i as usize];
+    if v.b.len() == 0 { continue }
+    buf.push_str("<t tx=\"");
+    xml_escape(&v.gnx, &mut buf);
+    buf.push('"');
+    if let Some(s) = extras.tattrs.get(&v.gnx) { buf.push_str(s) }
+    buf.push('>');
+    xml_escape(&v.b, &mut buf);
+    buf.push_str("</t>\n");
+  }
+  buf.push_str("</tnodes>");
+  buf.push_str(&extras.tail);
+  buf
+}
+/// appends s to buf escaping characters which are special in xml
+pub fn xml_escape(s:&str, buf:&mut String) {
+  for c in s.chars() {
+    match c {
+      '&' => buf.push_str("&amp;"),
+      '<' => buf.push_str("&lt;"),
+      '>' => buf.push_str("&gt;"),
+      '"' => buf.push_str("&quot;"),
+      _ => buf.push(c)
+    }
+  }
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parsing::from_leo_content;
+  #[test]
+  fn test_to_leo_content() {
+    let (o, nodes) = from_leo_content(r#"<leo_file><vnodes>
+<v t="a"><vh>A &amp; B</vh><v t="b"><vh>B</vh><v t="c"><vh>C</vh></v></v></v>
+<v t="b"/>
+</vnodes><tnodes><t tx="c">x &lt; y
+</t></tnodes></leo_file>"#);
+    let s = to_leo_content(&o, &nodes, 0);
+    let (o2, nodes2) = from_leo_content(&s);
+    assert_eq!(o2.len(), o.len());
+    assert_eq!(nodes2[1].h, "A & B");
+    assert_eq!(nodes2[3].b, "x < y\n");
+    assert!(s.contains("<v t=\"b\"/>"));
+    let s = to_leo_content(&o, &nodes, 2);
+    assert!(s.contains("<vh>C</vh>") && !s.contains("A &amp; B"));
+  }
+  #[test]
+  fn test_to_leo_document() {
+    let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<leo_file>
+<leo_header file_format="2" tnodes="0" max_tnode_index="0"/>
+<globals body_outline_ratio="0.5"/>
+<preferences/>
+<find_panel_settings/>
+<vnodes>
+<v t="a" descendentVnodeUnknownAttributes="7d71"><vh>A</vh>
+<v t="f"><vh>@file f.py</vh>
+<v t="g"><vh>G</vh></v>
+</v>
+</v>
+<v t="g"/>
+</vnodes>
+<tnodes>
+<t tx="a" ua="7d71">body a</t>
+<t tx="f">@others</t>
+<t tx="g">body g</t>
+</tnodes>
+</leo_file>
+"#;
+    let (o, nodes) = from_leo_content(xml);
+    let extras = LeoExtras::from_content(xml).unwrap();
+    let s = to_leo_document(&o, &nodes, &extras);
+    assert!(s.starts_with(&xml[..xml.find("<vnodes>").unwrap()]));
+    assert!(s.contains(r#"<v t="a" descendentVnodeUnknownAttributes="7d71"><vh>A</vh>"#));
+    assert!(s.contains(r#"<t tx="a" ua="7d71">body a</t>"#));
+    // @file subtree is left out, but the clone outside of it is written
+    assert!(s.contains("<v t=\"f\"><vh>@file f.py</vh>\n</v>\n</v>\n"));
+    assert!(!s.contains("@others"));
+    assert!(s.contains("<v t=\"g\"><vh>G</vh>\n</v>\n</vnodes>"));
+    assert!(s.contains("body g"));
+    assert!(s.ends_with("</tnodes>\n</leo_file>\n"));
+    assert_eq!(LeoExtras::from_content(&s), Some(extras));
+  }
+}